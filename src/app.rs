@@ -1,9 +1,27 @@
 //! A structure that contains all parsaed arguments for the command line
 
-use crate::{cli::Opts, config::Config};
+use crate::{cli::Opts, config::Config, lxhkd_fatal};
 use anyhow::{Context, Result};
+use colored::Colorize;
 use std::{env, path::PathBuf};
 
+#[cfg(feature = "daemon")]
+use daemonize::{Daemonize, User};
+#[cfg(feature = "daemon")]
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::{self, Uid},
+};
+#[cfg(feature = "daemon")]
+use psutil::process::{self as psprocess, os::unix::ProcessExt as _, Process};
+#[cfg(feature = "daemon")]
+use std::{convert::TryFrom, fs, process, thread, time::Duration};
+
+/// The name of the process, which is used to confirm that a PID belongs to
+/// this program
+#[cfg(feature = "daemon")]
+const PROCESS_NAME: &str = env!("CARGO_PKG_NAME");
+
 /// The structure containing parsed arguments
 #[derive(Debug, Clone)]
 pub(crate) struct App {
@@ -12,21 +30,19 @@ pub(crate) struct App {
     list_keysyms: bool,
     temporary:    bool,
 
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     daemonize:    bool,
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     pidfile:      PathBuf,
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     kill:         bool,
+    #[cfg(feature = "daemon")]
+    status:       bool,
+    #[cfg(feature = "daemon")]
+    restart:      bool,
 }
 
 impl App {
-    /// Run the application
-    pub(crate) fn run(opts: Opts, config: Config) -> Result<()> {
-        let mut app = Self::new(&opts, config);
-        Ok(())
-    }
-
     /// Parse the configuration and command line arguments
     pub(crate) fn new(opts: &Opts, config: Config) -> Self {
         let color_when = match opts.color_when {
@@ -46,21 +62,30 @@ impl App {
             list_keysyms: opts.keysyms,
             temporary:    opts.temporary,
 
-            #[cfg(feature = "daemonize")]
+            #[cfg(feature = "daemon")]
             daemonize:    opts.daemonize,
-            #[cfg(feature = "daemonize")]
+            #[cfg(feature = "daemon")]
             pidfile:      opts.pidfile.clone().unwrap_or_else(|| {
                 config.global.pid_file.unwrap_or_else(|| {
-                    dirs::runtime_dir()
-                        .unwrap_or_else(env::temp_dir)
-                        .join("lxhkd.pid")
+                    // The temporary directory is shared by every user, unlike the
+                    // runtime directory
+                    dirs::runtime_dir().map_or_else(
+                        || env::temp_dir().join(format!("lxhkd-{}.pid", Uid::current())),
+                        |dir| dir.join("lxhkd.pid"),
+                    )
                 })
             }),
-            #[cfg(feature = "daemonize")]
+            #[cfg(feature = "daemon")]
             kill:         opts.kill,
+            #[cfg(feature = "daemon")]
+            status:       opts.status,
+            #[cfg(feature = "daemon")]
+            restart:      opts.restart,
         }
     }
 
+    /// Act on the command line arguments that are handled before the daemon
+    /// starts. `--kill` and `--status` exit the program
     pub(crate) fn run_commands(&self) -> Result<()> {
         if self.color_when == "never" {
             colored::control::SHOULD_COLORIZE.set_override(false);
@@ -68,6 +93,154 @@ impl App {
             colored::control::SHOULD_COLORIZE.set_override(true);
         }
 
+        #[cfg(feature = "daemon")]
+        self.run_lifecycle()?;
+
         Ok(())
     }
 }
+
+// ================== Lifecycle ===================
+
+#[cfg(feature = "daemon")]
+impl App {
+    /// Handle `--status`, `--kill`, `--restart` and `--daemonize`. Nothing is
+    /// started if another instance is already running, whether it was
+    /// daemonized or is running in the foreground
+    fn run_lifecycle(&self) -> Result<()> {
+        if self.list_keysyms {
+            return Ok(());
+        }
+
+        let running = self.find_running()?;
+
+        if self.status {
+            match running {
+                Some(pid) => {
+                    println!("{} is running: {}", PROCESS_NAME, pid.to_string().green().bold());
+                    process::exit(0);
+                },
+                None => {
+                    println!("{PROCESS_NAME} is not running");
+                    process::exit(1);
+                },
+            }
+        }
+
+        if self.kill {
+            match running {
+                Some(pid) => {
+                    self.stop(pid)?;
+                    process::exit(0);
+                },
+                None => lxhkd_fatal!(
+                    "the daemon is not currently running or the PID file has been modified: {}",
+                    self.pidfile.display().to_string().green().bold()
+                ),
+            }
+        }
+
+        match (running, self.restart) {
+            (Some(pid), true) => self.stop(pid)?,
+            (Some(pid), false) => lxhkd_fatal!(
+                "{} is already running: {}",
+                PROCESS_NAME,
+                pid.to_string().green().bold()
+            ),
+            (None, _) => {},
+        }
+
+        if self.daemonize || self.restart {
+            self.daemonize();
+        }
+
+        Ok(())
+    }
+
+    /// Return the PID of another running instance of this program started by
+    /// the same user. The PID file is checked first, which only exists for
+    /// daemonized instances, and then every process, to find instances running
+    /// in the foreground
+    fn find_running(&self) -> Result<Option<u32>> {
+        let own = process::id();
+        let uid = Uid::current().as_raw();
+        let is_lxhkd = |p: &Process| {
+            p.pid() != own
+                && p.name().is_ok_and(|name| name == PROCESS_NAME)
+                && p.uids().is_ok_and(|uids| uids.real == uid)
+        };
+
+        if let Ok(contents) = fs::read_to_string(&self.pidfile) {
+            match contents.trim().parse::<u32>() {
+                Ok(pid) if Process::new(pid).is_ok_and(|p| is_lxhkd(&p)) => {
+                    return Ok(Some(pid));
+                },
+                _ => log::info!(
+                    "ignoring stale PID file {} ({})",
+                    self.pidfile.display().to_string().green().bold(),
+                    contents.trim()
+                ),
+            }
+        }
+
+        Ok(psprocess::processes()
+            .context("failed to get list of processes")?
+            .iter()
+            .filter_map(|p| p.as_ref().ok())
+            .find(|p| is_lxhkd(p))
+            .map(Process::pid))
+    }
+
+    /// Stop the instance running at `pid`, and wait for it to put the keyboard
+    /// back the way it was found
+    fn stop(&self, pid: u32) -> Result<()> {
+        /// The number of times to check whether the process has exited
+        const ATTEMPTS: usize = 50;
+
+        let colored_pid = pid.to_string().green().bold();
+        let nix_pid = unistd::Pid::from_raw(i32::try_from(pid).context("PID is out of range")?);
+
+        signal::kill(nix_pid, Signal::SIGTERM)
+            .with_context(|| format!("failed to terminate process {colored_pid}"))?;
+
+        for _ in 0..ATTEMPTS {
+            if !Process::new(pid).is_ok_and(|p| p.is_running()) {
+                log::info!("successfully terminated daemon: {}", colored_pid);
+
+                // The PID file is not removed when a daemonized process exits
+                if fs::read_to_string(&self.pidfile)
+                    .is_ok_and(|contents| contents.trim() == pid.to_string())
+                {
+                    let _ = fs::remove_file(&self.pidfile);
+                }
+
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        lxhkd_fatal!("process {} did not exit after being terminated", colored_pid);
+    }
+
+    /// Send the process to the background, writing its PID to the PID file
+    fn daemonize(&self) {
+        log::info!("pid-path: {}", self.pidfile.display().to_string().blue().bold());
+
+        Daemonize::new()
+            .pid_file(&self.pidfile)
+            .user(User::Id(Uid::current().into()))
+            .umask(0o600)
+            .exit_action(|| log::info!("> Daemon started <"))
+            .start()
+            .unwrap_or_else(|e| {
+                lxhkd_fatal!(
+                    "failed to daemonize ({}): {}",
+                    e,
+                    fs::read_to_string(&self.pidfile)
+                        .unwrap_or_else(|_| String::from("N/A"))
+                        .green()
+                        .bold()
+                )
+            });
+    }
+}
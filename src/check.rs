@@ -0,0 +1,562 @@
+//! Validation of a configuration file without starting the daemon. This is ran
+//! with `--check`, and reports the lines that would otherwise be skipped when
+//! the daemon starts
+
+use crate::{
+    config::{ComboValue, Config, DeviceValue, RemapValue},
+    keys::{
+        binding::Binding,
+        chord::{Chain, Chord},
+        conflict::{self, Conflict},
+        keyboard::Keyboard,
+        keys::CharacterMap,
+    },
+    parse::parser::{self, Error as ParseError},
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use format_serde_error::SerdeError;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs,
+    path::Path,
+};
+use thiserror::Error;
+use x11rb::protocol::xproto::Keycode;
+
+// =================== Error ======================
+
+/// A problem found in the configuration file
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum Error {
+    #[error("{0}")]
+    Parse(ParseError),
+    #[error("no keys were found")]
+    NoKeys,
+    #[error("same keys as {0}, which is the one that is used")]
+    Duplicate(Item),
+    #[error("never reached, since {0} is matched first")]
+    Shadowed(Item),
+    #[error("its groups expand to the same keys more than once")]
+    RepeatedExpansion,
+    #[error("unsupported target `{0}`: {1}")]
+    UnsupportedTarget(String, &'static str),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+// ==================== Item ======================
+
+/// Another item of the same section that a problem is with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Item {
+    /// The key of the item
+    key:  String,
+    /// The line of the configuration file the item is on, if it was found
+    line: Option<usize>,
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}>", self.key)?;
+        if let Some(line) = self.line {
+            write!(f, " on line {line}")?;
+        }
+        Ok(())
+    }
+}
+
+// ================= Diagnostic ===================
+
+/// An [`Error`] along with where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Diagnostic {
+    /// The section of the configuration file (e.g., `bindings`)
+    section: &'static str,
+    /// The key of the item in the section
+    source:  String,
+    /// The key of the item, or the value of it the problem is in
+    text:    String,
+    /// The line of the configuration file the problem is on, if it was found
+    line:    Option<usize>,
+    /// The problem that was found
+    error:   Error,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "{}:", line.to_string().yellow().bold())?;
+        }
+        write!(
+            f,
+            "{}: <{}> - {}",
+            self.section.green().bold(),
+            self.source.purple().bold(),
+            self.error
+        )
+    }
+}
+
+// =================== Checker ====================
+
+/// Walks every section of a configuration file, collecting [`Diagnostic`]s
+#[derive(Debug)]
+pub(crate) struct Checker<'a> {
+    /// The keys available on the keyboard
+    charmaps:    &'a [CharacterMap],
+    /// The contents of the configuration file, used to find line numbers
+    contents:    &'a str,
+    /// The problems found so far
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    /// Create a new `Checker`
+    pub(crate) fn new(charmaps: &'a [CharacterMap], contents: &'a str) -> Self {
+        Self {
+            charmaps,
+            contents,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Check every section of `config`, returning the problems found
+    pub(crate) fn check(mut self, config: &Config) -> Vec<Diagnostic> {
+        self.check_bindings(config);
+        self.check_remaps(config);
+        self.check_combos(config);
+        self.check_leader(config);
+        self.check_escape(config);
+
+        self.diagnostics
+    }
+
+    /// Return the item `source` of `section`, for naming it in another item's
+    /// problem
+    fn item(&self, section: &str, source: &str) -> Item {
+        Item {
+            key:  source.to_string(),
+            line: parser::locate(self.contents, section, source, source).map(|(line, _)| line),
+        }
+    }
+
+    /// Record a problem with the item `source` of `section`
+    fn report(&mut self, section: &'static str, source: &str, error: Error) {
+        self.report_in(section, source, source, error);
+    }
+
+    /// Record a problem with `text`, which is either the item `source` of
+    /// `section` or one of its values
+    fn report_in(&mut self, section: &'static str, source: &str, text: &str, error: Error) {
+        let line = parser::locate(self.contents, section, source, text).map(|(line, _)| line);
+        self.diagnostics.push(Diagnostic {
+            section,
+            source: source.to_string(),
+            text: text.to_string(),
+            line,
+            error,
+        });
+    }
+
+    /// Record the problems found while parsing `text`. See
+    /// [`Checker::report_in`]
+    fn report_keys(
+        &mut self,
+        section: &'static str,
+        source: &str,
+        text: &str,
+        errors: Vec<ParseError>,
+    ) {
+        for error in errors {
+            self.report_in(section, source, text, Error::Parse(error));
+        }
+    }
+
+    /// Parse `text`, which is either the item `source` of `section` or one of
+    /// its values, into a `Chain` for every combination of keys its groups
+    /// expand to. See [`parser::parse_chains`]
+    fn check_chains(
+        &mut self,
+        section: &'static str,
+        source: &str,
+        text: &str,
+        all_groups: bool,
+    ) -> Option<Vec<Chain>> {
+        parser::parse_chains(self.charmaps, text, all_groups)
+            .map_err(|errors| self.report_keys(section, source, text, errors))
+            .ok()
+    }
+
+    /// Parse `text` into a single `Chain`. See [`Checker::check_chains`]
+    fn check_chain(&mut self, section: &'static str, source: &str, text: &str) -> Option<Chain> {
+        parser::parse_chain(self.charmaps, text)
+            .map_err(|errors| self.report_keys(section, source, text, errors))
+            .ok()
+    }
+
+    /// Check a key that is sent by a remap or combo. Mouse buttons and release
+    /// events cannot be sent, and tap-hold and one-shot keys can only send a
+    /// single key
+    fn check_target(
+        &mut self,
+        section: &'static str,
+        source: &str,
+        target: &str,
+        single: bool,
+    ) -> Option<Chain> {
+        let chain = self.check_chain(section, source, target)?;
+
+        let unsupported = if chain.chords().iter().any(|c| c.button().code() != 0) {
+            Some("mouse buttons cannot be sent")
+        } else if chain.is_release() {
+            Some("release events cannot be sent")
+        } else if single && chain.chords().len() > 1 {
+            Some("only a single key can be sent")
+        } else {
+            None
+        };
+
+        if let Some(reason) = unsupported {
+            let error = Error::UnsupportedTarget(target.to_string(), reason);
+            self.report_in(section, source, target, error);
+            return None;
+        }
+
+        Some(chain)
+    }
+
+    /// Check the `bindings` section
+    fn check_bindings(&mut self, config: &Config) {
+        let mut chains = Vec::<(&str, Vec<Chord>)>::new();
+
+        for (source, value) in config.bindings.iter().flatten() {
+            let parsed = self.check_chains("bindings", source, source, config.global.all_groups);
+            for mut chain in parsed.into_iter().flatten() {
+                chain.update_device(value.device());
+                chains.push((source, chain.chords().clone()));
+            }
+
+            if let Err(e) = Binding::from_value(source, value) {
+                self.report("bindings", source, Error::Invalid(format!("invalid binding: {e}")));
+            }
+        }
+
+        for found in conflict::find_conflicts(&chains) {
+            let (source, error) = match found {
+                Conflict::Duplicate { first, second } if first == second =>
+                    (second, Error::RepeatedExpansion),
+                Conflict::Duplicate { first, second } =>
+                    (second, Error::Duplicate(self.item("bindings", first))),
+                Conflict::Shadowed { prefix, shadowed } =>
+                    (shadowed, Error::Shadowed(self.item("bindings", prefix))),
+            };
+            self.report("bindings", source, error);
+        }
+    }
+
+    /// Check the `remaps` section
+    fn check_remaps(&mut self, config: &Config) {
+        let mut seen = HashMap::<(Keycode, Option<&DeviceValue>), String>::new();
+
+        for (source, value) in config.remaps.iter().flatten() {
+            let Some(chain) = self.check_chain("remaps", source, source) else {
+                continue;
+            };
+
+            if chain.chords().len() > 1 {
+                let error = Error::Invalid(String::from("a remap can only be from a single key"));
+                self.report("remaps", source, error);
+            }
+
+            let key = (chain.chords()[0].charmap().code(), value.device());
+            if let Some(first) = seen.get(&key) {
+                let error = Error::Duplicate(self.item("remaps", first));
+                self.report("remaps", source, error);
+            } else {
+                seen.insert(key, source.clone());
+            }
+
+            match value {
+                RemapValue::Keys(to) => {
+                    self.check_target("remaps", source, to, false);
+                },
+                RemapValue::Device(opts) => {
+                    self.check_target("remaps", source, &opts.keys, false);
+                },
+                RemapValue::TapHold(opts) => {
+                    if let Some(tap) = &opts.tap {
+                        self.check_target("remaps", source, tap, true);
+                    }
+                    self.check_target("remaps", source, &opts.hold, true);
+                },
+                RemapValue::OneShot(opts) => {
+                    let is_modifier = self
+                        .check_target("remaps", source, &opts.one_shot, true)
+                        .and_then(|chain| chain.chords().first().map(|c| c.charmap().modmask()))
+                        .map(|modmask| modmask != 0);
+
+                    if is_modifier == Some(false) {
+                        let error = Error::UnsupportedTarget(
+                            opts.one_shot.clone(),
+                            "a one-shot key has to send a modifier",
+                        );
+                        self.report_in("remaps", source, &opts.one_shot, error);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Check the `combos` section
+    fn check_combos(&mut self, config: &Config) {
+        let mut seen = Vec::<(HashSet<Keycode>, String)>::new();
+
+        for (source, value) in config.combos.iter().flatten() {
+            if let Some(chain) = self.check_chain("combos", source, source) {
+                let keys = chain
+                    .chords()
+                    .iter()
+                    .map(|c| c.charmap().code())
+                    .collect::<HashSet<_>>();
+
+                if keys.len() < 2 || chain.modmask() != 0 {
+                    let error = Error::Invalid(String::from(
+                        "a combo needs at least two keys and no modifiers",
+                    ));
+                    self.report("combos", source, error);
+                } else if let Some((_, first)) = seen.iter().find(|(k, _)| *k == keys) {
+                    let error = Error::Duplicate(self.item("combos", first));
+                    self.report("combos", source, error);
+                } else {
+                    seen.push((keys, source.clone()));
+                }
+            }
+
+            if let ComboValue::Keys(to) = value {
+                self.check_target("combos", source, to, false);
+            }
+        }
+    }
+
+    /// Check the `leader` and `leader_bindings`
+    fn check_leader(&mut self, config: &Config) {
+        match &config.global.leader {
+            Some(leader) => {
+                self.check_chain("global", "leader", leader);
+            },
+            None =>
+                if config.leader_bindings.is_some() {
+                    let error = Error::Invalid(String::from(
+                        "`leader_bindings` do nothing without a `leader`",
+                    ));
+                    self.report("global", "leader_bindings", error);
+                },
+        }
+
+        let mut seen = HashMap::<Vec<Vec<Chord>>, String>::new();
+
+        for source in config.leader_bindings.iter().flat_map(|b| b.keys()) {
+            let mut errors = Vec::new();
+            let mut sequence = Vec::new();
+
+            for (offset, key) in parser::split_keys(source) {
+                match parser::parse_chain(self.charmaps, key) {
+                    Ok(chain) if chain.chords().len() == 1 => sequence.push(chain.chords().clone()),
+                    Ok(_) => errors.push(Error::Invalid(format!(
+                        "`{key}` is more than one key. Keys of a sequence are separated by spaces"
+                    ))),
+                    Err(found) =>
+                        errors.extend(found.into_iter().map(|e| Error::Parse(e.shift(offset)))),
+                }
+            }
+
+            if sequence.is_empty() && errors.is_empty() {
+                errors.push(Error::NoKeys);
+            }
+
+            if !errors.is_empty() {
+                for error in errors {
+                    self.report("leader_bindings", source, error);
+                }
+            } else if let Some(first) = seen.get(&sequence) {
+                let error = Error::Duplicate(self.item("leader_bindings", first));
+                self.report("leader_bindings", source, error);
+            } else {
+                seen.insert(sequence, source.clone());
+            }
+        }
+    }
+
+    /// Check the `escape` keys
+    fn check_escape(&mut self, config: &Config) {
+        if let Some(escape) = &config.global.escape {
+            if !escape.eq_ignore_ascii_case("none") {
+                self.check_chain("global", "escape", escape);
+            }
+        }
+    }
+}
+
+// ================ Helper Funcs ==================
+
+/// Check the configuration file at `path`, printing every problem that was
+/// found. Returns whether the file is valid
+pub(crate) fn run(keyboard: &Keyboard, path: &Path) -> Result<bool> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file: {}", path.display()))?;
+
+    let config = match serde_yaml::from_str::<Config>(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", SerdeError::new(contents, e));
+            return Ok(false);
+        },
+    };
+
+    let diagnostics = Checker::new(keyboard.charmap(), &contents).check(&config);
+    let path = path.display().to_string();
+
+    for diagnostic in &diagnostics {
+        println!("{}:{}", path.blue().bold(), diagnostic);
+
+        if let Error::Parse(e) = &diagnostic.error {
+            println!(
+                "{}",
+                e.render(Some(&contents), diagnostic.section, &diagnostic.source, &diagnostic.text)
+            );
+        }
+    }
+
+    if diagnostics.is_empty() {
+        println!("{}: {}", path.blue().bold(), "OK".green().bold());
+    } else {
+        println!(
+            "{}: {} problem(s) found",
+            path.blue().bold(),
+            diagnostics.len().to_string().red().bold()
+        );
+    }
+
+    Ok(diagnostics.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{lexer::Span, parser::ErrorKind};
+
+    /// A keyboard with a few keys on it
+    fn charmaps() -> Vec<CharacterMap> {
+        [
+            ("a", 38, 0, 0x61),
+            ("b", 56, 0, 0x62),
+            ("c", 54, 0, 0x63),
+            ("Escape", 9, 0, 0xff1b),
+            ("Super_L", 133, 64, 0xffeb),
+            ("Shift_L", 50, 1, 0xffe1),
+        ]
+        .into_iter()
+        .map(|(utf, code, mask, sym)| CharacterMap::new(utf.into(), code, mask, sym, 1, 0, 0, true))
+        .collect()
+    }
+
+    /// Check `contents`, returning the errors found along with their lines
+    fn check(contents: &str) -> Vec<(Option<usize>, Error)> {
+        let config = serde_yaml::from_str::<Config>(contents).unwrap();
+        let charmaps = charmaps();
+
+        Checker::new(&charmaps, contents)
+            .check(&config)
+            .into_iter()
+            .map(|d| (d.line, d.error))
+            .collect()
+    }
+
+    #[test]
+    fn check_reports_bad_keys() {
+        let contents = "\
+bindings:
+  super + a: echo ok
+  super + foo: echo unknown
+  superx + a: echo modifier
+  super + {c-a}: echo range
+  hyper + a: echo modifier
+";
+        let parse = |kind, start, end| Error::Parse(ParseError::new(kind, Span::new(start, end)));
+
+        assert_eq!(check(contents), vec![
+            (Some(3), parse(ErrorKind::UnknownKeysym(String::from("foo")), 8, 11)),
+            (Some(4), parse(ErrorKind::UnknownKeysym(String::from("superx")), 0, 6)),
+            (Some(5), parse(ErrorKind::RangeOrder(String::from("c"), String::from("a")), 8, 13)),
+            (Some(6), parse(ErrorKind::InvalidModifier(String::from("hyper")), 0, 5)),
+        ]);
+    }
+
+    #[test]
+    fn check_reports_conflicts_and_targets() {
+        let contents = "\
+bindings:
+  super + a: echo first
+  Super_L + a: echo second
+  super + {a-c}: echo range
+  super + b ; c: echo chain
+  '{c,c}': echo twice
+remaps:
+  a: mouse1
+  b:
+    one_shot: c
+escape: esc
+";
+        let item = |key: &str, line| Item {
+            key:  key.to_string(),
+            line: Some(line),
+        };
+
+        assert_eq!(check(contents), vec![
+            (Some(3), Error::Duplicate(item("super + a", 2))),
+            (Some(4), Error::Duplicate(item("super + a", 2))),
+            (Some(6), Error::RepeatedExpansion),
+            (Some(5), Error::Shadowed(item("super + {a-c}", 4))),
+            (
+                Some(8),
+                Error::UnsupportedTarget(String::from("mouse1"), "mouse buttons cannot be sent")
+            ),
+            (
+                Some(10),
+                Error::UnsupportedTarget(String::from("c"), "a one-shot key has to send a modifier")
+            ),
+        ]);
+    }
+
+    #[test]
+    fn check_allows_same_keys_on_other_devices() {
+        let contents = "\
+bindings:
+  super + a: echo main
+  super + [kc:38]:
+    command: echo pad
+    device: Macro Pad
+  Super_L + [kc:38]:
+    command: echo again
+    device: Macro Pad
+remaps:
+  b: c
+  '[kc:56]':
+    keys: Escape
+    device: 12
+";
+        let config = serde_yaml::from_str::<Config>(contents).unwrap();
+        let remaps = config.remaps.as_ref().unwrap();
+        assert_eq!(remaps["[kc:56]"].device(), Some(&DeviceValue::Id(12)));
+
+        assert_eq!(check(contents), vec![(
+            Some(6),
+            Error::Duplicate(Item {
+                key:  String::from("super + [kc:38]"),
+                line: Some(3),
+            })
+        )]);
+    }
+}
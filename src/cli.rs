@@ -84,13 +84,31 @@ pub(crate) struct Opts {
     )]
     pub(crate) keysyms: bool,
 
+    /// Check the configuration file for problems without starting the daemon
+    #[clap(
+        name = "check",
+        long = "check",
+        value_name = "file",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = &["keysyms", "temporary"],
+        long_help = "\
+        Check a configuration file for problems, such as unknown keysyms, invalid modifiers, \
+                     ranges or options that expand to nothing, duplicate bindings, and remaps to \
+                     keys that cannot be sent. Each problem is printed along with its line, and \
+                     the exit code is 1 if any are found. The daemon is not started. If no file \
+                     is given, the file passed to `--config` or the default one is checked"
+    )]
+    #[allow(clippy::option_option)]
+    pub(crate) check: Option<Option<PathBuf>>,
+
     /// Kill the daemon if it is running
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     #[clap(
         name = "kill",
         long = "kill",
         short = 'k',
         takes_value = false,
+        conflicts_with = "check",
         long_help = "\
         Kill the daemonized process if it is currently running. If a PID file was specified on the \
                      command line when the process was first daemonized, that same PID file must \
@@ -99,14 +117,42 @@ pub(crate) struct Opts {
     )]
     pub(crate) kill: bool,
 
+    /// Show whether the daemon is running
+    #[cfg(feature = "daemon")]
+    #[clap(
+        name = "status",
+        long = "status",
+        short = 's',
+        takes_value = false,
+        conflicts_with_all = &["kill", "daemonize", "restart", "keysyms", "check"],
+        long_help = "\
+        Show whether lxhkd is running, either daemonized or in the foreground, along with its \
+                     PID. The exit code is 0 if it is running and 1 if it is not"
+    )]
+    pub(crate) status: bool,
+
+    /// Restart the daemon, or start it if it is not running
+    #[cfg(feature = "daemon")]
+    #[clap(
+        name = "restart",
+        long = "restart",
+        short = 'r',
+        takes_value = false,
+        conflicts_with_all = &["kill", "daemonize", "keysyms", "temporary", "check"],
+        long_help = "\
+        Stop the running instance of lxhkd, waiting for it to exit, and then start a new one in \
+                     the background. The new instance is started even if none was running"
+    )]
+    pub(crate) restart: bool,
+
     /// Daemonize the process by sending it to the background
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     #[clap(
         name = "daemonize",
         long = "daemonize",
         short = 'd',
         takes_value = false,
-        conflicts_with_all = &["kill", "keysyms", "temporary"],
+        conflicts_with_all = &["kill", "keysyms", "temporary", "check"],
         long_help = "Send the process to the background and write the PID of the process to a \
                       PID file"
     )]
@@ -122,7 +168,7 @@ pub(crate) struct Opts {
     pub(crate) temporary: bool,
 
     /// Specify a PID file. A default PID file should be used most of the time
-    #[cfg(feature = "daemonize")]
+    #[cfg(feature = "daemon")]
     #[clap(
         long = "pidfile",
         short = 'p',
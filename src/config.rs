@@ -1,7 +1,11 @@
 //! Configuration options
 
-use crate::keys::chord::{Chain, Chord};
-use anyhow::{Context, Result};
+use crate::keys::{
+    chord::{Chain, Chord},
+    executor::Executor,
+    remap::Strategy,
+};
+use anyhow::{anyhow, Context, Result};
 use colored::Colorize;
 use format_serde_error::SerdeError;
 use indexmap::IndexMap;
@@ -11,6 +15,7 @@ use std::{
     cmp::Ordering,
     collections::HashMap,
     env,
+    fmt,
     fs,
     io::Write,
     path::{Path, PathBuf},
@@ -68,6 +73,44 @@ pub(crate) struct GlobalSettings {
     /// The speed in which keys repeat after the delay
     #[serde(alias = "autorepeat-interval")]
     pub(crate) autorepeat_interval: Option<u16>,
+
+    /// The file that the state of persisted `toggle`/`cycle` bindings is
+    /// written to
+    #[serde(alias = "state-file")]
+    pub(crate) state_file: Option<PathBuf>,
+
+    /// The number of milliseconds all keys of a combo have to be pressed
+    /// within
+    #[serde(alias = "combo-timeout")]
+    pub(crate) combo_timeout: Option<u32>,
+
+    /// The binding that starts a leader sequence
+    pub(crate) leader: Option<String>,
+
+    /// The number of milliseconds to wait for each key of a leader sequence
+    #[serde(alias = "leader-timeout")]
+    pub(crate) leader_timeout: Option<u32>,
+
+    /// The keys that give back every grab and release every key pressed by
+    /// this program, in case the keyboard ends up stuck. `none` disables it
+    pub(crate) escape: Option<String>,
+
+    /// Whether the daemon stops after the `escape` keys are pressed
+    #[serde(alias = "escape-exits")]
+    #[serde(default)]
+    pub(crate) escape_exits: bool,
+
+    /// Whether bindings to keysyms also match in the other layout groups. In a
+    /// group without the keysym, the key it is on in the first group is used
+    #[serde(alias = "all-groups")]
+    #[serde(default)]
+    pub(crate) all_groups: bool,
+
+    /// The keyboards whose key events are ignored altogether, such as the
+    /// `Virtual core XTEST keyboard` that tools like `xdotool` type with
+    #[serde(alias = "ignore-devices")]
+    #[serde(default)]
+    pub(crate) ignore_devices: Vec<DeviceValue>,
 }
 
 // =================== Config =====================
@@ -82,12 +125,22 @@ pub(crate) struct Config {
     #[serde(flatten)]
     pub(crate) global:   GlobalSettings,
     /// The mappings of keys to shell commands
-    pub(crate) bindings: Option<IndexMap<String, String>>,
+    pub(crate) bindings: Option<IndexMap<String, BindingValue>>,
     /// The mappings of keys to other keybindings
-    pub(crate) remaps:   Option<IndexMap<String, String>>,
+    pub(crate) remaps:   Option<IndexMap<String, RemapValue>>,
+    /// The mappings of keys pressed together to other keys or shell commands
+    pub(crate) combos:   Option<IndexMap<String, ComboValue>>,
+    /// The mappings of sequences typed after the `leader` to shell commands
+    #[serde(alias = "leader-bindings")]
+    pub(crate) leader_bindings: Option<IndexMap<String, String>>,
 
     /// Mappings of modifiers to one key when pressed & another when held down
     pub(crate) xcape: Option<IndexMap<String, String>>,
+
+    /// The contents of the file the configuration was loaded from, which is
+    /// used to point at the problems found in it
+    #[serde(skip)]
+    pub(crate) contents: Option<String>,
 }
 
 impl Config {
@@ -127,7 +180,11 @@ impl Config {
         // serde_yaml::from_slice(&file).context("failed to deserialize config file")
 
         let file = fs::read_to_string(&path).context("failed to read config file")?;
-        Ok(serde_yaml::from_str(&file).map_err(|e| SerdeError::new(file, e))?)
+        let mut config: Self =
+            serde_yaml::from_str(&file).map_err(|e| SerdeError::new(file.clone(), e))?;
+        config.contents = Some(file);
+
+        Ok(config)
     }
 
     /// Load the default configuration file
@@ -138,6 +195,286 @@ impl Config {
     }
 }
 
+// ================= BindingValue =================
+
+/// The right-hand side of a binding in the configuration file. This is either a
+/// single shell command, or a mapping of options
+///
+/// ```yaml
+/// bindings:
+///   super + t: alacritty
+///   super + m:
+///     toggle: [pactl set-sink-mute 0 1, pactl set-sink-mute 0 0]
+///   super + l:
+///     command: slock
+///     hold: systemctl suspend
+///     hold_time: 800
+///   Shift_L:
+///     double: rofi -show drun
+///   KP_1:
+///     command: obs-cli scene switch 1
+///     device: Macro Pad
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum BindingValue {
+    /// A shell command that is ran every time the binding is pressed
+    Command(String),
+    /// A binding with extra options
+    Options(BindingOptions),
+}
+
+/// The options a binding can have when it is not a plain shell command
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BindingOptions {
+    /// A shell command that is ran every time the binding is pressed
+    #[serde(alias = "run")]
+    pub(crate) command: Option<String>,
+
+    /// Two commands that are alternated between on successive presses
+    pub(crate) toggle: Option<Vec<String>>,
+
+    /// A list of commands that are cycled through on successive presses
+    pub(crate) cycle: Option<Vec<String>>,
+
+    /// Whether the position of a `toggle` or `cycle` is kept after the daemon
+    /// reloads or restarts
+    #[serde(default)]
+    pub(crate) persist: bool,
+
+    /// A shell command that is ran instead when the binding is held down for
+    /// at least `hold_time`
+    pub(crate) hold: Option<String>,
+
+    /// The number of milliseconds a binding has to be held for `hold` to run
+    #[serde(alias = "hold-time")]
+    pub(crate) hold_time: Option<u32>,
+
+    /// Run `hold` as soon as `hold_time` has passed, while the key is still
+    /// down, instead of waiting for the key to be released
+    #[serde(alias = "fire-on-hold")]
+    #[serde(default)]
+    pub(crate) fire_on_hold: bool,
+
+    /// A shell command that is ran when the binding is pressed twice within
+    /// `tap_window`
+    pub(crate) double: Option<String>,
+
+    /// A shell command that is ran when the binding is pressed three times
+    /// within `tap_window`
+    pub(crate) triple: Option<String>,
+
+    /// The number of milliseconds allowed between each tap of a `double` or
+    /// `triple` binding
+    #[serde(alias = "tap-window")]
+    pub(crate) tap_window: Option<u32>,
+
+    /// The keyboard the binding is limited to
+    pub(crate) device: Option<DeviceValue>,
+}
+
+impl BindingValue {
+    /// Convert the right-hand side of a binding into the [`Action`] that is ran
+    /// when the binding is tapped once. There is no such `Action` when the
+    /// binding only has a `hold`, `double`, or `triple` command
+    pub(crate) fn to_action(&self) -> Result<Option<Action>> {
+        let opts = match self {
+            Self::Command(cmd) => return Ok(Some(Action::Shell(cmd.clone()))),
+            Self::Options(opts) => opts,
+        };
+
+        match (&opts.command, &opts.toggle, &opts.cycle) {
+            (Some(cmd), None, None) => Ok(Some(Action::Shell(cmd.clone()))),
+            (None, Some(cmds), None) => {
+                if cmds.len() != 2 {
+                    return Err(anyhow!(
+                        "`toggle` requires exactly two commands, found {}",
+                        cmds.len()
+                    ));
+                }
+                Ok(Some(Action::Toggle(cmds.clone())))
+            },
+            (None, None, Some(cmds)) => {
+                if cmds.is_empty() {
+                    return Err(anyhow!("`cycle` requires at least one command"));
+                }
+                Ok(Some(Action::Cycle(cmds.clone())))
+            },
+            (None, None, None) =>
+                if opts.hold.is_some() || opts.double.is_some() || opts.triple.is_some() {
+                    Ok(None)
+                } else {
+                    Err(anyhow!(
+                        "a binding needs one of `command`, `toggle`, `cycle`, `hold`, `double`, \
+                         or `triple`"
+                    ))
+                },
+            _ => Err(anyhow!(
+                "only one of `command`, `toggle`, or `cycle` can be used in a binding"
+            )),
+        }
+    }
+
+    /// Whether the state of the binding should be kept across reloads
+    pub(crate) fn persist(&self) -> bool {
+        match self {
+            Self::Command(_) => false,
+            Self::Options(opts) => opts.persist,
+        }
+    }
+
+    /// Return the options of the binding, if it isn't a plain command
+    pub(crate) fn options(&self) -> Option<&BindingOptions> {
+        match self {
+            Self::Command(_) => None,
+            Self::Options(opts) => Some(opts),
+        }
+    }
+
+    /// Return the keyboard the binding is limited to, if any
+    pub(crate) fn device(&self) -> Option<&DeviceValue> {
+        self.options().and_then(|opts| opts.device.as_ref())
+    }
+}
+
+// ================== RemapValue ==================
+
+/// The right-hand side of a remap in the configuration file. This is either the
+/// keys that replace the remapped key, or a key that is different when tapped
+/// and held
+///
+/// ```yaml
+/// remaps:
+///   Caps_Lock: Escape
+///   KP_Enter:
+///     keys: ctrl + s
+///     device: 12
+///   a:
+///     hold: super
+///     strategy: permissive-hold
+///   Shift_L:
+///     one_shot: Shift_L
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum RemapValue {
+    /// Keys that replace the remapped key
+    Keys(String),
+    /// Keys that replace the remapped key when it is pressed on one keyboard
+    Device(DeviceRemapOptions),
+    /// A key that is different when tapped and held
+    TapHold(TapHoldOptions),
+    /// A key that applies a modifier to the next key only
+    OneShot(OneShotOptions),
+}
+
+impl RemapValue {
+    /// Return the keyboard the remap is limited to, if any
+    pub(crate) fn device(&self) -> Option<&DeviceValue> {
+        match self {
+            Self::Device(opts) => Some(&opts.device),
+            Self::Keys(_) | Self::TapHold(_) | Self::OneShot(_) => None,
+        }
+    }
+}
+
+/// The options of keys that replace the remapped key on one keyboard only
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DeviceRemapOptions {
+    /// Keys that replace the remapped key
+    #[serde(alias = "to")]
+    pub(crate) keys:   String,
+
+    /// The keyboard the remap is limited to
+    pub(crate) device: DeviceValue,
+}
+
+/// The options of a key that is different when tapped and held
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TapHoldOptions {
+    /// The key that is sent when tapped. Defaults to the remapped key
+    pub(crate) tap: Option<String>,
+
+    /// The key that is sent while held
+    pub(crate) hold: String,
+
+    /// How a tap is told apart from a hold when other keys are pressed
+    #[serde(default)]
+    pub(crate) strategy: Strategy,
+
+    /// The number of milliseconds the key has to be down to be held
+    #[serde(alias = "tapping-term")]
+    pub(crate) tapping_term: Option<u32>,
+
+    /// Pressing the key again within this many milliseconds of a tap is always
+    /// a tap, which allows the tap key to be repeated
+    #[serde(alias = "quick-tap")]
+    pub(crate) quick_tap: Option<u32>,
+}
+
+/// The options of a key that applies a modifier to the next key only
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OneShotOptions {
+    /// The modifier that is applied
+    #[serde(alias = "one-shot")]
+    pub(crate) one_shot: String,
+}
+
+// ================== ComboValue ==================
+
+/// The right-hand side of a combo in the configuration file. This is either the
+/// keys that are sent in place of the combo, or a shell command
+///
+/// ```yaml
+/// combos:
+///   j + k: Escape
+///   d + f:
+///     command: rofi -show run
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum ComboValue {
+    /// Keys that are sent in place of the combo
+    Keys(String),
+    /// A shell command that is ran when the combo is pressed
+    Command {
+        /// The shell command
+        #[serde(alias = "run")]
+        command: String,
+    },
+}
+
+// ================= DeviceValue ==================
+
+/// A keyboard, either by the name `xinput list` shows for it or by its XInput2
+/// id. A name matches every device with that name, since a keyboard often has
+/// more than one
+///
+/// ```yaml
+/// ignore_devices: [Virtual core XTEST keyboard]
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub(crate) enum DeviceValue {
+    /// The XInput2 id of the device
+    Id(u16),
+    /// The name of the device
+    Name(String),
+}
+
+impl fmt::Display for DeviceValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{id}"),
+            Self::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
 // =================== Action =====================
 
 /// The action that a mapping will do
@@ -154,6 +491,12 @@ pub(crate) enum Action {
     ///     - `Caps_Lock` => `Escape` when tapped
     ///     - `Caps_Lock` => `Hyper_L` when held
     Xcape(String),
+
+    /// Two shell commands that are alternated between (i.e., mute/unmute)
+    Toggle(Vec<String>),
+
+    /// Shell commands that are cycled through, one per key press
+    Cycle(Vec<String>),
 }
 
 impl Action {
@@ -192,12 +535,12 @@ impl Action {
         }
     }
 
-    /// Run the given `Action`
-    pub(crate) fn run(&self, shell: &Option<String>) {
+    /// Run the given `Action` on the [`Executor`]
+    pub(crate) fn run(&self, exec: &Executor) {
         match self {
             Self::Shell(cmd) => {
                 log::trace!("running shell: {}", cmd);
-                Self::spawn_shell(cmd, shell.as_ref().unwrap_or(&SHELL.to_string()));
+                exec.spawn(cmd);
             },
             Self::Remap(remap) => {
                 log::trace!("running remap: {}", remap);
@@ -206,6 +549,13 @@ impl Action {
             Self::Xcape(xcape) => {
                 log::trace!("running xcape: {}", xcape);
             },
+            // Without any state, the first branch is the one that is ran
+            Self::Toggle(cmds) | Self::Cycle(cmds) => {
+                if let Some(cmd) = cmds.first() {
+                    log::trace!("running first branch: {}", cmd);
+                    exec.spawn(cmd);
+                }
+            },
         }
     }
 }
@@ -218,3 +568,8 @@ pub(crate) fn get_config_path() -> Result<PathBuf> {
         .map(|p| p.join("lxhkd"))
         .context("unable to join config path")
 }
+
+/// Get the default configuration file
+pub(crate) fn get_config_file() -> Result<PathBuf> {
+    get_config_path().map(|p| p.join(CONFIG_FILE))
+}
@@ -0,0 +1,404 @@
+//! Bindings parsed from the configuration file, and the state that is kept
+//! for bindings which change every time they are pressed
+
+use super::{chord::Chain, executor::Executor};
+use crate::config::{Action, BindingValue};
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The default number of milliseconds a long-press binding is held for
+const DEFAULT_HOLD_TIME: u32 = 500;
+
+/// The default number of milliseconds allowed between the taps of a multi-tap
+/// binding
+const DEFAULT_TAP_WINDOW: u32 = 250;
+
+// =================== Binding ====================
+
+/// A single binding from the configuration file after it has been parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Binding {
+    /// The left-hand side of the binding as it was written in the configuration
+    source:  String,
+    /// The name the position of a `Toggle` or `Cycle` is kept under. Each of the
+    /// keys that the groups of the binding expand to has its own position
+    state:   String,
+    /// What is done whenever the binding is matched
+    action:  Option<Action>,
+    /// Whether the position of a `Toggle` or `Cycle` survives a reload
+    persist: bool,
+    /// A different action for when the binding is held down
+    hold:    Option<Hold>,
+    /// Different actions for when the binding is tapped multiple times
+    taps:    Option<Taps>,
+}
+
+impl Binding {
+    /// Create a new `Binding`
+    pub(crate) fn new(source: &str, action: Option<Action>, persist: bool) -> Self {
+        Self {
+            source: source.to_string(),
+            state: source.to_string(),
+            action,
+            persist,
+            hold: None,
+            taps: None,
+        }
+    }
+
+    /// Create a `Binding` from the right-hand side of a binding in the
+    /// configuration file
+    pub(crate) fn from_value(source: &str, value: &BindingValue) -> Result<Self> {
+        let mut binding = Self::new(source, value.to_action()?, value.persist());
+
+        if let Some(opts) = value.options() {
+            if let Some(cmd) = &opts.hold {
+                binding.hold = Some(Hold {
+                    action:       Action::Shell(cmd.clone()),
+                    time:         opts.hold_time.unwrap_or(DEFAULT_HOLD_TIME),
+                    while_held:   opts.fire_on_hold,
+                });
+            } else if opts.hold_time.is_some() || opts.fire_on_hold {
+                log::warn!(
+                    "<{}> - `hold_time` and `fire_on_hold` do nothing without `hold`",
+                    source.purple().bold()
+                );
+            }
+
+            if opts.double.is_some() || opts.triple.is_some() {
+                if binding.hold.is_some() {
+                    return Err(anyhow!("`hold` cannot be used with `double` or `triple`"));
+                }
+
+                binding.taps = Some(Taps {
+                    double: opts.double.clone().map(Action::Shell),
+                    triple: opts.triple.clone().map(Action::Shell),
+                    window: opts.tap_window.unwrap_or(DEFAULT_TAP_WINDOW),
+                });
+            } else if opts.tap_window.is_some() {
+                log::warn!(
+                    "<{}> - `tap_window` does nothing without `double` or `triple`",
+                    source.purple().bold()
+                );
+            }
+        }
+
+        Ok(binding)
+    }
+
+    /// Return the `Binding` for `chain`, one of the keys that its groups expand
+    /// to, so that it keeps a position apart from the other keys
+    pub(crate) fn expanded(&self, chain: &Chain) -> Self {
+        Self {
+            state: format!("{}: {}", self.source, chain),
+            ..self.clone()
+        }
+    }
+
+    /// Return the binding as it was written in the configuration file
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Return the [`Action`] of the `Binding` that is ran on a single press
+    pub(crate) fn action(&self) -> Option<&Action> {
+        self.action.as_ref()
+    }
+
+    /// Return the name the position of the `Binding` is kept under
+    pub(crate) fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Return whether the state of the `Binding` is persisted
+    pub(crate) fn persist(&self) -> bool {
+        self.persist
+    }
+
+    /// Return the [`Hold`] of the `Binding`, if it is a long-press binding
+    pub(crate) fn hold(&self) -> Option<&Hold> {
+        self.hold.as_ref()
+    }
+
+    /// Return the [`Taps`] of the `Binding`, if it is a multi-tap binding
+    pub(crate) fn taps(&self) -> Option<&Taps> {
+        self.taps.as_ref()
+    }
+
+    /// Run the action for a press that lasted `held` milliseconds. Returns
+    /// whether the `hold` action was the one that ran
+    pub(crate) fn run_for(
+        &self,
+        held: u32,
+        state: &mut BindingState,
+        exec: &Executor,
+    ) -> bool {
+        match &self.hold {
+            Some(hold) if held >= hold.time => {
+                hold.run(self, held, exec);
+                true
+            },
+            _ => {
+                self.run(state, exec);
+                false
+            },
+        }
+    }
+
+    /// Run the `Binding`. `Toggle` and `Cycle` actions are advanced to their
+    /// next branch in the given [`BindingState`]
+    pub(crate) fn run(&self, state: &mut BindingState, exec: &Executor) {
+        match &self.action {
+            Some(action @ (Action::Toggle(branches) | Action::Cycle(branches))) => {
+                let idx = state.advance(self, branches.len());
+                let cmd = &branches[idx];
+
+                log::info!(
+                    "{}: running branch {}/{} of {}: {}",
+                    if matches!(action, Action::Toggle(_)) { "toggle" } else { "cycle" }
+                        .red()
+                        .bold(),
+                    idx + 1,
+                    branches.len(),
+                    self.source.purple().bold(),
+                    cmd.green().bold()
+                );
+
+                exec.spawn(cmd);
+            },
+            Some(action) => action.run(exec),
+            None => log::debug!(
+                "<{}> - nothing to run for a single press",
+                self.source.purple().bold()
+            ),
+        }
+    }
+
+    /// Run the action for a binding that was tapped `count` times in a row
+    pub(crate) fn run_taps(&self, count: u8, state: &mut BindingState, exec: &Executor) {
+        match self.taps.as_ref().and_then(|taps| taps.action_for(count)) {
+            Some(action) => {
+                log::info!(
+                    "{}: {} was tapped {} times",
+                    "taps".red().bold(),
+                    self.source.purple().bold(),
+                    count
+                );
+                action.run(exec);
+            },
+            None => self.run(state, exec),
+        }
+    }
+}
+
+// ===================== Hold =====================
+
+/// The action of a long-press binding, which runs in place of the normal
+/// action when the binding is held down long enough
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hold {
+    /// What is done when the binding is held
+    action:       Action,
+    /// The number of milliseconds the binding has to be held for
+    time:         u32,
+    /// Whether the action fires while the key is still down
+    while_held:   bool,
+}
+
+impl Hold {
+    /// Return the number of milliseconds the binding has to be held for
+    pub(crate) fn time(&self) -> u32 {
+        self.time
+    }
+
+    /// Return the amount of time the binding has to be held for
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(u64::from(self.time))
+    }
+
+    /// Return whether the action fires while the key is still down, rather
+    /// than when it is released
+    pub(crate) fn fire_on_hold(&self) -> bool {
+        self.while_held
+    }
+
+    /// Run the `hold` action of `binding`
+    pub(crate) fn run(&self, binding: &Binding, held: u32, exec: &Executor) {
+        log::info!(
+            "{}: {} was held for {}ms",
+            "hold".red().bold(),
+            binding.source().purple().bold(),
+            held
+        );
+        self.action.run(exec);
+    }
+}
+
+// ===================== Taps =====================
+
+/// The actions of a multi-tap binding, which run in place of the normal action
+/// when the binding is pressed several times within a short window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Taps {
+    /// What is done when the binding is tapped twice
+    double: Option<Action>,
+    /// What is done when the binding is tapped three times
+    triple: Option<Action>,
+    /// The number of milliseconds allowed between each tap
+    window: u32,
+}
+
+impl Taps {
+    /// Return the number of milliseconds allowed between each tap
+    pub(crate) fn window(&self) -> u32 {
+        self.window
+    }
+
+    /// Return the amount of time that is waited for another tap
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(u64::from(self.window))
+    }
+
+    /// Return the most taps that can be told apart. Once this many taps are
+    /// seen there is no need to wait for another one
+    pub(crate) fn max(&self) -> u8 {
+        if self.triple.is_some() {
+            3
+        } else {
+            2
+        }
+    }
+
+    /// Return the action for `count` taps. This is the action of the highest
+    /// number of taps that is defined and not greater than `count`. `None`
+    /// means the single press action should be ran
+    pub(crate) fn action_for(&self, count: u8) -> Option<&Action> {
+        match count {
+            0 | 1 => None,
+            2 => self.double.as_ref(),
+            _ => self.triple.as_ref().or(self.double.as_ref()),
+        }
+    }
+}
+
+// ================= BindingState =================
+
+/// The position of every `Toggle` and `Cycle` binding. This lives within the
+/// [`Daemon`](super::daemon::Daemon) and is reset whenever the configuration
+/// is processed again, unless the binding asks for it to be persisted
+#[derive(Debug, Default)]
+pub(crate) struct BindingState {
+    /// Index of the next branch to run, keyed by the binding's
+    /// [`state`](Binding::state)
+    positions: HashMap<String, usize>,
+    /// The states of the bindings whose positions are written to `path`
+    persisted: HashSet<String>,
+    /// The file that persisted positions are written to
+    path:      Option<PathBuf>,
+}
+
+impl BindingState {
+    /// Create a `BindingState`, loading any persisted positions from `path`
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let positions = fs::read_to_string(path)
+            .ok()
+            .and_then(|file| {
+                serde_yaml::from_str::<HashMap<String, usize>>(&file)
+                    .map_err(|e| {
+                        log::warn!("ignoring invalid state file {}: {}", path.display(), e);
+                    })
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        Self {
+            positions,
+            persisted: HashSet::new(),
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    /// Reset the positions of every binding that is not persisted. This is
+    /// called each time the bindings are processed
+    pub(crate) fn retain_persisted<'a>(&mut self, bindings: impl Iterator<Item = &'a Binding>) {
+        self.persisted = bindings
+            .filter(|b| b.persist())
+            .map(|b| b.state().to_string())
+            .collect();
+
+        let persisted = &self.persisted;
+        self.positions.retain(|state, _| persisted.contains(state));
+    }
+
+    /// Return the branch that should be ran for `binding` and move on to the
+    /// next one
+    pub(crate) fn advance(&mut self, binding: &Binding, len: usize) -> usize {
+        let pos = self
+            .positions
+            .entry(binding.state().to_string())
+            .or_insert(0);
+        let current = *pos % len;
+        *pos = (current + 1) % len;
+
+        if binding.persist() {
+            if let Err(e) = self.save() {
+                log::warn!("failed to persist binding state: {}", e);
+            }
+        }
+
+        current
+    }
+
+    /// Write the current positions to the state file
+    pub(crate) fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("failed to create state directory")?;
+            }
+
+            let persisted = self
+                .positions
+                .iter()
+                .filter(|(state, _)| self.persisted.contains(*state))
+                .collect::<BTreeMap<_, _>>();
+            fs::write(
+                path,
+                serde_yaml::to_string(&persisted).context("failed to serialize binding state")?,
+            )
+            .with_context(|| format!("failed to write state file: {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{chord::Chord, keys::CharacterMap};
+
+    #[test]
+    fn expansions_keep_their_own_position() {
+        let chain = |utf: &str, code| {
+            let charmap = CharacterMap::new(utf.into(), code, 0, 0, 1, 0, 0, true);
+            Chain::new(vec![Chord::new(&charmap, 0, 0.into(), 2)], false, 0.into())
+        };
+        let cycle = Action::Cycle(vec![String::from("one"), String::from("two")]);
+        let binding = Binding::new("{a,b}", Some(cycle), false);
+        let (a, b) = (binding.expanded(&chain("a", 38)), binding.expanded(&chain("b", 56)));
+
+        let mut state = BindingState::default();
+        assert_eq!(state.advance(&a, 2), 0);
+        assert_eq!(state.advance(&a, 2), 1);
+        assert_eq!(state.advance(&b, 2), 0);
+        assert_eq!(state.advance(&a, 2), 0);
+        assert_ne!(a.state(), b.state());
+    }
+}
@@ -1,16 +1,19 @@
 //! The intermediate structures in the following steps:
 //!     (1) Configuration file gets parsed
-//!     (2) Further tokenized into `TokenizedLine`s
+//!     (2) Further parsed into a `ChainExpr`
 //!     (3) Transformed into `Chord`s and `Chain`s
 //!     (4) Interface with mappings and bindings
 
 use super::{
-    keys::{ButtonCode, CharacterMap, ModifierMask, XButton},
+    keys::{ButtonCode, CharacterMap, ModifierMask, Sides, XButton},
     keysym::XKeysym,
 };
 use crate::{
-    config::Action,
-    parse::parser::{Line, Token, TokenizedLine},
+    config::{Action, DeviceValue},
+    parse::{
+        lexer::{LINK_SEP, RELEASE_PREFIX, SYM_SEP},
+        parser::{self, Error as ParseError},
+    },
 };
 use anyhow::{Context, Result};
 use colored::Colorize;
@@ -18,7 +21,7 @@ use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::{cmp::Ordering, collections::BTreeMap, fmt};
 use thiserror::Error;
-use x11rb::protocol::xproto::Keycode;
+use x11rb::protocol::xproto::{self, Keycode, ModMask};
 
 // =================== Error ======================
 
@@ -33,13 +36,27 @@ pub(crate) enum Error {
 
 // =================== Chord ======================
 
+/// The modifiers of a [`Chord`] in the order they are written, along with the
+/// names they are written as, and the names of the left and right keys of the
+/// modifier if it has to be held on one side. `mod4` and `mod1` are named
+/// after the keys that are usually bound to them. `mod3` and `mod5` have no
+/// keys that are usually bound to them, so they are written without a side
+const MODIFIER_NAMES: &[(ModMask, &str, Option<[&str; 2]>)] = &[
+    (ModMask::M4, "super", Some(["lsuper", "rsuper"])),
+    (ModMask::M3, "mod3", None),
+    (ModMask::M5, "mod5", None),
+    (ModMask::M1, "alt", Some(["lalt", "ralt"])),
+    (ModMask::CONTROL, "ctrl", Some(["lctrl", "rctrl"])),
+    (ModMask::SHIFT, "shift", Some(["lshift", "rshift"])),
+];
+
 /// An abstraction of a step in the process of binding keys.
 ///
 /// If this is created from configuration bindings, then everything except
 /// `modmask` and `event_type` are filled in. Once this `Chord` gets parsed by
 /// the [`Handler`](super::event_handler::Handler), the `modmask` and
 /// `event_type` from the reply are filled in
-#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
 pub(crate) struct Chord {
     /// Extra information about the key
     charmap:    CharacterMap,
@@ -49,18 +66,38 @@ pub(crate) struct Chord {
     button:     XButton,
     /// Modmask -- only used X-Server events, not configuration
     modmask:    ModifierMask,
+    /// The side each modifier of `modmask` has to be held on, if any
+    sides:      Sides,
+    /// The keyboard the key has to be pressed on, if any
+    device:     Option<DeviceValue>,
     /// Event's response code -- only use X-Server events, not configuration
     event_type: u8,
 }
 
+/// Write the `Chord` as it would be in the configuration file (e.g., `super +
+/// rshift + a`). A modifier held on both sides is written for each side
 impl fmt::Display for Chord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "keysym: {}, modmask: {:?}",
-            self.keysym,
-            self.charmap.modmask()
-        )
+        for (mask, name, sided) in MODIFIER_NAMES {
+            let mask = u16::from(*mask);
+            if !self.modmask.has_mod(mask) {
+                continue;
+            }
+
+            let on = (self.sides.left() & mask != 0, self.sides.right() & mask != 0);
+            match (sided, on) {
+                (Some([left, _]), (true, false)) => write!(f, "{left} {SYM_SEP} ")?,
+                (Some([_, right]), (false, true)) => write!(f, "{right} {SYM_SEP} ")?,
+                (Some([left, right]), (true, true)) =>
+                    write!(f, "{left} {SYM_SEP} {right} {SYM_SEP} ")?,
+                _ => write!(f, "{name} {SYM_SEP} ")?,
+            }
+        }
+
+        if self.is_release() {
+            write!(f, "{RELEASE_PREFIX}")?;
+        }
+        write!(f, "{}", self.charmap.utf())
     }
 }
 
@@ -94,6 +131,8 @@ impl Chord {
             keysym: XKeysym::from(charmap.symbol()),
             button: XButton::new(mask, button),
             modmask: mask,
+            sides: Sides::default(),
+            device: None,
             event_type,
         }
     }
@@ -113,6 +152,16 @@ impl Chord {
         self.modmask
     }
 
+    /// Return the [`Sides`] the modifiers of the `Chord` have to be held on
+    pub(crate) fn sides(&self) -> Sides {
+        self.sides
+    }
+
+    /// Return the keyboard the key of the `Chord` has to be pressed on
+    pub(crate) fn device(&self) -> Option<&DeviceValue> {
+        self.device.as_ref()
+    }
+
     /// Return the [`CharacterMap`] of the `Chord`
     pub(crate) fn charmap(&self) -> &CharacterMap {
         &self.charmap
@@ -128,6 +177,12 @@ impl Chord {
         self.event_type
     }
 
+    /// Return whether the `Chord` matches the release of its key or button
+    pub(crate) fn is_release(&self) -> bool {
+        self.event_type == xproto::KEY_RELEASE_EVENT
+            || self.event_type == xproto::BUTTON_RELEASE_EVENT
+    }
+
     /// Return whether the `Chord` contains and uppercase character
     pub(crate) fn contains_uppercase(&self) -> bool {
         (self.charmap.symbol() >= 0x41 && self.charmap.symbol() <= 0x5A)
@@ -145,6 +200,16 @@ impl Chord {
         self.modmask = new;
     }
 
+    /// Change the `Sides` value, keeping only the modifiers of the `Chord`
+    pub(crate) fn update_sides(&mut self, new: Sides) {
+        self.sides = new.only(self.modmask);
+    }
+
+    /// Change the keyboard the key has to be pressed on
+    pub(crate) fn update_device(&mut self, new: Option<DeviceValue>) {
+        self.device = new;
+    }
+
     /// Change the `Keycode` of the `CharacterMap`
     pub(crate) fn update_keycode(&mut self, new: Keycode) {
         self.charmap.set_code(new);
@@ -164,12 +229,33 @@ pub(crate) struct Chain {
     is_release: bool,
 }
 
+/// Write the `Chain` as it would be in the configuration file (e.g., `super +
+/// shift + a ; ctrl + x`). It is parsed back with [`Chain::parse`]
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, chord) in self.chords.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " {LINK_SEP} ")?;
+            }
+            write!(f, "{chord}")?;
+        }
+        Ok(())
+    }
+}
+
 impl Chain {
     /// Create a new `Chain`
     pub(crate) fn new(chords: Vec<Chord>, is_release: bool, modmask: ModifierMask) -> Self {
         Self { chords, modmask, is_release }
     }
 
+    /// Parse the keys of a `Chain` as they are written in the configuration
+    /// file. Unlike `FromStr`, this needs the keys of the keyboard to find the
+    /// keys on
+    pub(crate) fn parse(charmaps: &[CharacterMap], input: &str) -> Result<Self, Vec<ParseError>> {
+        parser::parse_chain(charmaps, input)
+    }
+
     /// Check whether the `Chain` has release `Chord`s
     pub(crate) const fn is_release(&self) -> bool {
         self.is_release
@@ -210,6 +296,13 @@ impl Chain {
         self.chords.push(chord);
     }
 
+    /// Change the keyboard that the key of every `Chord` has to be pressed on
+    pub(crate) fn update_device(&mut self, new: Option<&DeviceValue>) {
+        for chord in &mut self.chords {
+            chord.update_device(new.cloned());
+        }
+    }
+
     /// Return the length of the `Chord`s
     pub(crate) fn len(&self) -> usize {
         self.chords.len()
@@ -0,0 +1,322 @@
+//! Combos, where several ordinary keys pressed at nearly the same time act as a
+//! single different key or command (e.g., `j + k` => `Escape`)
+//!
+//! The first key of a possible combo is held back until it is known whether
+//! the rest of the combo follows. If it does not, the keys that were held back
+//! are replayed
+
+use super::{chord::Chord, remap::HeldKey};
+use crate::config::Action;
+use colored::Colorize;
+use itertools::Itertools;
+use std::{collections::HashSet, time::Duration};
+use x11rb::protocol::xproto::{Keycode, Timestamp};
+
+/// The default number of milliseconds all keys of a combo have to be pressed
+/// within
+pub(crate) const DEFAULT_COMBO_TIMEOUT: u32 = 40;
+
+// ================== ComboAction =================
+
+/// What is done when a combo is completed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ComboAction {
+    /// Keys that are sent in place of the combo
+    Keys(Vec<Chord>),
+    /// A shell command that is ran
+    Command(Action),
+}
+
+// ===================== Combo ====================
+
+/// Several keys that are pressed together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Combo {
+    /// The combo as it was written in the configuration
+    source: String,
+    /// The keys that make up the combo
+    keys:   HashSet<Keycode>,
+    /// What is done when the combo is completed
+    action: ComboAction,
+}
+
+impl Combo {
+    /// Create a new `Combo`
+    pub(crate) fn new(source: &str, keys: HashSet<Keycode>, action: ComboAction) -> Self {
+        Self {
+            source: source.to_string(),
+            keys,
+            action,
+        }
+    }
+
+    /// Return the combo as it was written in the configuration
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Return the keys that make up the combo
+    pub(crate) fn keys(&self) -> &HashSet<Keycode> {
+        &self.keys
+    }
+
+    /// Return what is done when the combo is completed
+    pub(crate) fn action(&self) -> &ComboAction {
+        &self.action
+    }
+}
+
+// ================== ComboOutput =================
+
+/// What should be done with a key event after [`ComboState`] has seen it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ComboOutput {
+    /// The event has nothing to do with a combo
+    Pass,
+    /// The event is held back while waiting on the rest of a combo
+    Wait,
+    /// The event is the release of a combo that already fired, and is dropped
+    Swallow,
+    /// The combo at the given index was completed
+    Fire(usize),
+    /// No combo was completed. The events that were held back are sent again
+    Replay(Vec<HeldKey>),
+}
+
+// ================== ComboState ==================
+
+/// The combos in the configuration file, along with the keys that are being
+/// held back
+#[derive(Debug, Default)]
+pub(crate) struct ComboState {
+    /// Every combo in the configuration file
+    combos:    Vec<Combo>,
+    /// The number of milliseconds all keys of a combo have to be pressed within
+    timeout:   u32,
+    /// The events that are being held back
+    held:      Vec<HeldKey>,
+    /// The time the first held back key was pressed
+    started:   Timestamp,
+    /// Keys of a combo that fired whose releases are dropped
+    swallowed: HashSet<Keycode>,
+}
+
+impl ComboState {
+    /// Create an empty `ComboState`
+    pub(crate) fn new(timeout: u32) -> Self {
+        Self {
+            timeout,
+            ..Self::default()
+        }
+    }
+
+    /// Add a `Combo`
+    pub(crate) fn insert(&mut self, combo: Combo) {
+        self.combos.push(combo);
+    }
+
+    /// Return whether there are any combos
+    pub(crate) fn is_empty(&self) -> bool {
+        self.combos.is_empty()
+    }
+
+    /// Return the `Combo` at `idx`
+    pub(crate) fn get(&self, idx: usize) -> Option<&Combo> {
+        self.combos.get(idx)
+    }
+
+    /// Return every key that is part of a combo. These are the keys that have
+    /// to be grabbed, so that they can be held back
+    pub(crate) fn keys(&self) -> HashSet<Keycode> {
+        self.combos
+            .iter()
+            .flat_map(|c| c.keys.iter().copied())
+            .collect()
+    }
+
+    /// Return the amount of time that is waited for the rest of a combo
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(u64::from(self.timeout))
+    }
+
+    /// Drop the events that are being held back, and stop dropping the
+    /// releases of a combo that fired
+    pub(crate) fn reset(&mut self) {
+        self.held.clear();
+        self.swallowed.clear();
+        self.started = 0;
+    }
+
+    /// Return whether any events are being held back
+    pub(crate) fn is_waiting(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// The keys that are currently held down out of those being held back
+    fn held_down(&self) -> HashSet<Keycode> {
+        self.held
+            .iter()
+            .filter(|h| h.pressed)
+            .map(|h| h.keycode)
+            .collect()
+    }
+
+    /// Return the index of the combo made up of exactly `keys`
+    fn exact(&self, keys: &HashSet<Keycode>) -> Option<usize> {
+        self.combos.iter().position(|c| c.keys == *keys)
+    }
+
+    /// Return whether `keys` could still become a larger combo
+    fn is_partial(&self, keys: &HashSet<Keycode>) -> bool {
+        self.combos
+            .iter()
+            .any(|c| c.keys.len() > keys.len() && keys.is_subset(&c.keys))
+    }
+
+    /// Fire the combo at `idx`, dropping the events that were held back
+    fn fire(&mut self, idx: usize) -> ComboOutput {
+        log::debug!(
+            "{}: completed {}",
+            "combo".red().bold(),
+            self.combos[idx].source.purple().bold()
+        );
+        self.swallowed = self.held_down();
+        self.held.clear();
+        ComboOutput::Fire(idx)
+    }
+
+    /// Give up on a combo, returning the events that were held back
+    fn replay(&mut self) -> ComboOutput {
+        log::debug!(
+            "{}: replaying {}",
+            "combo".red().bold(),
+            self.held.iter().map(|h| h.keycode).join(",")
+        );
+        ComboOutput::Replay(self.held.drain(..).collect())
+    }
+
+    /// Handle a key press
+    pub(crate) fn press(&mut self, key: Keycode, time: Timestamp) -> ComboOutput {
+        if self.held.is_empty() {
+            if self.combos.iter().any(|c| c.keys.contains(&key)) {
+                self.held.push(HeldKey { keycode: key, pressed: true });
+                self.started = time;
+                return ComboOutput::Wait;
+            }
+            return ComboOutput::Pass;
+        }
+
+        self.held.push(HeldKey { keycode: key, pressed: true });
+        if time.wrapping_sub(self.started) > self.timeout {
+            return self.replay();
+        }
+
+        let down = self.held_down();
+        if self.is_partial(&down) {
+            ComboOutput::Wait
+        } else if let Some(idx) = self.exact(&down) {
+            self.fire(idx)
+        } else {
+            self.replay()
+        }
+    }
+
+    /// Handle a key release
+    pub(crate) fn release(&mut self, key: Keycode) -> ComboOutput {
+        if self.swallowed.remove(&key) {
+            return ComboOutput::Swallow;
+        }
+
+        if self.held.is_empty() {
+            return ComboOutput::Pass;
+        }
+
+        // Releasing any key before the combo is completed means it was typed
+        self.held.push(HeldKey { keycode: key, pressed: false });
+        self.replay()
+    }
+
+    /// Handle the combo timeout passing. The keys that are held down are used
+    /// if they make up a combo, even if they could still become a larger one
+    pub(crate) fn expire(&mut self) -> ComboOutput {
+        if self.held.is_empty() {
+            return ComboOutput::Pass;
+        }
+
+        match self.exact(&self.held_down()) {
+            Some(idx) => self.fire(idx),
+            None => self.replay(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> ComboState {
+        let mut state = ComboState::new(40);
+        let action = ComboAction::Command(Action::Shell(String::from("true")));
+        state.insert(Combo::new("j + k", [44, 45].into(), action.clone()));
+        state.insert(Combo::new("j + k + l", [44, 45, 46].into(), action));
+        state
+    }
+
+    #[test]
+    fn combo_fires_within_timeout() {
+        let mut state = ComboState::new(40);
+        state.insert(Combo::new(
+            "j + k",
+            [44, 45].into(),
+            ComboAction::Command(Action::Shell(String::from("true"))),
+        ));
+
+        assert_eq!(state.press(44, 100), ComboOutput::Wait);
+        assert_eq!(state.press(45, 120), ComboOutput::Fire(0));
+        assert_eq!(state.release(44), ComboOutput::Swallow);
+        assert_eq!(state.release(45), ComboOutput::Swallow);
+        assert_eq!(state.release(45), ComboOutput::Pass);
+    }
+
+    #[test]
+    fn combo_replays_when_late_or_typed() {
+        let mut state = state();
+
+        assert_eq!(state.press(44, 100), ComboOutput::Wait);
+        assert_eq!(
+            state.press(45, 200),
+            ComboOutput::Replay(vec![
+                HeldKey { keycode: 44, pressed: true },
+                HeldKey { keycode: 45, pressed: true },
+            ])
+        );
+
+        assert_eq!(state.press(44, 300), ComboOutput::Wait);
+        assert_eq!(
+            state.release(44),
+            ComboOutput::Replay(vec![
+                HeldKey { keycode: 44, pressed: true },
+                HeldKey { keycode: 44, pressed: false },
+            ])
+        );
+
+        assert_eq!(state.press(30, 400), ComboOutput::Pass);
+    }
+
+    #[test]
+    fn combo_prefers_larger_combo() {
+        let mut state = state();
+
+        assert_eq!(state.press(44, 100), ComboOutput::Wait);
+        assert_eq!(state.press(45, 110), ComboOutput::Wait);
+        assert_eq!(state.press(46, 120), ComboOutput::Fire(1));
+
+        assert_eq!(state.release(44), ComboOutput::Swallow);
+        assert_eq!(state.release(45), ComboOutput::Swallow);
+        assert_eq!(state.release(46), ComboOutput::Swallow);
+
+        assert_eq!(state.press(44, 200), ComboOutput::Wait);
+        assert_eq!(state.press(45, 210), ComboOutput::Wait);
+        assert_eq!(state.expire(), ComboOutput::Fire(0));
+    }
+}
@@ -0,0 +1,121 @@
+//! Detection of bindings that can never run, either because another binding
+//! has the same keys or because another binding's keys start theirs
+
+use super::trie::Trie;
+use std::hash::Hash;
+
+// =================== Conflict ===================
+
+/// A problem between two bindings, each identified by where it came from `S`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Conflict<S> {
+    /// Both have the same keys, and only the `first` one is used. Both are the
+    /// same binding when its groups expand to the same keys more than once
+    Duplicate {
+        /// The binding that is used
+        first:  S,
+        /// The binding that is ignored
+        second: S,
+    },
+    /// The keys of `prefix` are the start of the keys of `shadowed`, which is
+    /// never reached since `prefix` is matched first
+    Shadowed {
+        /// The binding with the shorter keys
+        prefix:   S,
+        /// The binding that is never reached
+        shadowed: S,
+    },
+}
+
+/// Find the conflicts between `bindings`, which are in the order they were
+/// written. Each pair of bindings is only reported once for each kind of
+/// conflict, no matter how many of their groups' keys conflict
+pub(crate) fn find_conflicts<K, S>(bindings: &[(S, Vec<K>)]) -> Vec<Conflict<S>>
+where
+    K: Eq + Hash + Clone,
+    S: Clone + PartialEq,
+{
+    let mut trie = Trie::new();
+    let mut conflicts = Vec::new();
+    let mut push = |conflict| {
+        if !conflicts.contains(&conflict) {
+            conflicts.push(conflict);
+        }
+    };
+
+    for (source, keys) in bindings {
+        if let Some(first) = trie.get(keys) {
+            push(Conflict::Duplicate {
+                first:  S::clone(first),
+                second: source.clone(),
+            });
+        } else {
+            trie.insert(keys.iter().cloned(), source.clone());
+        }
+    }
+
+    for (source, keys) in bindings {
+        // A duplicate was never inserted, so it cannot be reached regardless
+        if trie.get(keys) != Some(source) {
+            continue;
+        }
+
+        for end in 1..keys.len() {
+            if let Some(prefix) = trie.get(&keys[..end]) {
+                push(Conflict::Shadowed {
+                    prefix:   prefix.clone(),
+                    shadowed: source.clone(),
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_duplicates_and_shadows() {
+        let bindings = [
+            ("{a-c}", "a"),
+            ("{a-c}", "b"),
+            ("{a-c}", "c"),
+            ("b", "b"),
+            ("{x,x}", "x"),
+            ("{x,x}", "x"),
+            ("t", "t"),
+            ("t ; {u,v}", "tu"),
+            ("t ; {u,v}", "tv"),
+            ("tu ; w", "tuw"),
+        ]
+        .into_iter()
+        .map(|(source, keys)| (source, keys.chars().collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+
+        assert_eq!(find_conflicts(&bindings), vec![
+            Conflict::Duplicate {
+                first:  "{a-c}",
+                second: "b",
+            },
+            Conflict::Duplicate {
+                first:  "{x,x}",
+                second: "{x,x}",
+            },
+            Conflict::Shadowed {
+                prefix:   "t",
+                shadowed: "t ; {u,v}",
+            },
+            Conflict::Shadowed {
+                prefix:   "t",
+                shadowed: "tu ; w",
+            },
+            Conflict::Shadowed {
+                prefix:   "t ; {u,v}",
+                shadowed: "tu ; w",
+            },
+        ]);
+    }
+}
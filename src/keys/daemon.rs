@@ -3,38 +3,58 @@
 // TODO: Allow the user to add keybindings while the daemon is running
 
 use super::{
+    binding::{Binding, BindingState},
     chord::{Chain, ChainLink, Chord},
+    combo::{Combo, ComboAction, ComboOutput, ComboState, DEFAULT_COMBO_TIMEOUT},
+    conflict::{self, Conflict},
+    device::{Devices, RawKey},
     event_handler::Handler,
+    executor::{Executor, Job},
     keyboard::Keyboard,
-    keys::{CharacterMap, ModifierMask},
-    remap::{RemapKeyState, RemapState},
+    leader::{Leader, LeaderKey, LeaderOutput, DEFAULT_LEADER_TIMEOUT},
+    keys::{CharacterMap, ModifierMask, Sides},
+    remap::{
+        HeldKey,
+        OneShotKey,
+        RemapKeyState,
+        RemapState,
+        TapHoldKey,
+        RemapOutput,
+        DEFAULT_TAPPING_TERM,
+    },
+    record,
+    timer::{Timer, Timers},
+    trie::{Lookup, Trie},
 };
 use crate::{
-    config::{Action, Config, SHELL},
+    config::{Action, ComboValue, Config, DeviceValue, RemapValue, SHELL},
     keys::keysym::XKeysym,
     lxhkd_fatal,
-    parse::parser::{Line, TokenizedLine},
+    parse::parser::{self, Error as ParseError},
 };
 use anyhow::{Context, Result};
 use colored::Colorize;
 use crossbeam_channel as channel;
 use indexmap::IndexMap;
 use itertools::Itertools;
+use nix::sys::signal::{SigSet, Signal};
 use std::{
-    collections::BTreeMap,
-    convert::TryFrom,
+    collections::{HashMap, HashSet, VecDeque},
+    env,
     fmt,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
 };
 use x11rb::{
     connection::Connection,
     cookie::RecordEnableContextCookie,
     protocol::{
-        record::{self, ConnectionExt as _, EnableContextReply},
-        xproto::{self, Timestamp},
+        xinput::{self, DeviceId},
+        xproto::{self, KeyPressEvent, Keycode, Timestamp},
         Event,
     },
-    x11_utils::TryParse,
 };
 
 // TODO: Add layers/modes
@@ -46,70 +66,284 @@ use x11rb::{
 pub(crate) struct Daemon {
     /// The current keyboard setup
     keyboard:      Arc<Keyboard>,
+    /// The thread that actions and fake key events are sent to
+    executor:      Executor,
     /// Configuration file of the user
     config:        Config,
-    /// The parsed bindings registered in all modes
-    bindings:      BTreeMap<Chain, Action>,
+    /// The parsed bindings registered in all modes, indexed by their `Chord`s
+    bindings:      Trie<Chord, Binding>,
+    /// The keyboards that bindings are limited to
+    bound_devices: Vec<DeviceValue>,
+    /// The position of `toggle` and `cycle` bindings
+    binding_state: BindingState,
     /// The parsed remaps in the configuration file
     remaps:        RemapState,
+    /// The parsed combos in the configuration file
+    combos:        ComboState,
+    /// The leader binding and the sequences that follow it
+    leader:        Option<Leader>,
     /// Current chain being pressed
     active_chain:  Chain,
     /// Tracker of last keypress
     last_keypress: Timestamp,
+    /// Long-press bindings that are currently held down, keyed by `Keycode`
+    holds:         HashMap<Keycode, PendingHold>,
+    /// The multi-tap binding that is waiting to see if it is tapped again
+    pending_taps:  Option<PendingTaps>,
+    /// Deadlines to wake up for while waiting on the X-Server
+    timers:        Timers,
     /// List of keys generated by this program
     generated:     Vec<CharacterMap>,
+    /// The keys that the user is holding down, leaving out the ones sent by
+    /// this program
+    pressed:       HashSet<Keycode>,
+    /// The modifier keys that are held down, along with the side of the
+    /// keyboard they are on
+    modifiers:     HashMap<Keycode, Sides>,
+    /// The keyboards that are plugged in, and the ones keys are pressed on
+    devices:       Devices,
+    /// Whether the raw key events that tell keyboards apart are received
+    device_events: bool,
+    /// The key events waiting for the raw event that says which keyboard they
+    /// came from, in the order they were received
+    deferred:      VecDeque<KeyPressEvent>,
+    /// The keys that give back every grab, checked before anything else
+    escape:        Option<Chord>,
+    /// Whether the daemon should stop once the current event is handled
+    stopping:      bool,
 }
 // /// Max allowed time between keypresses
 // keypress_timeout: u32,
 
+/// How long a release of a long-press binding is held onto before it is
+/// resolved. Autorepeat sends a release immediately followed by a press
+const REPEAT_GRACE: Duration = Duration::from_millis(10);
+
+/// How long the user has to not be pressing any key before the keys that are
+/// still down are released, since they can only have been sent by this program
+const STUCK_GRACE: Duration = Duration::from_secs(1);
+
+/// How long to wait for the rest of the events of a keymap change before the
+/// keymap is read again
+const KEYMAP_GRACE: Duration = Duration::from_millis(100);
+
+/// How long a key event waits for its raw event before it is handled without
+/// it. The two are read from different connections, so either can come first
+const SOURCE_GRACE: Duration = Duration::from_millis(20);
+
+/// The keys that give back every grab when `escape` is not in the
+/// configuration file
+const DEFAULT_ESCAPE: &str = "ctrl + alt + shift + Escape";
+
+// ================= PendingHold ==================
+
+/// A long-press binding that is currently held down
+#[derive(Debug, Clone)]
+pub(crate) struct PendingHold {
+    /// The `Chain` of the binding that was matched
+    chain:    Chain,
+    /// The time the key was pressed
+    pressed:  Timestamp,
+    /// The time the key was released, while it is unknown whether the release
+    /// was from autorepeat
+    released: Option<Timestamp>,
+    /// Whether the `hold` action has already been ran
+    fired:    bool,
+}
+
+// ================= PendingTaps ==================
+
+/// A multi-tap binding that has been tapped, but may still be tapped again
+#[derive(Debug, Clone)]
+pub(crate) struct PendingTaps {
+    /// The `Chain` of the binding that was matched
+    chain:    Chain,
+    /// The key that is being tapped
+    keycode:  Keycode,
+    /// The number of taps seen so far
+    count:    u8,
+    /// The time of the most recent tap
+    last:     Timestamp,
+    /// The time the key was last released, or `None` if it is still down
+    released: Option<Timestamp>,
+}
+
 impl Daemon {
     /// Create a new `Daemon`
     pub(crate) fn new(keyboard: Keyboard, config: Config) -> Self {
         // keypress_timeout: config.global.timeout.unwrap_or(300),
+        let state_file = config.global.state_file.as_ref().map_or_else(
+            || {
+                dirs::cache_dir()
+                    .unwrap_or_else(env::temp_dir)
+                    .join("lxhkd")
+                    .join("state.yml")
+            },
+            |file| {
+                PathBuf::from(
+                    shellexpand::full(&file.display().to_string())
+                        .map_or_else(|_| file.display().to_string(), |f| f.to_string()),
+                )
+            },
+        );
+
+        let keyboard = Arc::new(keyboard);
+        let executor = Executor::new(
+            Arc::clone(&keyboard),
+            config.global.shell.as_ref().unwrap_or(&SHELL),
+        );
+
         Self {
-            keyboard: Arc::new(keyboard),
+            keyboard,
+            executor,
             config,
-            bindings: BTreeMap::new(),
+            bindings: Trie::new(),
+            bound_devices: Vec::new(),
+            binding_state: BindingState::load(state_file),
             remaps: RemapState::new(),
+            combos: ComboState::new(DEFAULT_COMBO_TIMEOUT),
+            leader: None,
             active_chain: Chain::default(),
             last_keypress: 0,
+            holds: HashMap::new(),
+            pending_taps: None,
+            timers: Timers::new(),
             generated: Vec::new(),
+            pressed: HashSet::new(),
+            modifiers: HashMap::new(),
+            devices: Devices::new(),
+            device_events: false,
+            deferred: VecDeque::new(),
+            escape: None,
+            stopping: false,
         }
     }
 
     // TODO: These functions are so similar, condense them
-    // TODO: If binding contains an unknown, then confirm it is skipped
 
-    /// Parse the configuration bindings
-    pub(crate) fn process_bindings(&mut self) -> Result<()> {
-        let mut parsed_bindings = BTreeMap::new();
+    /// Log every problem found with `text`, which is either the item `key` of
+    /// `section` in the configuration file or one of its values
+    fn report(&self, section: &str, key: &str, text: &str, errors: &[ParseError]) {
+        for e in errors {
+            log::error!(
+                "<{}> - {}",
+                key.purple().bold(),
+                e.render(self.config.contents.as_deref(), section, key, text)
+            );
+        }
+    }
+
+    /// Parse `text`, which is either the item `key` of `section` or one of its
+    /// values, into a single `Chain`
+    fn parse_chain(&self, section: &str, key: &str, text: &str) -> Option<Chain> {
+        parser::parse_chain(self.keyboard.charmap(), text)
+            .map_err(|errors| self.report(section, key, text, &errors))
+            .ok()
+    }
+
+    /// Parse a single key (e.g., `super` or `a`) into a `Chord`. See
+    /// [`Daemon::parse_chain`]
+    fn parse_key(&self, section: &str, key: &str, text: &str) -> Option<Chord> {
+        self.parse_chain(section, key, text)
+            .and_then(|chain| chain.chords().first().cloned())
+    }
+
+    /// Parse a single key (e.g., `super` or `a`) into its `Keycode`. See
+    /// [`Daemon::parse_chain`]
+    fn parse_keycode(&self, section: &str, key: &str, text: &str) -> Option<Keycode> {
+        self.parse_key(section, key, text).map(|c| c.charmap().code())
+    }
+
+    /// Name the item `key` of `section`, along with its line in the
+    /// configuration file if it can be found
+    fn describe(&self, section: &str, key: &str) -> String {
+        let name = format!("<{}>", key.purple().bold());
+        match self
+            .config
+            .contents
+            .as_deref()
+            .and_then(|contents| parser::locate(contents, section, key, key))
+        {
+            Some((line, _)) => format!("{name} on line {}", line.to_string().yellow().bold()),
+            None => name,
+        }
+    }
+
+    /// Warn about the bindings that can never run. See
+    /// [`conflict::find_conflicts`]
+    fn report_conflicts(&self, chains: &[(&str, Vec<Chord>)]) {
+        for found in conflict::find_conflicts(chains) {
+            match found {
+                Conflict::Duplicate { first, second } if first == second => log::warn!(
+                    "{} - its groups expand to the same keys more than once",
+                    self.describe("bindings", first)
+                ),
+                Conflict::Duplicate { first, second } => log::warn!(
+                    "{} - same keys as {}, which is the one that is used",
+                    self.describe("bindings", second),
+                    self.describe("bindings", first)
+                ),
+                Conflict::Shadowed { prefix, shadowed } => log::warn!(
+                    "{} - never reached, since {} is matched first",
+                    self.describe("bindings", shadowed),
+                    self.describe("bindings", prefix)
+                ),
+            }
+        }
+    }
+
+    /// Parse the configuration bindings. A binding with groups (e.g., `super +
+    /// {a-c}`) is bound to every key they expand to. When bindings have the
+    /// same keys, the first one is used. Bindings limited to different
+    /// keyboards never have the same keys
+    pub(crate) fn process_bindings(&mut self) {
+        let mut parsed_bindings = Trie::new();
+        let mut chains = Vec::new();
+        let mut bound_devices = Vec::new();
 
         if let Some(bindings) = &self.config.bindings {
-            for (mut idx, l) in bindings.keys().enumerate() {
-                idx += 1;
+            for (l, value) in bindings {
+                let all_groups = self.config.global.all_groups;
+                let parsed = match parser::parse_chains(self.keyboard.charmap(), l, all_groups) {
+                    Ok(parsed) => parsed,
+                    Err(errors) => {
+                        self.report("bindings", l, l, &errors);
+                        continue;
+                    },
+                };
 
-                let line = Line::new_plus(l, idx);
-                let mut tokenized = line.tokenize();
-                tokenized.parse_tokens()?;
+                if let Some(device) = value.device() {
+                    if !bound_devices.contains(device) {
+                        bound_devices.push(device.clone());
+                    }
+                }
 
-                if let Some(mut chain) = tokenized.convert_to_chain(self.keyboard.charmap(), false)
-                {
-                    let action = bindings
-                        .get_index(idx - 1)
-                        .context(
-                            "failed to get valid index of item in configuration's `Bindings` \
-                             section",
-                        )?
-                        .1;
-
-                    let cmd = Action::Shell(action.to_string());
-                    parsed_bindings.insert(chain, cmd);
+                // Each of the keys the groups expand to keeps its own position
+                let expands = parsed.len() > 1;
+                match Binding::from_value(l, value) {
+                    Ok(binding) =>
+                        for mut chain in parsed {
+                            chain.update_device(value.device());
+                            if parsed_bindings.get(chain.chords()).is_none() {
+                                let binding = if expands {
+                                    binding.expanded(&chain)
+                                } else {
+                                    binding.clone()
+                                };
+                                parsed_bindings.insert(chain.chords().clone(), binding);
+                            }
+                            chains.push((l.as_str(), chain.chords().clone()));
+                        },
+                    Err(e) => log::error!("<{}> - invalid binding: {}", l.purple().bold(), e),
                 }
             }
         }
-        self.bindings = parsed_bindings;
+        self.report_conflicts(&chains);
 
-        Ok(())
+        self.binding_state
+            .retain_persisted(parsed_bindings.values().into_iter());
+        self.bindings = parsed_bindings;
+        self.bound_devices = bound_devices;
     }
 
     /// Parse the configuration `remaps`
@@ -119,64 +353,216 @@ impl Daemon {
         let mut parsed_remaps = RemapState::new();
 
         if let Some(remaps) = &self.config.remaps {
-            for (mut idx, l) in remaps.keys().enumerate() {
-                idx += 1;
+            for (l, action_to) in remaps {
+                let Some(mut chain_from) = self.parse_chain("remaps", l, l) else {
+                    continue;
+                };
+                chain_from.update_device(action_to.device());
 
-                let line = Line::new_plus(l, idx);
-                let mut tokenized = line.tokenize();
-                tokenized.parse_tokens()?;
+                let action_to = match action_to {
+                    RemapValue::Keys(to) => to,
+                    RemapValue::Device(opts) => &opts.keys,
+                    RemapValue::OneShot(opts) => {
+                        let key = chain_from.chords().first().map(|c| c.charmap().code());
+                        let modifier = self.parse_keycode("remaps", l, &opts.one_shot);
 
-                if let Some(mut chain_from) =
-                    tokenized.convert_to_chain(self.keyboard.charmap(), false)
-                {
-                    let action_to = remaps
-                        .get_index(idx - 1)
-                        .context(
-                            "failed to get valid index of item in configuration's `Remaps` section",
-                        )?
-                        .1;
-
-                    log::trace!("{}:action: {}", "remaps".red().bold(), action_to);
-
-                    let line = Line::new_plus(action_to, idx);
-                    let mut tokenized_to = line.tokenize();
-                    tokenized_to.parse_tokens()?;
-                    if let Some(chain_to) =
-                        tokenized_to.convert_to_chain(self.keyboard.charmap(), false)
-                    {
-                        parsed_remaps.insert(
-                            RemapKeyState::from_chains(&chain_from, &chain_to)
-                                .context("failed to insert chains into `RemapKeyState`")?,
-                        );
-                    }
+                        if let (Some(key), Some(modifier)) = (key, modifier) {
+                            parsed_remaps
+                                .one_shots_mut()
+                                .insert(OneShotKey::new(l, key, modifier));
+                        } else {
+                            log::error!("<{}> - invalid one-shot key", l.purple().bold());
+                        }
+                        continue;
+                    },
+                    RemapValue::TapHold(opts) => {
+                        let key = chain_from.chords().first().map(|c| c.charmap().code());
+                        let tap = match &opts.tap {
+                            Some(tap) => self.parse_keycode("remaps", l, tap),
+                            None => key,
+                        };
+                        let hold = self.parse_keycode("remaps", l, &opts.hold);
+
+                        if let (Some(key), Some(tap), Some(hold)) = (key, tap, hold) {
+                            parsed_remaps.tap_holds_mut().insert(TapHoldKey::new(
+                                l,
+                                key,
+                                tap,
+                                hold,
+                                opts.strategy,
+                                opts.tapping_term.unwrap_or(DEFAULT_TAPPING_TERM),
+                                opts.quick_tap,
+                            ));
+                        } else {
+                            log::error!("<{}> - invalid tap-hold key", l.purple().bold());
+                        }
+                        continue;
+                    },
+                };
+
+                log::trace!("{}:action: {}", "remaps".red().bold(), action_to);
+
+                if let Some(chain_to) = self.parse_chain("remaps", l, action_to) {
+                    parsed_remaps.insert(
+                        RemapKeyState::from_chains(&chain_from, &chain_to)
+                            .context("failed to insert chains into `RemapKeyState`")?,
+                    );
                 }
             }
         }
 
-        // println!("REMAPS: {:#?}", parsed_remaps);
-        // std::process::exit(1);
-
         self.remaps = parsed_remaps;
 
         Ok(())
     }
 
+    /// Parse the configuration `leader` and `leader_bindings`
+    pub(crate) fn process_leader(&mut self) {
+        let mut parsed_leader = None;
+
+        if let Some(leader) = &self.config.global.leader {
+            if let Some(chain) = self.parse_chain("global", "leader", leader) {
+                let mut parsed = Leader::new(
+                    chain,
+                    self.config
+                        .global
+                        .leader_timeout
+                        .unwrap_or(DEFAULT_LEADER_TIMEOUT),
+                );
+
+                for (l, cmd) in self.config.leader_bindings.iter().flatten() {
+                    let keys = parser::split_keys(l)
+                        .map(|(offset, key)| {
+                            match parser::parse_chain(self.keyboard.charmap(), key) {
+                                Ok(chain) => chain
+                                    .chords()
+                                    .first()
+                                    .map(|c| (c.charmap().code(), c.modmask().mask())),
+                                Err(errors) => {
+                                    let errors = errors
+                                        .into_iter()
+                                        .map(|e| e.shift(offset))
+                                        .collect::<Vec<_>>();
+                                    self.report("leader_bindings", l, l, &errors);
+                                    None
+                                },
+                            }
+                        })
+                        .collect::<Option<Vec<LeaderKey>>>();
+
+                    match keys {
+                        Some(keys) if !keys.is_empty() =>
+                            if !parsed.insert(l, keys, Action::Shell(cmd.clone())) {
+                                log::warn!(
+                                    "<{}> - duplicate leader sequence",
+                                    l.purple().bold()
+                                );
+                            },
+                        _ => log::error!("<{}> - invalid leader sequence", l.purple().bold()),
+                    }
+                }
+
+                parsed_leader = Some(parsed);
+            } else {
+                log::error!("<{}> - invalid leader", leader.purple().bold());
+            }
+        } else if self.config.leader_bindings.is_some() {
+            log::warn!("`leader_bindings` do nothing without a `leader`");
+        }
+
+        self.leader = parsed_leader;
+    }
+
+    /// Parse the configuration `escape`, falling back to [`DEFAULT_ESCAPE`]
+    pub(crate) fn process_escape(&mut self) {
+        let escape = self
+            .config
+            .global
+            .escape
+            .as_deref()
+            .unwrap_or(DEFAULT_ESCAPE);
+
+        self.escape = if escape.eq_ignore_ascii_case("none") {
+            log::warn!("the {} keys are disabled", "escape".red().bold());
+            None
+        } else {
+            let chord = self.parse_key("global", "escape", escape);
+            if chord.is_none() {
+                log::error!("<{}> - invalid escape", escape.purple().bold());
+            }
+            chord
+        };
+    }
+
+    /// Parse the configuration `combos`
+    pub(crate) fn process_combos(&mut self) {
+        let mut parsed_combos =
+            ComboState::new(self.config.global.combo_timeout.unwrap_or(DEFAULT_COMBO_TIMEOUT));
+
+        if let Some(combos) = &self.config.combos {
+            for (l, value) in combos {
+                let Some(chain) = self.parse_chain("combos", l, l) else {
+                    continue;
+                };
+
+                let keys = chain
+                    .chords()
+                    .iter()
+                    .map(|c| c.charmap().code())
+                    .collect::<HashSet<_>>();
+
+                if keys.len() < 2 || chain.modmask() != 0 {
+                    log::error!(
+                        "<{}> - a combo needs at least two keys and no modifiers",
+                        l.purple().bold()
+                    );
+                    continue;
+                }
+
+                let action = match value {
+                    ComboValue::Keys(to) => match self.parse_chain("combos", l, to) {
+                        Some(chain_to) => ComboAction::Keys(chain_to.chords().clone()),
+                        None => continue,
+                    },
+                    ComboValue::Command { command } =>
+                        ComboAction::Command(Action::Shell(command.clone())),
+                };
+
+                parsed_combos.insert(Combo::new(l, keys, action));
+            }
+        }
+
+        self.combos = parsed_combos;
+    }
+
     /// Combination of the above three functions to parse and process the
     /// configuration file's bindings into the `Daemon` struct
     pub(crate) fn process_configuration(&mut self) -> Result<()> {
         /// The `bindings` section of the configuration file
         /// Remaps keys to shell commands
-        self.process_bindings()?;
+        self.process_bindings();
 
         /// The `remaps` section of the configuration file
         /// Remaps keys to other keys
         self.process_remaps()?;
 
+        /// The `combos` section of the configuration file
+        /// Maps keys pressed together to other keys or shell commands
+        self.process_combos();
+
+        /// The `leader` and `leader_bindings` of the configuration file
+        /// Maps sequences typed after the leader to shell commands
+        self.process_leader();
+
+        /// The `escape` of the configuration file
+        /// Gives back every grab in case the keyboard ends up stuck
+        self.process_escape();
+
         Ok(())
     }
 
     /// Parse the `Chords` generated from actions happening while the `Daemon`
-    /// is running
+    /// is running. `device` is the keyboard the key was pressed on, if known
     #[allow(clippy::unnecessary_wraps)]
     pub(crate) fn process_chords(
         &mut self,
@@ -184,118 +570,860 @@ impl Daemon {
         time: Timestamp,
         response_type: u8,
         window: xproto::Window,
+        device: Option<DeviceId>,
     ) -> Result<()> {
+        let keycode = chord.charmap().code();
+
+        // A long-press binding is being held down. Its release is resolved once it
+        // is known not to be from autorepeat
+        if let Some(pending) = self.holds.get_mut(&keycode) {
+            match response_type {
+                xproto::KEY_PRESS_EVENT => {
+                    if pending.released.take().is_some() {
+                        self.timers.cancel(Timer::Release(keycode));
+                    }
+                    self.last_keypress = time;
+                    return Ok(());
+                },
+                xproto::KEY_RELEASE_EVENT => {
+                    pending.released = Some(time);
+                    self.timers.schedule(REPEAT_GRACE, Timer::Release(keycode));
+                    return Ok(());
+                },
+                _ => {},
+            }
+        }
+
+        // A multi-tap binding is waiting on another tap. Any other key being pressed
+        // ends the wait, unless the tapped key is still down and being used as a
+        // modifier, in which case the taps are thrown away
+        if let Some(pending) = &mut self.pending_taps {
+            if pending.keycode == keycode {
+                if response_type == xproto::KEY_RELEASE_EVENT {
+                    pending.released = Some(time);
+                    return Ok(());
+                }
+            } else if response_type == xproto::KEY_PRESS_EVENT {
+                if pending.released.is_some() {
+                    self.resolve_taps();
+                } else {
+                    self.timers.cancel(Timer::Taps(pending.keycode));
+                    self.pending_taps = None;
+                }
+            }
+        }
+
         if self.last_keypress + self.config.global.timeout.unwrap_or(300) < time {
             self.active_chain.clear();
         }
 
         // println!("ACTIVE CHORD: {:#?}", chord);
+        let chord = self.narrow(chord, device);
         self.active_chain.push(chord);
-        let mut should_clear = true;
 
-        for (chain, action) in &self.bindings {
-            match self.active_chain.matches(chain) {
-                ChainLink::None => {},
-                ChainLink::Partial => {
-                    log::info!("partially matched binding: {:#?}", action);
-                    log::info!("partially matched chain: {:#?}", self.active_chain.chords());
+        // The leader starts a sequence of plain keys instead of running anything
+        let is_leader = self.leader.as_ref().is_some_and(|leader| {
+            matches!(self.active_chain.matches(leader.chain()), ChainLink::Full)
+        });
+        if is_leader && response_type == xproto::KEY_PRESS_EVENT {
+            self.active_chain.clear();
+            self.start_leader();
+            self.last_keypress = time;
+            return Ok(());
+        }
+
+        // Only the bindings starting with the active chain are ever looked at
+        let mut matched = None;
+        let should_clear = match self.bindings.lookup(self.active_chain.chords()) {
+            Lookup::None => true,
+            Lookup::Prefix => {
+                log::info!("partially matched chain: {}", self.active_chain);
+                false
+            },
+            // FIX: Differentiate between key press and release
+            // For some reason, when a key is held, it registers a release event after a certain
+            // amount of time, and not when the key is actually released
+            Lookup::Match(binding) | Lookup::Ambiguous(binding) => {
+                log::info!("matched binding: {}", binding.source());
+                log::info!("matched chain: {}", self.active_chain);
+
+                matched = Some(self.active_chain.clone());
+                true
+            },
+        };
+
+        if should_clear {
+            self.active_chain.clear();
+        }
+
+        if let Some(chain) = matched {
+            self.run_matched(chain, keycode, time, response_type);
+        }
+
+        self.last_keypress = time;
+
+        Ok(())
+    }
+
+    /// Run the binding of a `Chain` that was fully matched. Long-press and
+    /// multi-tap bindings are deferred until it is known how they were pressed
+    fn run_matched(
+        &mut self,
+        chain: Chain,
+        keycode: Keycode,
+        time: Timestamp,
+        response_type: u8,
+    ) {
+        if let Some(binding) = self.bindings.get(chain.chords()) {
+            if let Some(hold) = binding.hold() {
+                if response_type == xproto::KEY_PRESS_EVENT {
+                    if hold.fire_on_hold() {
+                        self.timers.schedule(hold.duration(), Timer::Hold(keycode));
+                    }
+
+                    self.holds.insert(keycode, PendingHold {
+                        chain,
+                        pressed: time,
+                        released: None,
+                        fired: false,
+                    });
+                }
+            } else if let Some(taps) = binding.taps() {
+                if response_type == xproto::KEY_PRESS_EVENT {
+                    let (window, max, duration) = (taps.window(), taps.max(), taps.duration());
+                    self.tap(chain, keycode, time, window, max, duration);
+                }
+            } else {
+                binding.run(&mut self.binding_state, &self.executor);
+            }
+        }
+    }
+
+    /// Count a press of a multi-tap binding. The binding is ran once it has
+    /// been tapped the most times it can be, or once its `tap_window` passes
+    fn tap(
+        &mut self,
+        chain: Chain,
+        keycode: Keycode,
+        time: Timestamp,
+        window: u32,
+        max: u8,
+        duration: Duration,
+    ) {
+        if let Some(pending) = &mut self.pending_taps {
+            if pending.chain == chain {
+                match pending.released {
+                    // Autorepeat sends a release and a press at the same time
+                    Some(released) if released == time => {
+                        pending.released = None;
+                        return;
+                    },
+                    Some(_) if time.wrapping_sub(pending.last) <= window => {
+                        pending.count += 1;
+                        pending.last = time;
+                        pending.released = None;
+
+                        if pending.count >= max {
+                            self.resolve_taps();
+                        } else {
+                            self.timers.schedule(duration, Timer::Taps(keycode));
+                        }
+                        return;
+                    },
+                    None => return,
+                    Some(_) => {},
+                }
+            }
+        }
+
+        self.resolve_taps();
+        self.pending_taps = Some(PendingTaps {
+            chain,
+            keycode,
+            count: 1,
+            last: time,
+            released: None,
+        });
+        self.timers.schedule(duration, Timer::Taps(keycode));
+    }
+
+    /// Run the multi-tap binding that is waiting on another tap, using the
+    /// number of times it has been tapped so far
+    fn resolve_taps(&mut self) {
+        if let Some(pending) = self.pending_taps.take() {
+            self.timers.cancel(Timer::Taps(pending.keycode));
+
+            if let Some(binding) = self.bindings.get(pending.chain.chords()) {
+                binding.run_taps(pending.count, &mut self.binding_state, &self.executor);
+            }
+        }
+    }
 
-                    should_clear = false;
+    /// Run the `Timer`s that have expired
+    pub(crate) fn process_timers(&mut self) {
+        for timer in self.timers.expired(Instant::now()) {
+            match timer {
+                Timer::Hold(keycode) =>
+                    if let Some(pending) = self.holds.get_mut(&keycode) {
+                        if let Some((binding, hold)) = self
+                            .bindings
+                            .get(pending.chain.chords())
+                            .and_then(|b| b.hold().map(|h| (b, h)))
+                        {
+                            hold.run(binding, hold.time(), &self.executor);
+                            pending.fired = true;
+                        }
+                    },
+                Timer::Release(keycode) => {
+                    self.timers.cancel(Timer::Hold(keycode));
+
+                    if let Some(pending) = self.holds.remove(&keycode) {
+                        if pending.fired {
+                            continue;
+                        }
+
+                        let held = pending
+                            .released
+                            .unwrap_or(pending.pressed)
+                            .wrapping_sub(pending.pressed);
+
+                        if let Some(binding) = self.bindings.get(pending.chain.chords()) {
+                            binding.run_for(held, &mut self.binding_state, &self.executor);
+                        }
+                    }
                 },
-                // FIX: Differentiate between key press and release
-                // For some reason, when a key is held, it registers a release event after a certain
-                // amount of time, and not when the key is actually released
-                ChainLink::Full => {
-                    // match (chain.is_release(), response_type) {
-                    // (true, xproto::KEY_RELEASE_EVENT) | (false, xproto::KEY_PRESS_EVENT) => {
-                    log::info!("matched binding: {:?}", action);
-                    log::info!(
-                        "matched utf-code {:#?}",
-                        self.active_chain
-                            .chords()
-                            .iter()
-                            .map(|ch| format!("({}-{})", ch.charmap().utf(), ch.charmap().code(),))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    );
+                Timer::TapHold => {
+                    let output = self.remaps.tap_holds_mut().expire();
+                    self.handle_remap(output, Timer::TapHoldRelease);
+                },
+                Timer::TapHoldRelease => {
+                    let output = self.remaps.tap_holds_mut().finish();
+                    self.handle_remap(output, Timer::TapHoldRelease);
+                },
+                Timer::OneShotRelease => {
+                    let output = self.remaps.one_shots_mut().finish();
+                    self.handle_remap(output, Timer::OneShotRelease);
+                },
+                Timer::Leader =>
+                    if let Some(leader) = &mut self.leader {
+                        let output = leader.expire();
+                        self.handle_leader(output);
+                    },
+                Timer::Combo => {
+                    let output = self.combos.expire();
+                    self.handle_combo(output);
+                },
+                Timer::Taps(_) => {
+                    if let Some(pending) = self.pending_taps.take() {
+                        if let Some(binding) = self.bindings.get(pending.chain.chords()) {
+                            let state = &mut self.binding_state;
+                            binding.run_taps(pending.count, state, &self.executor);
+                        }
+                    }
+                },
+                Timer::Stuck =>
+                    if self.pressed.is_empty()
+                        && !self.remaps.is_active()
+                        && !self.combos.is_waiting()
+                    {
+                        self.executor.send(Job::ReleaseStuck);
+                    },
+                Timer::Keymap => self.reload_keymap(),
+                Timer::Source =>
+                    if let Err(e) = self.replay_deferred(true) {
+                        log::error!("{}: {}", "daemon".red().bold(), e);
+                    },
+            }
+        }
+    }
+
+    /// Grab the keyboard and start waiting for a leader sequence
+    fn start_leader(&mut self) {
+        if let Some(leader) = &mut self.leader {
+            log::info!("{}: waiting for a sequence", "leader".red().bold());
 
-                    action.run(&self.config.global.shell);
+            leader.start();
+            self.timers.schedule(leader.duration(), Timer::Leader);
+            self.executor.send(Job::GrabKeyboard);
+        }
+    }
 
-                    should_clear = true;
-                    break;
+    /// Pass a key press to the active leader. Modifier keys are skipped, and
+    /// the modifiers of the leader binding itself are ignored, since they are
+    /// often still held
+    fn leader_press(&mut self, key: Keycode, state: u16) {
+        let is_modifier = self
+            .keyboard
+            .charmap_from_keycode(key)
+            .is_some_and(|charmap| charmap.modmask() != 0);
+
+        if let (false, Some(leader)) = (is_modifier, &mut self.leader) {
+            let mut mask = ModifierMask::new(state);
+            mask.filter_ignored();
+            mask.ignore(leader.chain().modmask());
+
+            let output = leader.press((key, mask.mask()));
+            self.handle_leader(output);
+        }
+    }
+
+    /// Act on what the [`Leader`] decided to do with a key
+    fn handle_leader(&mut self, output: LeaderOutput) {
+        match output {
+            LeaderOutput::Pass => {},
+            LeaderOutput::Wait =>
+                if let Some(leader) = &self.leader {
+                    self.timers.schedule(leader.duration(), Timer::Leader);
                 },
-                //     _ => {},
-                // }
-                // },
+            LeaderOutput::Run(action) => {
+                self.stop_leader();
+                action.run(&self.executor);
+            },
+            LeaderOutput::Abort => {
+                log::info!("{}: no sequence was matched", "leader".red().bold());
+                self.stop_leader();
+            },
+        }
+    }
+
+    /// Stop waiting for a leader sequence and give the keyboard back
+    fn stop_leader(&mut self) {
+        if let Some(leader) = &mut self.leader {
+            leader.stop();
+        }
+        self.timers.cancel(Timer::Leader);
+        self.executor.send(Job::UngrabKeyboard);
+    }
+
+    /// Keep track of the keys the user is holding down. Events sent again by
+    /// this program after being held back are left out. Once every key is
+    /// released, the keys that are still down are checked after a while
+    fn track_pressed(&mut self, key: Keycode, pressed: bool) {
+        if self.remaps.is_replayed(key) {
+            return;
+        }
+
+        if pressed {
+            self.pressed.insert(key);
+        } else if self.pressed.remove(&key) && self.pressed.is_empty() {
+            self.timers.schedule(STUCK_GRACE, Timer::Stuck);
+        }
+    }
+
+    /// Keep track of the modifier keys that are held down, and the side of the
+    /// keyboard each one is on. Keys sent by this program are left out
+    fn track_modifier(&mut self, key: Keycode, pressed: bool) {
+        if !pressed {
+            self.modifiers.remove(&key);
+            return;
+        }
+
+        if let Some(charmap) = self
+            .keyboard
+            .charmap_from_keycode(key)
+            .filter(|charmap| parser::is_modifier(charmap.utf()))
+        {
+            self.modifiers.insert(key, Sides::of_key(charmap));
+        }
+    }
+
+    /// Set the keyboard `chord` was pressed on and the sides that its modifiers
+    /// are held on, as far as the bindings care about them. The keyboard is
+    /// chosen first: each keyboard that bindings are limited to and that
+    /// `device` is, then no keyboard at all. The first of these that a binding
+    /// continuing the active chain with `chord` is limited to is kept, and the
+    /// sides are then narrowed for it. If there is no such binding, `chord` is
+    /// on no keyboard and has no sides
+    fn narrow(&self, chord: Chord, device: Option<DeviceId>) -> Chord {
+        let selectors = self
+            .bound_devices
+            .iter()
+            .filter(|selector| self.is_on(selector, device))
+            .map(|selector| Some(selector.clone()))
+            .chain([None]);
+
+        for selector in selectors {
+            let mut chord = chord.clone();
+            chord.update_device(selector);
+            if let Some(chord) = self.narrow_sides(chord) {
+                return chord;
             }
         }
 
-        if should_clear {
-            self.active_chain.clear();
+        chord
+    }
+
+    /// Set the sides that the modifiers of `chord` are held on to the most of
+    /// the held ones that a binding continuing the active chain with `chord`
+    /// has. Returns `None` if no binding has `chord` with any of them
+    fn narrow_sides(&self, mut chord: Chord) -> Option<Chord> {
+        let mut held = Sides::default();
+        for sides in self.modifiers.values() {
+            held.combine(*sides);
         }
 
-        self.last_keypress = time;
+        let mut chords = self.active_chain.chords().clone();
+        for sides in held.only(chord.modmask()).subsets() {
+            chord.update_sides(sides);
+            chords.push(chord.clone());
+            if !matches!(self.bindings.lookup(&chords), Lookup::None) {
+                return Some(chord);
+            }
+            chords.pop();
+        }
 
-        Ok(())
+        None
+    }
+
+    /// Return the keyboard a key event came from. The keys sent again by this
+    /// program after being held back are from the keyboard they were pressed on
+    fn source(&mut self, key: Keycode, time: Timestamp, pressed: bool) -> Option<DeviceId> {
+        if self.remaps.is_replayed(key) {
+            return self.devices.held(key);
+        }
+
+        self.devices.source(key, time, pressed)
+    }
+
+    /// Return whether `device` is the keyboard `selector` is for
+    fn is_on(&self, selector: &DeviceValue, device: Option<DeviceId>) -> bool {
+        device.is_some_and(|id| self.devices.matches(selector, id))
+    }
+
+    /// Return whether the key events of `device` are ignored altogether
+    fn is_ignored(&self, device: Option<DeviceId>) -> bool {
+        self.config
+            .global
+            .ignore_devices
+            .iter()
+            .any(|selector| self.is_on(selector, device))
+    }
+
+    /// Read the names of the keyboards again, after one was plugged in or taken
+    /// out. The previous names are kept if they cannot be read
+    fn refresh_devices(&mut self) {
+        match self.keyboard.query_devices() {
+            Ok(names) => self.devices.set_names(names),
+            Err(e) => log::error!("{}: failed to read the devices: {}", "daemon".red().bold(), e),
+        }
+    }
+
+    /// Return whether a key press is the `escape` keys
+    fn is_escape(&self, key: Keycode, state: u16) -> bool {
+        self.escape.as_ref().is_some_and(|escape| {
+            let mut mask = ModifierMask::new(state);
+            mask.filter_ignored();
+            escape.charmap().code() == key && escape.modmask() == mask
+        })
+    }
+
+    /// Give back every grab, release the keys pressed by fake events, and throw
+    /// away everything that is in progress. The keys that are held back are
+    /// grabbed again afterwards, unless `escape_exits` is set
+    fn escape(&mut self) {
+        log::warn!("{}: releasing every grab", "escape".red().bold());
+
+        self.reset();
+        self.executor.send(Job::Release);
+
+        if self.config.global.escape_exits {
+            self.stopping = true;
+        } else {
+            self.grab_keys();
+        }
+    }
+
+    /// Throw away every binding, remap, combo, and leader sequence that is in
+    /// progress
+    fn reset(&mut self) {
+        if let Some(leader) = &mut self.leader {
+            leader.stop();
+        }
+        self.active_chain.clear();
+        self.holds.clear();
+        self.pending_taps = None;
+        self.timers.clear();
+        self.remaps.reset();
+        self.combos.reset();
+        self.devices.reset();
+    }
+
+    /// Read the keymap again after it changed (e.g., after `setxkbmap`,
+    /// `xmodmap`, or plugging in a keyboard), and parse the configuration again
+    /// with the new keys. Everything in progress is thrown away, and the keys
+    /// that are held back are grabbed again by their new keycodes. The previous
+    /// keymap is kept if the new one cannot be read
+    fn reload_keymap(&mut self) {
+        log::info!("{}: the keymap changed, reloading it", "daemon".red().bold());
+
+        let mut keyboard = Keyboard::clone(&self.keyboard);
+        if let Err(e) = keyboard.generate_charmap() {
+            log::error!("{}: failed to reload the keymap: {}", "daemon".red().bold(), e);
+            return;
+        }
+        // The capture threads only use the connections, which the new keyboard
+        // shares, but the executor looks keys up in the keymap
+        self.keyboard = Arc::new(keyboard);
+        self.executor.send(Job::SetKeyboard(Arc::clone(&self.keyboard)));
+
+        self.reset();
+        self.modifiers.clear();
+        self.executor.send(Job::Release);
+
+        if let Err(e) = self.process_configuration() {
+            log::error!("{}: failed to reload the configuration: {}", "daemon".red().bold(), e);
+        }
+        self.grab_keys();
+    }
+
+    /// Grab the keys that are part of a combo or that are different when
+    /// tapped and held, so that they can be held back until it is known what
+    /// they should do
+    fn grab_keys(&self) {
+        let grabbed = self
+            .combos
+            .keys()
+            .into_iter()
+            .chain(self.remaps.tap_holds().keys())
+            .unique()
+            .filter_map(|key| self.keyboard.charmap_from_keycode(key))
+            .map(|charmap| Chord::new(charmap, 0, 0.into(), xproto::KEY_PRESS_EVENT))
+            .collect::<Vec<_>>();
+
+        // The modifier of a one-shot key is often still applied when the key is
+        // pressed again
+        let any_modifier = self.remaps.one_shots().keys().into_iter().collect::<Vec<_>>();
+
+        self.executor.send(Job::GrabKeys(grabbed, any_modifier));
+    }
+
+    /// Send key events in place of ones that were held back. They are marked
+    /// as replayed, so that they are not held back again once they are seen
+    fn send_keys(&mut self, keys: &[HeldKey]) {
+        for key in keys {
+            self.remaps.mark_replayed(key.keycode);
+        }
+
+        self.executor.send(Job::Send(keys.to_vec()));
+    }
+
+    /// Pass a key event to the one-shot keys, the tap-hold keys, and then to
+    /// the combos, unless it was sent by [`Daemon::send_keys`]. Returns whether
+    /// the event was held back or used, and should not be processed any further
+    fn held_back(&mut self, key: Keycode, time: Timestamp, pressed: bool) -> bool {
+        if self.remaps.check_if_replayed(key) {
+            return false;
+        }
+
+        let output = if pressed {
+            self.remaps.one_shots_mut().press(key, time)
+        } else {
+            self.remaps.one_shots_mut().release(key, time)
+        };
+        if !self.handle_remap(output, Timer::OneShotRelease) {
+            return true;
+        }
+
+        let output = if pressed {
+            self.remaps.tap_holds_mut().press(key, time)
+        } else {
+            self.remaps.tap_holds_mut().release(key, time)
+        };
+        if !self.handle_remap(output, Timer::TapHoldRelease) {
+            return true;
+        }
+
+        let output = if pressed {
+            self.combos.press(key, time)
+        } else {
+            self.combos.release(key)
+        };
+        !self.handle_combo(output)
+    }
+
+    /// Act on what [`TapHoldState`](super::remap::TapHoldState) or
+    /// [`OneShotState`](super::remap::OneShotState) decided to do with a key
+    /// event. `release` is the `Timer` that resolves a deferred release.
+    /// Returns whether the event should continue to be processed
+    fn handle_remap(&mut self, output: RemapOutput, release: Timer) -> bool {
+        match output {
+            RemapOutput::Pass => return true,
+            RemapOutput::Wait =>
+                if !self.timers.contains(Timer::TapHold) {
+                    if let Some(term) = self.remaps.tap_holds().duration() {
+                        self.timers.schedule(term, Timer::TapHold);
+                    }
+                },
+            RemapOutput::Swallow => {},
+            RemapOutput::Defer => self.timers.schedule(REPEAT_GRACE, release),
+            RemapOutput::Send(keys) => {
+                if release == Timer::TapHoldRelease {
+                    self.timers.cancel(Timer::TapHold);
+                }
+                self.send_keys(&keys);
+            },
+            RemapOutput::Follow(keys) => {
+                self.send_keys(&keys);
+                return true;
+            },
+        }
+
+        false
+    }
+
+    /// Act on what [`ComboState`] decided to do with a key event. Returns
+    /// whether the event should continue to be processed
+    fn handle_combo(&mut self, output: ComboOutput) -> bool {
+        match output {
+            ComboOutput::Pass => return true,
+            ComboOutput::Wait =>
+                if !self.timers.contains(Timer::Combo) {
+                    self.timers.schedule(self.combos.duration(), Timer::Combo);
+                },
+            ComboOutput::Swallow => {},
+            ComboOutput::Fire(idx) => {
+                self.timers.cancel(Timer::Combo);
+
+                if let Some(combo) = self.combos.get(idx) {
+                    log::info!("{}: {}", "combo".red().bold(), combo.source().purple().bold());
+
+                    match combo.action().clone() {
+                        ComboAction::Keys(chords) => {
+                            let keys = chords
+                                .iter()
+                                .map(|c| c.charmap().code())
+                                .flat_map(|key| [HeldKey::press(key), HeldKey::release(key)])
+                                .collect::<Vec<_>>();
+                            self.send_keys(&keys);
+                        },
+                        ComboAction::Command(action) => action.run(&self.executor),
+                    }
+                }
+            },
+            ComboOutput::Replay(held) => {
+                self.timers.cancel(Timer::Combo);
+                self.send_keys(&held);
+            },
+        }
+
+        false
     }
 
     /// Start the loop that gets daemonized. Monitor X11 key presses that are
     /// prefixed by keys found within the configuration file
-    pub(crate) fn daemonize(&mut self) -> Result<()> {
-        const RECORD_FROM_SERVER: u8 = 0;
-        const START_OF_DATA: u8 = 4;
-
+    ///
+    /// The work is split across three threads:
+    ///   - the capture thread reads and decodes replies from the `record` context
+    ///   - this loop matches the events, waking up whenever a [`Timer`] expires
+    ///   - the [`Executor`] spawns actions and sends fake key events
+    ///
+    /// The loop stops once one of the `signals` is received, and the keyboard is
+    /// put back the way it was found. The signals must have been blocked with
+    /// [`Daemon::block_signals`]
+    pub(crate) fn daemonize(&mut self, signals: SigSet) -> Result<()> {
         self.keyboard
             .gen_record_ctx()
             .context("failed to generate record context")?;
+        self.keyboard
+            .select_keymap_events()
+            .context("failed to listen for keymap changes")?;
+        match self.keyboard.select_device_events() {
+            Ok(()) => self.device_events = true,
+            Err(e) =>
+                log::warn!("{}: keyboards cannot be told apart: {:#}", "daemon".red().bold(), e),
+        }
+        self.refresh_devices();
+        self.grab_keys();
 
-        for reply in self
-            .keyboard
-            .clone()
-            .data_connection()
-            .record_enable_context(self.keyboard.id())
-            .context("failed to get `record_enable_context`")?
-        {
-            let reply = reply.context("failed to get `record_enable_context` reply")?;
-
-            if reply.client_swapped {
-                log::warn!("byte swapped clients are unsupported");
-            } else if reply.category == RECORD_FROM_SERVER {
-                let mut remaining = &reply.data[..];
-                while !remaining.is_empty() {
-                    remaining = self.intercept(&reply.data)?;
+        let (sender, receiver) = channel::unbounded();
+        let keyboard = Arc::clone(&self.keyboard);
+        let events = sender.clone();
+        thread::spawn(move || record::capture(&keyboard, &sender));
+
+        let keyboard = Arc::clone(&self.keyboard);
+        thread::spawn(move || keyboard.forward_events(&events));
+
+        let (sender, stop) = channel::bounded(1);
+        thread::spawn(move || match signals.wait() {
+            Ok(signal) => {
+                let _ = sender.send(signal);
+            },
+            Err(e) => log::error!("failed to wait for signals: {}", e),
+        });
+
+        let result = loop {
+            let deadline = self.timers.next_deadline().map_or_else(channel::never, channel::at);
+
+            channel::select! {
+                recv(receiver) -> event => match event {
+                    Ok(event) =>
+                        if let Err(e) = event.and_then(|event| self.intercept(event)) {
+                            break Err(e);
+                        },
+                    Err(_) => break Ok(()),
+                },
+                recv(stop) -> signal => {
+                    if let Ok(signal) = signal {
+                        log::info!("{}: received {}", "daemon".red().bold(), signal);
+                    }
+                    break Ok(());
+                },
+                recv(deadline) -> _ => {},
+            }
+
+            if self.stopping {
+                log::info!("{}: stopping after the escape keys", "daemon".red().bold());
+                break Ok(());
+            }
+
+            self.process_timers();
+        };
+
+        self.executor.shutdown();
+        result
+    }
+
+    /// Block the signals that stop the daemon, returning them so that they can
+    /// be waited on. This has to be called before any other thread is spawned,
+    /// since threads inherit the signals that are blocked
+    pub(crate) fn block_signals() -> Result<SigSet> {
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGINT);
+        signals.add(Signal::SIGTERM);
+        signals
+            .thread_block()
+            .context("failed to block signals")?;
+
+        Ok(signals)
+    }
+
+    /// Intercept a single event. A key event waits for the raw event of the
+    /// same key press when the keyboard it came from matters, since the raw
+    /// events are read from another connection and can come after it
+    pub(crate) fn intercept(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::KeyPress(event) | Event::KeyRelease(event) if self.defers(&event) => {
+                self.deferred.push_back(event);
+                self.replay_deferred(false)
+            },
+            Event::XinputRawKeyPress(_) | Event::XinputRawKeyRelease(_) => {
+                self.handle(event)?;
+                self.replay_deferred(false)
+            },
+            event => self.handle(event),
+        }
+    }
+
+    /// Return whether a key event has to wait for its raw event. Once one is
+    /// waiting, every later key event waits behind it, so that they are still
+    /// handled in order
+    fn defers(&self, event: &KeyPressEvent) -> bool {
+        if !self.deferred.is_empty() {
+            return true;
+        }
+
+        let key = event.detail;
+        let pressed = is_press(event);
+
+        // Repeated presses and the keys sent again by this program have no raw
+        // event
+        self.device_events
+            && self.uses_devices()
+            && !self.remaps.is_replayed(key)
+            && !(pressed && self.pressed.contains(&key))
+            && !self.devices.has_raw(key, event.time, pressed)
+    }
+
+    /// Handle the key events that were waiting for their raw event, in order,
+    /// up to the first one that is still waiting. If `expired`, the first one
+    /// is handled even without its raw event. The release and press that
+    /// autorepeat sends for a key that is still held have the same time, and
+    /// neither has a raw event
+    fn replay_deferred(&mut self, mut expired: bool) -> Result<()> {
+        while let Some(event) = self.deferred.front().copied() {
+            let repeat = self.deferred.get(1).is_some_and(|next| {
+                !is_press(&event)
+                    && is_press(next)
+                    && next.detail == event.detail
+                    && next.time == event.time
+            });
+            let found = self.devices.has_raw(event.detail, event.time, is_press(&event));
+            if !expired && !repeat && !found {
+                break;
+            }
+            expired = false;
+
+            self.deferred.pop_front();
+            self.handle(key_event(event))?;
+            if repeat {
+                if let Some(next) = self.deferred.pop_front() {
+                    self.handle(key_event(next))?;
                 }
-            } else if reply.category == START_OF_DATA {
-                log::info!(
-                    "{} is {}",
-                    "foreground daemon".red().bold(),
-                    "STARTING".green().bold()
-                );
-            } else {
-                log::warn!("`daemon` reply category is unknown: {:#?}", reply);
             }
         }
 
+        if self.deferred.is_empty() {
+            self.timers.cancel(Timer::Source);
+        } else if !self.timers.contains(Timer::Source) {
+            self.timers.schedule(SOURCE_GRACE, Timer::Source);
+        }
         Ok(())
     }
 
+    /// Return whether any binding, remap or ignored keyboard depends on the
+    /// keyboard a key was pressed on
+    fn uses_devices(&self) -> bool {
+        !self.bound_devices.is_empty()
+            || !self.config.global.ignore_devices.is_empty()
+            || self
+                .remaps
+                .remapped_keys()
+                .iter()
+                .any(|c| c.from_key().device().is_some())
+    }
+
     // The outline of this function was taken from the `x11rb` examples folder
     //
-    /// Intercept a single packet of data, returning the remaining
-    pub(crate) fn intercept<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8]> {
-        match data[0] {
-            xproto::KEY_PRESS_EVENT => {
-                let (event, remaining) = xproto::KeyPressEvent::try_parse(data)
-                    .context("failed to parse `KeyPressEvent`")?;
+    /// Handle a single event
+    fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::KeyPress(event) => {
                 log::trace!("handling key press: {:#?}", event);
 
                 let key = event.detail;
                 let state = event.state;
 
+                // Checked first, so that it works however the keyboard is stuck
+                if self.is_escape(key, state) {
+                    self.escape();
+                    return Ok(());
+                }
+
                 // If the key was an `xtest_fake_input`, skip
                 if self.remaps.check_if_generated(key) {
                     log::debug!("auto-generated: {}", key);
-                    return Ok(remaining);
+                    return Ok(());
+                }
+
+                // A key held on an ignored keyboard is still down, so the keys
+                // that are down are not released as stuck while it is held
+                self.track_pressed(key, true);
+
+                let device = self.source(key, event.time, true);
+                if self.is_ignored(device) {
+                    log::debug!("ignored device: {}", key);
+                    return Ok(());
+                }
+
+                self.track_modifier(key, true);
+
+                if self.leader.as_ref().is_some_and(Leader::is_active) {
+                    self.leader_press(key, state);
+                    return Ok(());
+                }
+
+                if self.held_back(key, event.time, true) {
+                    return Ok(());
                 }
 
                 if self.remaps.mark_pressed(key).is_none() {
@@ -303,14 +1431,13 @@ impl Daemon {
                 }
 
                 if let Some(chord) = Handler::handle_key_press(&event, &self.keyboard) {
-                    self.process_chords(chord, event.time, event.response_type, event.root)?;
+                    let (time, response_type, root) = (event.time, event.response_type, event.root);
+                    self.process_chords(chord, time, response_type, root, device)?;
                 }
 
-                Ok(remaining)
+                Ok(())
             },
-            xproto::KEY_RELEASE_EVENT => {
-                let (event, remaining) = xproto::KeyReleaseEvent::try_parse(data)
-                    .context("failed to parse `KeyReleaseEvent`")?;
+            Event::KeyRelease(event) => {
                 log::trace!("handling key release: {:#?}", event);
                 let key = event.detail;
                 let state = event.state;
@@ -318,15 +1445,42 @@ impl Daemon {
                 // If the key was an `xtest_fake_input`, skip
                 if self.remaps.check_if_generated(key) {
                     log::debug!("auto-generated: {}", key);
-                    Ok(remaining)
+                    return Ok(());
+                }
+
+                // Tracked even on an ignored keyboard, like the press
+                self.track_pressed(key, false);
+
+                let device = self.source(key, event.time, false);
+                if self.is_ignored(device) {
+                    log::debug!("ignored device: {}", key);
+                    return Ok(());
+                }
+
+                self.track_modifier(key, false);
+
+                if self.leader.as_ref().is_some_and(Leader::is_active)
+                    || self.held_back(key, event.time, false)
+                {
+                    Ok(())
                 } else {
                     if let Some(chord) = Handler::handle_key_release(&event, &self.keyboard) {
-                        self.process_chords(chord, event.time, event.response_type, event.root)?;
+                        let (time, response_type, root) =
+                            (event.time, event.response_type, event.root);
+                        self.process_chords(chord, time, response_type, root, device)?;
                     }
 
-                    if let Some(new) = self.remaps.remapped_keys().iter().find(|c| {
-                        c.from_key().charmap().code() == key && c.from_key().modmask() == state
-                    }) {
+                    // A remap limited to the keyboard is used before one that is not
+                    if let Some(new) = self
+                        .remaps
+                        .remapped_keys()
+                        .iter()
+                        .filter(|c| {
+                            c.from_key().charmap().code() == key && c.from_key().modmask() == state
+                        })
+                        .filter(|c| c.from_key().device().map_or(true, |d| self.is_on(d, device)))
+                        .min_by_key(|c| c.from_key().device().is_none())
+                    {
                         if !new.is_used() {
                             log::debug!(
                                 "{}:{} => {}:{} -- {}",
@@ -350,9 +1504,7 @@ impl Daemon {
                                 //     event.root,
                                 // )?;
 
-                                self.keyboard
-                                    .make_key_press_event(chord.charmap().code(), &event)
-                                    .context("remap: failed to make key press event")?;
+                                self.executor.send(Job::Press(chord.charmap().code(), event));
                                 self.remaps.mark_generated(chord.charmap().code());
 
                                 // self.keyboard.make_keysequence(
@@ -377,19 +1529,15 @@ impl Daemon {
                                 //     .context("remap: failed to make key release event")?;
 
                                 self.remaps.mark_generated(chord.charmap().code());
-
-                                self.keyboard.flush();
                             }
                         }
                     }
                     let _ = self.remaps.mark_released(key);
 
-                    Ok(remaining)
+                    Ok(())
                 }
             },
-            xproto::BUTTON_PRESS_EVENT => {
-                let (event, remaining) = xproto::ButtonPressEvent::try_parse(data)
-                    .context("failed to parse `ButtonPressEvent`")?;
+            Event::ButtonPress(event) => {
                 log::trace!("handling button press: {:#?}", event);
                 log::debug!(
                     "{}::{}(code:{},mask:{})",
@@ -401,11 +1549,9 @@ impl Daemon {
                 self.remaps.set_modifier();
                 self.remaps.set_mouse_held(true);
 
-                Ok(remaining)
+                Ok(())
             },
-            xproto::BUTTON_RELEASE_EVENT => {
-                let (event, remaining) = xproto::ButtonReleaseEvent::try_parse(data)
-                    .context("failed to parse `ButtonReleaseEvent`")?;
+            Event::ButtonRelease(event) => {
                 log::trace!("handling button release: {:#?}", event);
                 log::debug!(
                     "{}::{}(code:{},mask:{})",
@@ -417,28 +1563,66 @@ impl Daemon {
 
                 self.remaps.set_mouse_held(false);
 
-                Ok(remaining)
+                Ok(())
             },
-            0 => {
-                // This is a reply, we compute its length as follows
-                let (length, _) = u32::try_parse(&data[4..])?;
-                let length = usize::try_from(length).unwrap() * 4 + 32;
-                log::error!(
-                    "{}::UnparsedReply({:?})",
-                    "daemon".red().bold(),
-                    &data[..length]
-                );
-                Ok(&data[length..])
+            Event::MotionNotify(event) => {
+                log::trace!("handling motion: {:#?}", event);
+
+                Ok(())
             },
-            _ => {
-                // Error or event always has length 32
-                log::error!(
-                    "{}::unparsed error/event: {:?}",
-                    "daemon".red().bold(),
-                    &data[..32]
-                );
-                Ok(&data[32..])
+            Event::XinputRawKeyPress(event) | Event::XinputRawKeyRelease(event) => {
+                log::trace!("handling raw key event: {:#?}", event);
+
+                if let Ok(key) = Keycode::try_from(event.detail) {
+                    let pressed = event.event_type == xinput::RAW_KEY_PRESS_EVENT;
+                    self.devices.push(RawKey::new(key, event.time, pressed, event.sourceid));
+                }
+
+                Ok(())
+            },
+            Event::XinputHierarchy(event) => {
+                log::debug!("{}::{:?}", "daemon".red().bold(), event);
+                self.refresh_devices();
+
+                Ok(())
+            },
+            Event::MappingNotify(event) if event.request != xproto::Mapping::POINTER => {
+                log::debug!("{}::{:?}", "daemon".red().bold(), event);
+                self.timers.schedule(KEYMAP_GRACE, Timer::Keymap);
+
+                Ok(())
+            },
+            Event::XkbNewKeyboardNotify(event) => {
+                log::debug!("{}::{:?}", "daemon".red().bold(), event);
+                self.timers.schedule(KEYMAP_GRACE, Timer::Keymap);
+
+                Ok(())
+            },
+            Event::XkbMapNotify(event) => {
+                log::debug!("{}::{:?}", "daemon".red().bold(), event);
+                self.timers.schedule(KEYMAP_GRACE, Timer::Keymap);
+
+                Ok(())
+            },
+            event => {
+                log::trace!("{}::unhandled event: {:?}", "daemon".red().bold(), event);
+
+                Ok(())
             },
         }
     }
 }
+
+/// Return whether a core key event is a press
+fn is_press(event: &KeyPressEvent) -> bool {
+    event.response_type & 0x7f == xproto::KEY_PRESS_EVENT
+}
+
+/// Return the core key event as an `Event`
+fn key_event(event: KeyPressEvent) -> Event {
+    if is_press(&event) {
+        Event::KeyPress(event)
+    } else {
+        Event::KeyRelease(event)
+    }
+}
@@ -0,0 +1,164 @@
+//! The keyboards that key events come from. The core key events do not say
+//! which keyboard they came from, so the XInput2 raw event that is sent for the
+//! same key press is used to find it
+
+use crate::config::DeviceValue;
+use std::collections::{HashMap, VecDeque};
+use x11rb::protocol::{
+    xinput::DeviceId,
+    xproto::{Keycode, Timestamp},
+};
+
+/// The number of raw key events that are kept around to be matched with the
+/// core key events
+const MAX_RAW_KEYS: usize = 64;
+
+// =================== RawKey =====================
+
+/// A raw key event, which is sent along with the core key event of the same key
+/// press, but says which keyboard the key was pressed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawKey {
+    /// The key that was pressed or released
+    keycode: Keycode,
+    /// The time of the event, which is the same as the one of the core event
+    time:    Timestamp,
+    /// Whether the key was pressed
+    pressed: bool,
+    /// The keyboard the key was pressed on
+    device:  DeviceId,
+}
+
+impl RawKey {
+    /// Create a new `RawKey`
+    pub(crate) fn new(keycode: Keycode, time: Timestamp, pressed: bool, device: DeviceId) -> Self {
+        Self { keycode, time, pressed, device }
+    }
+}
+
+// =================== Devices ====================
+
+/// The keyboards that are plugged in, and the keyboard that each key that is
+/// held down was pressed on
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Devices {
+    /// The name of each device, by its XInput2 id
+    names: HashMap<DeviceId, String>,
+    /// The most recent raw key events that have not been matched yet
+    raw:   VecDeque<RawKey>,
+    /// The keyboard each key was last pressed on
+    held:  HashMap<Keycode, DeviceId>,
+}
+
+impl Devices {
+    /// Create a new `Devices`
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Change the names of the devices that are plugged in
+    pub(crate) fn set_names(&mut self, names: HashMap<DeviceId, String>) {
+        self.names = names;
+    }
+
+    /// Keep a raw key event until its core event is seen
+    pub(crate) fn push(&mut self, raw: RawKey) {
+        if self.raw.len() == MAX_RAW_KEYS {
+            self.raw.pop_front();
+        }
+        self.raw.push_back(raw);
+    }
+
+    /// Return whether the raw event of a core key event has been seen. The two
+    /// are read from different connections, so the core event can come first
+    pub(crate) fn has_raw(&self, keycode: Keycode, time: Timestamp, pressed: bool) -> bool {
+        self.raw
+            .iter()
+            .any(|raw| raw.keycode == keycode && raw.time == time && raw.pressed == pressed)
+    }
+
+    /// Return the keyboard that a core key event came from. Repeated key
+    /// presses have no raw event, so a key that has no raw event is on the
+    /// keyboard it was last pressed on
+    pub(crate) fn source(
+        &mut self,
+        keycode: Keycode,
+        time: Timestamp,
+        pressed: bool,
+    ) -> Option<DeviceId> {
+        let found = self
+            .raw
+            .iter()
+            .position(|raw| raw.keycode == keycode && raw.time == time && raw.pressed == pressed)
+            .and_then(|idx| self.raw.remove(idx));
+
+        match found {
+            Some(raw) if pressed => {
+                self.held.insert(keycode, raw.device);
+                Some(raw.device)
+            },
+            Some(raw) => Some(raw.device),
+            None => self.held(keycode),
+        }
+    }
+
+    /// Return the keyboard the key was last pressed on
+    pub(crate) fn held(&self, keycode: Keycode) -> Option<DeviceId> {
+        self.held.get(&keycode).copied()
+    }
+
+    /// Return whether `device` is the one `selector` is for
+    pub(crate) fn matches(&self, selector: &DeviceValue, device: DeviceId) -> bool {
+        match selector {
+            DeviceValue::Id(id) => *id == device,
+            DeviceValue::Name(name) => self.names.get(&device) == Some(name),
+        }
+    }
+
+    /// Forget every raw key event and the keyboard every key is held on
+    pub(crate) fn reset(&mut self) {
+        self.raw.clear();
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_source_devices() {
+        let mut devices = Devices::new();
+        devices.set_names(HashMap::from([
+            (10, String::from("AT Translated Set 2 keyboard")),
+            (14, String::from("Macro Pad")),
+            (15, String::from("Macro Pad")),
+        ]));
+
+        devices.push(RawKey::new(87, 100, true, 15));
+        devices.push(RawKey::new(38, 101, true, 10));
+        devices.push(RawKey::new(87, 150, false, 15));
+
+        assert_eq!(devices.source(38, 101, true), Some(10));
+        assert_eq!(devices.source(87, 100, true), Some(15));
+        // Repeated presses have no raw event
+        assert_eq!(devices.source(87, 130, true), Some(15));
+        assert_eq!(devices.source(87, 150, false), Some(15));
+        assert_eq!(devices.source(39, 160, true), None);
+
+        // The core event can be seen before its raw event
+        assert!(!devices.has_raw(38, 200, true));
+        devices.push(RawKey::new(38, 200, true, 14));
+        assert!(devices.has_raw(38, 200, true));
+        assert!(!devices.has_raw(38, 200, false));
+        assert_eq!(devices.source(38, 200, true), Some(14));
+        assert!(!devices.has_raw(38, 200, true));
+
+        let pad = DeviceValue::Name(String::from("Macro Pad"));
+        assert!(devices.matches(&pad, 14));
+        assert!(devices.matches(&pad, 15));
+        assert!(!devices.matches(&pad, 10));
+        assert!(devices.matches(&DeviceValue::Id(10), 10));
+        assert!(!devices.matches(&DeviceValue::Id(10), 14));
+    }
+}
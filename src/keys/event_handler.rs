@@ -1,6 +1,6 @@
 //! Handle `Events` sent from the X-Server
 
-use super::{chord::Chord, keyboard::Keyboard, keys::CharacterMap};
+use super::{chord::Chord, keyboard::Keyboard, keys::ModifierMask};
 use anyhow::{Context, Result};
 use colored::Colorize;
 use x11rb::{
@@ -17,7 +17,8 @@ impl Handler {
     /// Handles the `KeyPressEvent` sent from the X-Server. A `KeyPressEvent`
     /// and `KeyReleaseEvent` are the same besides their event mask.  A new
     /// `Chord` is created from the matching `CharacterMap` and `mask` from
-    /// the event
+    /// the event. The `CharacterMap` is the one of the layout group the event
+    /// was typed in
     pub(crate) fn handle_key_press(event: &KeyPressEvent, keyboard: &Keyboard) -> Option<Chord> {
         let keycode = event.detail;
         let mask = event.state;
@@ -27,13 +28,14 @@ impl Handler {
                 log::debug!("key press handler: kc:{}-mask:{}", keycode, mask);
                 // println!("PRESS EVENT; {:#?}", event);
 
-                let charmap = CharacterMap::charmap_from_keycode(keyboard.charmap(), keycode)
+                let charmap = keyboard
+                    .charmap_from_keycode_group(keycode, ModifierMask::new(mask).group())
                     .with_context(|| {
                         format!("failed to find a `CharacterMap` for keycode={}", keycode)
                     })
                     .ok()?;
 
-                Some(Chord::new(&charmap, mask, 0.into(), event.response_type))
+                Some(Chord::new(charmap, mask, 0.into(), event.response_type))
             },
             xproto::KEY_RELEASE_EVENT => {
                 // log::debug!("key release handler: kc:{}-mask:{}", keycode, mask);
@@ -77,13 +79,14 @@ impl Handler {
                 log::debug!("key release handler: kc:{}-mask:{}", keycode, mask);
                 // println!("RELEASE EVENT; {:#?}", event);
 
-                let charmap = CharacterMap::charmap_from_keycode(keyboard.charmap(), keycode)
+                let charmap = keyboard
+                    .charmap_from_keycode_group(keycode, ModifierMask::new(mask).group())
                     .with_context(|| {
                         format!("failed to find a `CharacterMap` for keycode={}", keycode)
                     })
                     .ok()?;
 
-                Some(Chord::new(&charmap, mask, 0.into(), event.response_type))
+                Some(Chord::new(charmap, mask, 0.into(), event.response_type))
             },
             _ => None,
         }
@@ -0,0 +1,262 @@
+//! The executor thread, which spawns the actions chosen by the
+//! [`Daemon`](super::daemon::Daemon) and sends fake key events with `xtest`.
+//!
+//! Everything that changes the state of the X-Server goes through here, in the
+//! order it was sent. Spawning a process or waiting on a round trip to the
+//! X-Server never holds up the processing of events or timers
+
+use super::{chord::Chord, keyboard::Keyboard, remap::HeldKey};
+use crate::config::Action;
+use anyhow::{Context, Result};
+use colored::Colorize;
+use crossbeam_channel::{self as channel, Receiver, Sender};
+use itertools::Itertools;
+use std::{collections::HashSet, sync::Arc, thread, time::Duration};
+use x11rb::protocol::xproto::{KeyPressEvent, Keycode};
+
+// ===================== Job ======================
+
+/// Something for the executor thread to do
+#[derive(Debug, Clone)]
+pub(crate) enum Job {
+    /// Run a shell command
+    Spawn(String),
+    /// Grab keys so that they can be held back. The first are grabbed without
+    /// any modifiers, the second with any modifier
+    GrabKeys(Vec<Chord>, Vec<Keycode>),
+    /// Grab the whole keyboard
+    GrabKeyboard,
+    /// Give the keyboard back
+    UngrabKeyboard,
+    /// Send key events in place of ones that were held back. The keys that are
+    /// sent are ungrabbed while doing so, since the fake events would otherwise
+    /// be grabbed again. The keyboard grab is left to the daemon
+    Send(Vec<HeldKey>),
+    /// Send a key press at the position of another key event
+    Press(Keycode, KeyPressEvent),
+    /// Give back every grab and release the keys that are still pressed by
+    /// fake events
+    Release,
+    /// Release the keys that are logically down even though the user is not
+    /// pressing any key
+    ReleaseStuck,
+    /// Use the keyboard the keymap was reloaded into
+    SetKeyboard(Arc<Keyboard>),
+    /// Put the keyboard back the way it was found, then stop. The sender is
+    /// notified once this is done
+    Shutdown(Sender<()>),
+}
+
+// =================== Executor ===================
+
+/// A handle to the executor thread
+#[derive(Debug, Clone)]
+pub(crate) struct Executor {
+    /// The channel jobs are sent over
+    sender: Sender<Job>,
+}
+
+impl Executor {
+    /// Start the executor thread, which runs commands in `shell`
+    pub(crate) fn new(keyboard: Arc<Keyboard>, shell: &str) -> Self {
+        let (sender, receiver) = channel::unbounded();
+        let shell = shell.to_string();
+
+        thread::spawn(move || Worker::new(keyboard, shell).run(&receiver));
+
+        Self { sender }
+    }
+
+    /// Queue a `Job`
+    pub(crate) fn send(&self, job: Job) {
+        if self.sender.send(job).is_err() {
+            log::error!("{}: the executor thread has stopped", "executor".red().bold());
+        }
+    }
+
+    /// Queue a shell command
+    pub(crate) fn spawn(&self, cmd: &str) {
+        self.send(Job::Spawn(cmd.to_string()));
+    }
+
+    /// Stop the executor thread once the jobs before this one are done, and
+    /// put the keyboard back the way it was found. Waits a short time for it
+    /// to finish
+    pub(crate) fn shutdown(&self) {
+        let (sender, receiver) = channel::bounded(1);
+        self.send(Job::Shutdown(sender));
+
+        if receiver.recv_timeout(Duration::from_secs(1)).is_err() {
+            log::error!("{}: timed out while shutting down", "executor".red().bold());
+        }
+    }
+}
+
+// ==================== Worker ====================
+
+/// The state of the executor thread
+struct Worker {
+    /// The keyboard the fake events are sent to
+    keyboard:     Arc<Keyboard>,
+    /// The shell commands are ran in
+    shell:        String,
+    /// The keys that are grabbed without any modifiers
+    grabbed:      Vec<Chord>,
+    /// The keys that are grabbed with any modifier
+    any_modifier: Vec<Keycode>,
+    /// The keys that were pressed with fake events and not released yet
+    down:         HashSet<Keycode>,
+}
+
+impl Worker {
+    /// Create a new `Worker` with nothing grabbed
+    fn new(keyboard: Arc<Keyboard>, shell: String) -> Self {
+        Self {
+            keyboard,
+            shell,
+            grabbed: Vec::new(),
+            any_modifier: Vec::new(),
+            down: HashSet::new(),
+        }
+    }
+
+    /// Run jobs until every [`Executor`] is dropped
+    fn run(&mut self, receiver: &Receiver<Job>) {
+        for job in receiver {
+            if let Job::Shutdown(done) = job {
+                self.shutdown();
+                let _ = done.send(());
+                return;
+            }
+
+            if let Err(e) = self.execute(job) {
+                log::error!("{}: {}", "executor".red().bold(), e);
+            }
+        }
+    }
+
+    /// Release the keys that are still pressed by fake events, ungrab
+    /// everything, restore the autorepeat settings and free the `record`
+    /// context
+    fn shutdown(&mut self) {
+        self.release_down();
+        self.keyboard.cleanup();
+        self.keyboard.restore_controls();
+        self.keyboard.free_record_ctx();
+        self.keyboard.flush();
+
+        log::info!("{} has been {}", "daemon".red().bold(), "STOPPED".green().bold());
+    }
+
+    /// Release the keys that are still pressed by fake events
+    fn release_down(&mut self) {
+        for key in self.down.drain() {
+            if let Err(e) = self.keyboard.make_key_release_no_event(key) {
+                log::error!("failed to release key {}: {}", key, e);
+            }
+        }
+    }
+
+    /// Release the keys that are logically down even though the user is not
+    /// pressing any key. These are the keys pressed by fake events whose
+    /// release was never sent, along with any modifier that is still applied,
+    /// which can be left behind by an earlier instance that stopped early
+    fn release_stuck(&mut self) -> Result<()> {
+        let keymap = self.keyboard.query_keymap()?;
+        let is_down = |key: Keycode| keymap[usize::from(key / 8)] & (1 << (key % 8)) != 0;
+
+        // Keys that are no longer down were released some other way
+        let mut stuck = self.down.drain().filter(|key| is_down(*key)).collect::<HashSet<_>>();
+
+        if self.keyboard.get_state()?.base_mods != 0 {
+            stuck.extend(
+                self.keyboard
+                    .get_modifier_mapping()?
+                    .keycodes
+                    .into_iter()
+                    .filter(|key| *key != 0 && is_down(*key)),
+            );
+        }
+
+        for key in stuck {
+            log::warn!("{}: releasing stuck key {}", "executor".red().bold(), key);
+            self.keyboard
+                .make_key_release_no_event(key)
+                .context("failed to release stuck key")?;
+        }
+
+        Ok(())
+    }
+
+    /// Grab the keys that are held back, or only the ones `keep` is true for
+    fn grab(&self, keep: impl Fn(Keycode) -> bool) {
+        let grabbed = self
+            .grabbed
+            .iter()
+            .filter(|chord| keep(chord.charmap().code()))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !grabbed.is_empty() {
+            self.keyboard.grab_key(&grabbed);
+        }
+
+        let any_modifier = self
+            .any_modifier
+            .iter()
+            .copied()
+            .filter(|key| keep(*key))
+            .collect::<Vec<_>>();
+        if !any_modifier.is_empty() {
+            self.keyboard.grab_key_any_modifier(&any_modifier);
+        }
+    }
+
+    /// Run a single `Job`
+    fn execute(&mut self, job: Job) -> Result<()> {
+        match job {
+            Job::Spawn(cmd) => Action::spawn_shell(&cmd, &self.shell),
+            Job::GrabKeys(grabbed, any_modifier) => {
+                self.grabbed = grabbed;
+                self.any_modifier = any_modifier;
+                self.grab(|_| true);
+            },
+            Job::GrabKeyboard => self.keyboard.grab_keyboard()?,
+            Job::UngrabKeyboard => self.keyboard.ungrab_keyboard(),
+            Job::Send(keys) => {
+                let sent = keys.iter().map(|key| key.keycode).unique().collect::<Vec<_>>();
+                self.keyboard.ungrab_key_any_modifier(&sent);
+
+                let result = keys.iter().try_for_each(|key| {
+                    if key.pressed {
+                        self.down.insert(key.keycode);
+                        self.keyboard.make_key_press_no_event(key.keycode)
+                    } else {
+                        self.down.remove(&key.keycode);
+                        self.keyboard.make_key_release_no_event(key.keycode)
+                    }
+                });
+
+                self.grab(|key| sent.contains(&key));
+                result.context("failed to send held back key event")?;
+            },
+            Job::Press(keycode, event) => {
+                self.down.insert(keycode);
+                self.keyboard
+                    .make_key_press_event(keycode, &event)
+                    .context("remap: failed to make key press event")?;
+            },
+            Job::Release => {
+                self.release_down();
+                self.keyboard.cleanup();
+                self.grabbed.clear();
+                self.any_modifier.clear();
+            },
+            Job::ReleaseStuck => self.release_stuck()?,
+            Job::SetKeyboard(keyboard) => self.keyboard = keyboard,
+            Job::Shutdown(_) => {},
+        }
+
+        self.keyboard.flush();
+        Ok(())
+    }
+}
@@ -5,13 +5,13 @@ use super::{
     event_handler::Handler,
     keys::{self, CharacterMap, ModifierMask, XButton, XKeyCode},
     keysym::{KeysymHash, XKeysym},
+    modifiers::{self, ModifierMap},
 };
-use crate::{config::Config, lxhkd_fatal};
+use crate::{config::Config, lxhkd_fatal, parse::parser};
 use anyhow::{anyhow, Context, Result};
 use colored::{ColoredString, Colorize};
 use crossbeam_channel::Sender;
 use crossbeam_utils::thread as cthread;
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Borrow,
@@ -32,10 +32,12 @@ use x11rb::{
     protocol::{
         self,
         record::{self, ConnectionExt as _},
+        xinput::{self, ConnectionExt as _, DeviceId, XIEventMask},
         xkb::{
             self,
             BoolCtrl,
             ConnectionExt as _,
+            EventType,
             GetCompatMapReply,
             GetControlsReply,
             GetMapReply,
@@ -45,6 +47,7 @@ use x11rb::{
             KeySymMap,
             MapPart,
             NameDetail,
+            SelectEventsAux,
             ID,
         },
         xproto::{
@@ -132,6 +135,9 @@ pub(crate) struct Keyboard {
     root:                xproto::Window,
     /// The characters, keysyms, etc making up the `Keyboard`
     charmap:             Vec<CharacterMap>,
+    /// The index into `charmap` of the first `CharacterMap` of each `Keycode`
+    /// in each layout group
+    keycodes:            HashMap<(Keycode, u16), usize>,
     /// The device's ID
     device_id:           Xid,
     /// The minimum keycode
@@ -146,44 +152,11 @@ pub(crate) struct Keyboard {
     autorepeat_delay:    u16,
     /// The interval at which a key repeats
     autorepeat_interval: u16,
+    /// The delay and interval from before they were changed by the
+    /// configuration. These are restored on exit
+    original_repeat:     Option<(u16, u16)>,
 }
 
-/// Modifier strings and their keycodes
-#[allow(clippy::declare_interior_mutable_const)]
-pub(crate) const MODIFIERS: Lazy<Vec<(String, u32)>> = Lazy::new(|| {
-    let hash = KeysymHash::HASH;
-    [
-        "Control_L",
-        "Alt_L",
-        "Shift_L",
-        "Super_L",
-        "Hyper_L",
-        "Meta_L",
-        "Caps_Lock",
-        "Num_Lock",
-    ]
-    .iter()
-    .map(|m| ((*m).to_string(), hash.get_keysym_code_from_str(m).unwrap()))
-    .collect::<Vec<_>>()
-});
-
-/// Modifier strings and their keycodes
-#[allow(clippy::declare_interior_mutable_const)]
-pub(crate) const HELD_MODIFIERS: Lazy<Vec<(String, u32)>> = Lazy::new(|| {
-    let hash = KeysymHash::HASH;
-    [
-        "Control_L",
-        "Alt_L",
-        "Shift_L",
-        "Super_L",
-        "Hyper_L",
-        "Meta_L",
-    ]
-    .iter()
-    .map(|m| ((*m).to_string(), hash.get_keysym_code_from_str(m).unwrap()))
-    .collect::<Vec<_>>()
-});
-
 impl Keyboard {
     /// Construct a new instance of `Keyboard`
     pub(crate) fn new(
@@ -250,11 +223,13 @@ impl Keyboard {
             id,
             root,
             charmap: Vec::new(),
+            keycodes: HashMap::new(),
             device_id: 0,
             modmap: Vec::new(),
             keysyms_per_keycode: 0,
             autorepeat_interval: 0,
             autorepeat_delay: 0,
+            original_repeat: None,
         };
 
         keyboard.generate_charmap()?;
@@ -308,6 +283,26 @@ impl Keyboard {
         &self.charmap
     }
 
+    /// Return the first `CharacterMap` of a `Keycode`, without searching
+    /// through every `CharacterMap`
+    pub(crate) fn charmap_from_keycode(&self, keycode: Keycode) -> Option<&CharacterMap> {
+        self.charmap_from_keycode_group(keycode, 0)
+    }
+
+    /// Return the first `CharacterMap` of a `Keycode` in a layout group. A key
+    /// that is not in the group (e.g., `Escape` is usually only in the first)
+    /// types what it does in the first group
+    pub(crate) fn charmap_from_keycode_group(
+        &self,
+        keycode: Keycode,
+        group: u16,
+    ) -> Option<&CharacterMap> {
+        self.keycodes
+            .get(&(keycode, group))
+            .or_else(|| self.keycodes.get(&(keycode, 0)))
+            .and_then(|idx| self.charmap.get(*idx))
+    }
+
     /// Shorter `poll_for_event` (non-blocking)
     pub(crate) fn poll_for_event(&self) -> Option<Event> {
         self.conn
@@ -323,6 +318,76 @@ impl Keyboard {
             .context("failed to wait for next event")
     }
 
+    /// Ask for the XKB events that are sent when the keymap changes or another
+    /// keyboard is plugged in. `MappingNotify` is always sent
+    pub(crate) fn select_keymap_events(&self) -> Result<()> {
+        self.conn
+            .xkb_select_events(
+                ID::USE_CORE_KBD.into(),
+                0_u16,
+                EventType::NEW_KEYBOARD_NOTIFY | EventType::MAP_NOTIFY,
+                0_u16,
+                0_u16,
+                &SelectEventsAux::new(),
+            )
+            .context("failed to select XKB events")?
+            .check()
+            .context("failed to check selecting XKB events")
+    }
+
+    /// Ask for the XInput2 raw key events, which say which keyboard each key
+    /// was pressed on, and for the events that are sent when a keyboard is
+    /// plugged in or taken out
+    pub(crate) fn select_device_events(&self) -> Result<()> {
+        self.conn
+            .xinput_xi_query_version(2, 0)
+            .context("failed to query the XInput2 version")?
+            .reply()
+            .context("failed to get the XInput2 version reply")?;
+
+        let mask =
+            XIEventMask::RAW_KEY_PRESS | XIEventMask::RAW_KEY_RELEASE | XIEventMask::HIERARCHY;
+        self.conn
+            .xinput_xi_select_events(self.root, &[xinput::EventMask {
+                deviceid: xinput::Device::ALL.into(),
+                mask:     vec![mask.into()],
+            }])
+            .context("failed to select XInput2 events")?
+            .check()
+            .context("failed to check selecting XInput2 events")
+    }
+
+    /// Return the name of every input device, by its XInput2 id
+    pub(crate) fn query_devices(&self) -> Result<HashMap<DeviceId, String>> {
+        let reply = self
+            .conn
+            .xinput_xi_query_device(xinput::Device::ALL)
+            .context("failed to query the XInput2 devices")?
+            .reply()
+            .context("failed to get the XInput2 devices reply")?;
+
+        Ok(reply
+            .infos
+            .into_iter()
+            .map(|info| (info.deviceid, String::from_utf8_lossy(&info.name).into_owned()))
+            .collect())
+    }
+
+    /// Send every event that is sent to this program, such as the ones asked
+    /// for by [`Keyboard::select_keymap_events`], to the
+    /// [`Daemon`](super::daemon::Daemon). This runs on its own thread until
+    /// the `Daemon` stops listening, or the connection to the X-Server is lost
+    pub(crate) fn forward_events(&self, sender: &Sender<Result<Event>>) {
+        loop {
+            let event = self.wait_for_event();
+            let lost = event.is_err();
+
+            if sender.send(event).is_err() || lost {
+                return;
+            }
+        }
+    }
+
     /// Get the `GetKeyboardMappingReply`. This only contains the `Keysyms` from
     /// the minimum keycode to the maximum keycode. Much simpler that
     /// [`get_map_reply`](Keyboard::get_map_reply), but doesn't provide as much
@@ -350,13 +415,8 @@ impl Keyboard {
     // TODO: Fix this
     // XkbSetControls: https://code.woboq.org/qt5/include/X11/XKBlib.h.html
 
-    /// Set the key repeat-delay and repeat-interval
-    pub(crate) fn set_controls(&mut self, config: &Config) -> Result<()> {
-        let reply = self.get_controls_reply()?;
-
-        self.autorepeat_delay = reply.repeat_delay;
-        self.autorepeat_interval = reply.repeat_interval;
-
+    /// Send the key repeat-delay and repeat-interval to the X-Server
+    fn change_repeat(&self, delay: u16, interval: u16) -> Result<()> {
         self.conn
             .xkb_set_controls(
                 ID::USE_CORE_KBD.into(),
@@ -374,34 +434,53 @@ impl Keyboard {
                 0_u16,                      // affect_enabled_controls
                 0_u8,                       // enabled_controls
                 xkb::BoolCtrl::REPEAT_KEYS, // change_controls
-                config.global.autorepeat_delay.unwrap_or(reply.repeat_delay), // repeat_delay
-                // The reply from the server is already divided by a thousand
-                // FIX: This is not perfect. If the number isn't evenly divisible by 1000
-                (1000_f32
-                    / f32::from(
-                        config
-                            .global
-                            .autorepeat_interval
-                            .unwrap_or(1000 / reply.repeat_interval),
-                    )) as u16, // repeat_interval
-                0_u16,    // slow_keys_delay
-                0_u16,    // debounce_delay
-                0_u16,    // mouse_keys_delay
-                0_u16,    // mouse_keys_interval
-                0_u16,    // mouse_keys_time_to_max
-                0_u16,    // mouse_keys_max_speed
-                0_i16,    // mouse_keys_curve
-                0_u16,    // access_x_timeout
-                0_u16,    // access_x_timeout_mask
-                0_u16,    // access_x_timeout_values
-                0_u16,    // access_x_timeout_options_mask
-                0_u16,    // access_x_timeout_options_values
-                &[0; 32], // per_key_repeat
+                delay,                      // repeat_delay
+                interval,                   // repeat_interval
+                0_u16,                      // slow_keys_delay
+                0_u16,                      // debounce_delay
+                0_u16,                      // mouse_keys_delay
+                0_u16,                      // mouse_keys_interval
+                0_u16,                      // mouse_keys_time_to_max
+                0_u16,                      // mouse_keys_max_speed
+                0_i16,                      // mouse_keys_curve
+                0_u16,                      // access_x_timeout
+                0_u16,                      // access_x_timeout_mask
+                0_u16,                      // access_x_timeout_values
+                0_u16,                      // access_x_timeout_options_mask
+                0_u16,                      // access_x_timeout_options_values
+                &[0; 32],                   // per_key_repeat
             )
             .context("failed to set XKB controls")?
             .check()
             .context("failed to check XKB controls request")?;
 
+        Ok(())
+    }
+
+    /// Set the key repeat-delay and repeat-interval
+    pub(crate) fn set_controls(&mut self, config: &Config) -> Result<()> {
+        let reply = self.get_controls_reply()?;
+
+        self.autorepeat_delay = reply.repeat_delay;
+        self.autorepeat_interval = reply.repeat_interval;
+
+        if config.global.autorepeat_delay.is_some() || config.global.autorepeat_interval.is_some() {
+            self.original_repeat = Some((reply.repeat_delay, reply.repeat_interval));
+        }
+
+        self.change_repeat(
+            config.global.autorepeat_delay.unwrap_or(reply.repeat_delay),
+            // The reply from the server is already divided by a thousand
+            // FIX: This is not perfect. If the number isn't evenly divisible by 1000
+            (1000_f32
+                / f32::from(
+                    config
+                        .global
+                        .autorepeat_interval
+                        .unwrap_or(1000 / reply.repeat_interval),
+                )) as u16,
+        )?;
+
         // Get reply again to confirm the change took effect
         let reply = self.get_controls_reply()?;
 
@@ -452,6 +531,21 @@ impl Keyboard {
         Ok(())
     }
 
+    /// Restore the key repeat-delay and repeat-interval to what they were
+    /// before they were changed by the configuration
+    pub(crate) fn restore_controls(&self) {
+        if let Some((delay, interval)) = self.original_repeat {
+            match self.change_repeat(delay, interval) {
+                Ok(()) => log::info!(
+                    "restored {} and {}",
+                    "autorepeat_delay".green().bold(),
+                    "autorepeat_interval".green().bold()
+                ),
+                Err(e) => log::error!("failed to restore autorepeat settings: {}", e),
+            }
+        }
+    }
+
     /// Query the server for the current keyboard state
     pub(crate) fn get_state(&self) -> Result<GetStateReply> {
         self.conn
@@ -461,6 +555,19 @@ impl Keyboard {
             .context("failed to get XKB `GetStateReply`")
     }
 
+    /// Query the server for the keys that are logically down, which includes
+    /// keys pressed with `xtest`. Bit `n` of the result is set if keycode `n`
+    /// is down
+    pub(crate) fn query_keymap(&self) -> Result<[u8; 32]> {
+        Ok(self
+            .conn
+            .query_keymap()
+            .context("failed to get `QueryKeymapReply`")?
+            .reply()
+            .context("failed to get `QueryKeymapReply` reply")?
+            .keys)
+    }
+
     /// Get the modifier mappings on the keyboard. This returns the keycodes
     /// that are modifiers that are in use on the user's keyboard
     pub(crate) fn get_modifier_mapping(&self) -> Result<GetModifierMappingReply> {
@@ -489,6 +596,31 @@ impl Keyboard {
             .context("failed to get XKB `GetCompatMappingReply` reply")
     }
 
+    /// Return the XKB names of the physical keys (e.g., `AC01`), which do not
+    /// change along with the layout
+    pub(crate) fn get_key_names(&self) -> Result<HashMap<Keycode, String>> {
+        let reply = self
+            .conn
+            .xkb_get_names(ID::USE_CORE_KBD.into(), NameDetail::KEY_NAMES)
+            .context("failed to get `GetNamesReply`")?
+            .reply()
+            .context("failed to get XKB `GetNamesReply` reply")?;
+
+        Ok(reply
+            .value_list
+            .key_names
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter_map(|(idx, key)| {
+                let code = reply.first_key.checked_add(u8::try_from(idx).ok()?)?;
+                let name = String::from_utf8_lossy(&key.name);
+                let name = name.trim_end_matches('\0');
+                (!name.is_empty()).then(|| (code, name.to_string()))
+            })
+            .collect())
+    }
+
     /// Get the `GetMapReply`. Provides the minimum and maximum keycode, as well
     /// as `Keysyms` which are "real" modifiers and "virtual" modifiers.
     pub(crate) fn get_map_reply(&self) -> Result<GetMapReply> {
@@ -593,13 +725,17 @@ impl Keyboard {
         Ok(())
     }
 
-    /// Generate the [`CharacterMap`](super::keys::CharacterMap)
+    /// Generate the [`CharacterMap`](super::keys::CharacterMap). This can be
+    /// called again once the keymap changes, replacing the previous one
     pub(crate) fn generate_charmap(&mut self) -> Result<()> {
         let keysym_hash = KeysymHash::HASH;
         let get_reply = self.get_map_reply()?;
         let map = get_reply.map;
 
         self.device_id = get_reply.device_id;
+        self.min_keycode = get_reply.min_key_code;
+        self.max_keycode = get_reply.max_key_code;
+        self.charmap.clear();
 
         // KeyType {
         //     mods_mask: 1,
@@ -635,6 +771,20 @@ impl Keyboard {
 
         self.modmap = key_modmap.clone();
 
+        // The real modifiers of each virtual modifier, which are only listed for
+        // the virtual modifiers that are set in `virtual_mods`
+        let mut vmod_masks = vmods.iter();
+        let vmod_masks = (0..16)
+            .map(|bit| {
+                if get_reply.virtual_mods & (1 << bit) == 0 {
+                    0
+                } else {
+                    vmod_masks.next().map_or(0, |real| u16::from(*real))
+                }
+            })
+            .collect::<Vec<_>>();
+        let modifier_keycodes = self.get_modifier_mapping()?.keycodes;
+
         for (idx, symm) in sym_maps.iter().enumerate() {
             let kc = self.min_keycode + idx as u8;
             let vmod = virtual_mod
@@ -670,10 +820,22 @@ impl Keyboard {
                         .ok_or(Error::LookupKeysymHash(keysym))
                     {
                         Ok(ks) => {
+                            // A modifier sets its real modifiers no matter which level it
+                            // is on, rather than needing the modifiers of its level
+                            let modmask = if parser::is_modifier(ks) {
+                                modifiers::real_modifiers(
+                                    kc,
+                                    vmod,
+                                    &modifier_keycodes,
+                                    &vmod_masks,
+                                )
+                            } else {
+                                u16::from(modmask | keys::get_modmask_from_keycode(key_modmap, kc))
+                            };
                             let charmap = CharacterMap::new(
                                 ks.to_string(),
                                 kc,
-                                u16::from(modmask | keys::get_modmask_from_keycode(key_modmap, kc)),
+                                modmask,
                                 keysym,
                                 key_level,
                                 vmod,
@@ -697,7 +859,28 @@ impl Keyboard {
             }
         }
 
+        match self.get_key_names() {
+            Ok(names) =>
+                for charmap in &mut self.charmap {
+                    if let Some(name) = names.get(&charmap.code()) {
+                        charmap.set_name(name.clone());
+                    }
+                },
+            Err(e) => log::warn!("physical keys cannot be bound by their names: {}", e),
+        }
+
+        for ambiguity in ModifierMap::new(&self.charmap).ambiguities() {
+            log::warn!("{}: {}", "modifiers".red().bold(), ambiguity);
+        }
+
         // "L1", "L2"... get added multiple times with different `modmask`
+        self.keycodes.clear();
+        for (idx, charmap) in self.charmap.iter().enumerate() {
+            self.keycodes
+                .entry((charmap.code(), charmap.group()))
+                .or_insert(idx);
+        }
+
         let reply = self.get_keyboard_mapping_reply()?;
         self.keysyms_per_keycode = reply.keysyms_per_keycode;
 
@@ -773,7 +956,9 @@ impl Keyboard {
         }
     }
 
-    /// Grab a specified key plus possible modifiers
+    /// Grab a specified key plus possible modifiers. Each key is also grabbed
+    /// with `Lock` and `mod2`, so that it is still grabbed while Caps Lock or
+    /// Num Lock is on
     pub(crate) fn grab_key(&self, chords: &[Chord]) {
         for chord in chords {
             for mask in ModifierMask::return_ignored(chord.modmask()) {
@@ -802,15 +987,49 @@ impl Keyboard {
         }
     }
 
-    /// Ungrab a set of `XKeyCode`'s
+    /// Grab a set of `Keycode`s along with any modifiers that are held
+    pub(crate) fn grab_key_any_modifier(&self, keycodes: &[Keycode]) {
+        for keycode in keycodes {
+            log::debug!("grabbing code:{} with any modifier", keycode);
+            if let Err(e) = self.conn.grab_key(
+                false,
+                self.root,
+                xproto::ModMask::ANY,
+                *keycode,
+                xproto::GrabMode::ASYNC,
+                xproto::GrabMode::ASYNC,
+            ) {
+                lxhkd_fatal!("failed to grab key {:?} with any modifier: {}", keycode, e);
+            }
+        }
+    }
+
+    /// Ungrab a set of `XKeyCode`'s, along with the modifiers that
+    /// [`Keyboard::grab_key`] grabs them with as well
     pub(crate) fn ungrab_key(&self, chords: &[Chord]) {
         for chord in chords {
+            for mask in ModifierMask::return_ignored(chord.modmask()) {
+                if let Err(e) = self.conn.ungrab_key(
+                    chord.charmap().code(), // key
+                    self.root,              // window
+                    mask.mask(),            // modifier
+                ) {
+                    lxhkd_fatal!("failed to ungrab key: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Ungrab a set of `Keycode`s, no matter which modifiers they were grabbed
+    /// with
+    pub(crate) fn ungrab_key_any_modifier(&self, keycodes: &[Keycode]) {
+        for keycode in keycodes {
             if let Err(e) = self.conn.ungrab_key(
-                chord.charmap().code(), // key
-                self.root,              // window
-                chord.modmask(),        // modifier
+                *keycode,             // key
+                self.root,            // window
+                xproto::ModMask::ANY, // modifier
             ) {
-                lxhkd_fatal!("failed to ungrab key: {}", e);
+                lxhkd_fatal!("failed to ungrab key {:?} with any modifier: {}", keycode, e);
             }
         }
     }
@@ -1425,9 +1644,10 @@ impl Keyboard {
             delivered_events: empty,
             device_events:    record::Range8 {
                 // Want notification of core X11 events from key press (2) to motion notify (6)
-                // KeyPress = 2, KeyRelease = 3, ButtonPress = 4, ButtonRelease = 5
+                // KeyPress = 2, KeyRelease = 3, ButtonPress = 4, ButtonRelease = 5,
+                // MotionNotify = 6
                 first: xproto::KEY_PRESS_EVENT,
-                last:  xproto::BUTTON_RELEASE_EVENT,
+                last:  xproto::MOTION_NOTIFY_EVENT,
             },
             errors:           empty, // core and ext errors
             client_started:   false, // connection setup reply from server
@@ -1473,6 +1693,17 @@ impl Keyboard {
         Ok(())
     }
 
+    /// Disable and free the [`record`](x11rb::protocol::record) context. This
+    /// ends the replies being read from it
+    pub(crate) fn free_record_ctx(&self) {
+        if let Err(e) = self.conn.record_disable_context(self.id) {
+            log::error!("failed to disable record context: {}", e);
+        }
+        if let Err(e) = self.conn.record_free_context(self.id) {
+            log::error!("failed to free record context: {}", e);
+        }
+    }
+
     // =================== Other ======================
 
     /// Create a full click of the mouse (`ButtonPress` + `ButtonRelease`)
@@ -11,13 +11,12 @@
 //  - https://www.x.org/releases/X11R7.6/doc/libX11/specs/XKB/xkblib.html
 
 use super::keysym::KeysymHash;
-use crate::parse::parser::{Token, TokenizedLine};
 use anyhow::{Context, Result};
 use colored::Colorize;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::{
-    cmp::Ordering,
+    cmp::{Ordering, Reverse},
     fmt,
     ops::{BitAnd, BitOr, BitXor},
     str::FromStr,
@@ -109,6 +108,9 @@ impl fmt::Display for XKeyCode {
 
 // ================== Modmask =====================
 
+/// The bits of an event's state that are the layout group the keyboard is in
+pub(crate) const GROUP_MASK: u16 = 0x6000;
+
 /// Builtin (real) modifiers available within XOrg. These modifiers can be set
 /// to anything, which is why there is not an `Alt`, `Control_L`, `Hyper_R`, etc
 /// available.
@@ -147,9 +149,16 @@ impl ModifierMask {
         self.mask &= !(mask);
     }
 
-    /// Filter `lock` modifiers
+    /// Filter `lock` modifiers, along with the layout group of an event's
+    /// state, which is not a modifier
     pub(crate) fn filter_ignored(&mut self) {
-        self.mask &= !(u16::from(ModMask::LOCK | ModMask::M2));
+        self.mask &= !(u16::from(ModMask::LOCK | ModMask::M2) | GROUP_MASK);
+    }
+
+    /// Return the layout group of an event's state (e.g., the second one when
+    /// switched to a `ru` layout from `us,ru`)
+    pub(crate) const fn group(self) -> u16 {
+        (self.mask & GROUP_MASK) >> 13
     }
 
     /// Return modifiers to ignore when grabbign keys
@@ -297,6 +306,94 @@ impl fmt::Display for ModifierMask {
     }
 }
 
+// ==================== Sides =====================
+
+/// The side of the keyboard that the modifiers of a [`ModifierMask`] are held
+/// on (e.g., `Control_R` is `ctrl` on the right). A modifier can be held on
+/// both sides at once
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, PartialOrd, Ord, Hash)]
+pub(crate) struct Sides {
+    /// The modifiers held on the left side
+    left:  u16,
+    /// The modifiers held on the right side
+    right: u16,
+}
+
+impl Sides {
+    /// Create a new `Sides`
+    pub(crate) const fn new(left: u16, right: u16) -> Self {
+        Self { left, right }
+    }
+
+    /// Return the side of the modifier key `charmap`, which is named after it
+    /// (e.g., `Alt_L`). Keys that are not on a side have none
+    pub(crate) fn of_key(charmap: &CharacterMap) -> Self {
+        let utf = charmap.utf();
+        if utf.ends_with("_L") {
+            Self::new(charmap.modmask(), 0)
+        } else if utf.ends_with("_R") {
+            Self::new(0, charmap.modmask())
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Return the modifiers held on the left side
+    pub(crate) const fn left(self) -> u16 {
+        self.left
+    }
+
+    /// Return the modifiers held on the right side
+    pub(crate) const fn right(self) -> u16 {
+        self.right
+    }
+
+    /// Combine sides
+    pub(crate) fn combine(&mut self, other: Self) {
+        self.left |= other.left;
+        self.right |= other.right;
+    }
+
+    /// Return only the sides of the modifiers in `mask`
+    pub(crate) const fn only(self, mask: ModifierMask) -> Self {
+        Self::new(self.left & mask.mask, self.right & mask.mask)
+    }
+
+    /// Return the number of modifiers that are on a side
+    const fn count(self) -> u32 {
+        self.left.count_ones() + self.right.count_ones()
+    }
+
+    /// Return every combination of these sides, from the one with the most
+    /// modifiers on a side to the one with none
+    pub(crate) fn subsets(self) -> Vec<Self> {
+        let bits = |mask: u16| {
+            (0..u16::BITS)
+                .map(|bit| 1 << bit)
+                .filter(move |bit| mask & bit != 0)
+        };
+        let held = bits(self.left)
+            .map(|bit| Self::new(bit, 0))
+            .chain(bits(self.right).map(|bit| Self::new(0, bit)))
+            .collect::<Vec<_>>();
+
+        let mut subsets = (0..1_u32 << held.len())
+            .map(|chosen| {
+                let mut sides = Self::default();
+                for (idx, side) in held.iter().enumerate() {
+                    if chosen & (1 << idx) != 0 {
+                        sides.combine(*side);
+                    }
+                }
+                sides
+            })
+            .collect::<Vec<_>>();
+        subsets.sort_by_key(|sides| Reverse(sides.count()));
+
+        subsets
+    }
+}
+
 // ================ Button Code ===================
 
 // /// The available buttons on a mouse. This is more easily represented as an
@@ -439,7 +536,7 @@ impl fmt::Display for XButton {
 /// since `mod4` is represented the same regardless of which key it is mapped
 /// to, we will scan the built `CharacterMap` and find the corresponding key
 /// that has the same `modmask` and set everything else the same
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
 pub(crate) struct CharacterMap {
     /// The UTF-8 representation of the key. E.g., `Hyper_L`
     utf:      String,
@@ -462,6 +559,9 @@ pub(crate) struct CharacterMap {
     group:    u16,
     /// Whether the key has a spot on the keyboard
     is_bound: bool,
+    /// The XKB name of the physical key (e.g., `AC01`), which is the same no
+    /// matter which layout is used. This is empty if it is unknown
+    name:     String,
 }
 
 impl CharacterMap {
@@ -510,6 +610,16 @@ impl CharacterMap {
         self.is_bound
     }
 
+    /// Return the XKB `name` of the physical key
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the XKB `name` of the physical key
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
     /// Generate a new `CharacterMap`
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
@@ -531,6 +641,7 @@ impl CharacterMap {
             vmod,
             group: u16::from(group),
             is_bound,
+            name: String::new(),
         }
     }
 
@@ -551,6 +662,7 @@ impl CharacterMap {
                 vmod:     0,
                 group:    0,
                 is_bound: false,
+                name:     String::new(),
             }
         }
     }
@@ -566,6 +678,7 @@ impl CharacterMap {
             vmod:     0,
             group:    0,
             is_bound: true,
+            name:     String::new(),
         }
     }
 
@@ -585,6 +698,18 @@ impl CharacterMap {
         charmaps.iter().find(|c| c.code == keycode).cloned()
     }
 
+    /// Return the first `CharacterMap` of every layout group a `Keycode` is
+    /// in, which are the keysyms the key types without any modifiers
+    pub(crate) fn charmaps_from_keycode(charmaps: &[Self], keycode: Keycode) -> Vec<Self> {
+        let mut found = Vec::<Self>::new();
+        for charmap in charmaps.iter().filter(|c| c.code == keycode) {
+            if !found.iter().any(|c| c.group == charmap.group) {
+                found.push(charmap.clone());
+            }
+        }
+        found
+    }
+
     /// Return the `CharacterMap` corresponding to the `XKeyCode` given
     pub(crate) fn charmap_from_xkeycode(charmaps: &[Self], keycode: XKeyCode) -> Option<Self> {
         charmaps.iter().find(|c| c.code == keycode.code).cloned()
@@ -0,0 +1,182 @@
+//! Leader-key sequences. After the leader binding is pressed, the keyboard is
+//! grabbed and a sequence of plain keys (e.g., `g b`) selects an action
+
+use super::{
+    chord::Chain,
+    trie::{Lookup, Trie},
+};
+use crate::config::Action;
+use colored::Colorize;
+use std::time::Duration;
+use x11rb::protocol::xproto::Keycode;
+
+/// The default number of milliseconds to wait for each key after the leader
+pub(crate) const DEFAULT_LEADER_TIMEOUT: u32 = 1000;
+
+/// A key typed after the leader, along with the modifiers that were held
+pub(crate) type LeaderKey = (Keycode, u16);
+
+// ================= LeaderOutput =================
+
+/// What should be done after [`Leader`] has seen a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LeaderOutput {
+    /// The leader is not active
+    Pass,
+    /// More keys are needed to select a sequence
+    Wait,
+    /// A sequence was selected. Its action should be ran
+    Run(Action),
+    /// No sequence starts with the keys that were typed
+    Abort,
+}
+
+// ==================== Leader ====================
+
+/// The leader binding along with the sequences that can follow it
+#[derive(Debug, Clone)]
+pub(crate) struct Leader {
+    /// The binding that starts a sequence
+    chain:     Chain,
+    /// The number of milliseconds to wait for each key
+    timeout:   u32,
+    /// Every sequence, along with its source and action
+    sequences: Trie<LeaderKey, (String, Action)>,
+    /// The keys typed since the leader was pressed, while it is active
+    typed:     Option<Vec<LeaderKey>>,
+}
+
+impl Leader {
+    /// Create a new `Leader` with no sequences
+    pub(crate) fn new(chain: Chain, timeout: u32) -> Self {
+        Self {
+            chain,
+            timeout,
+            sequences: Trie::new(),
+            typed: None,
+        }
+    }
+
+    /// Add a sequence. Returns `false` if the sequence was already added, in
+    /// which case the first one is kept
+    pub(crate) fn insert(&mut self, source: &str, keys: Vec<LeaderKey>, action: Action) -> bool {
+        if self.sequences.get(&keys).is_some() {
+            return false;
+        }
+
+        self.sequences.insert(keys, (source.to_string(), action));
+        true
+    }
+
+    /// Return the binding that starts a sequence
+    pub(crate) fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
+    /// Return the amount of time to wait for each key
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(u64::from(self.timeout))
+    }
+
+    /// Return whether the leader was pressed and a sequence is being typed
+    pub(crate) fn is_active(&self) -> bool {
+        self.typed.is_some()
+    }
+
+    /// Start waiting for a sequence
+    pub(crate) fn start(&mut self) {
+        self.typed = Some(Vec::new());
+    }
+
+    /// Stop waiting for a sequence
+    pub(crate) fn stop(&mut self) {
+        self.typed = None;
+    }
+
+    /// Handle a key typed after the leader
+    pub(crate) fn press(&mut self, key: LeaderKey) -> LeaderOutput {
+        if let Some(typed) = &mut self.typed {
+            typed.push(key);
+
+            match self.sequences.lookup(typed) {
+                Lookup::None => {
+                    self.typed = None;
+                    LeaderOutput::Abort
+                },
+                Lookup::Prefix | Lookup::Ambiguous(_) => LeaderOutput::Wait,
+                Lookup::Match((source, action)) => {
+                    log::info!("{}: matched {}", "leader".red().bold(), source.purple().bold());
+                    self.typed = None;
+                    LeaderOutput::Run(action.clone())
+                },
+            }
+        } else {
+            LeaderOutput::Pass
+        }
+    }
+
+    /// Handle the timeout passing without another key. A sequence that is the
+    /// start of a longer one is used if it was typed
+    pub(crate) fn expire(&mut self) -> LeaderOutput {
+        match self.typed.take() {
+            Some(typed) => match self.sequences.lookup(&typed) {
+                Lookup::Match((source, action)) | Lookup::Ambiguous((source, action)) => {
+                    log::info!("{}: matched {}", "leader".red().bold(), source.purple().bold());
+                    LeaderOutput::Run(action.clone())
+                },
+                Lookup::None | Lookup::Prefix => LeaderOutput::Abort,
+            },
+            None => LeaderOutput::Pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: LeaderKey = (42, 0);
+    const B: LeaderKey = (56, 0);
+    const X: LeaderKey = (53, 0);
+
+    fn leader() -> Leader {
+        let mut leader = Leader::new(Chain::default(), DEFAULT_LEADER_TIMEOUT);
+        assert!(leader.insert("g", vec![G], Action::Shell(String::from("git status"))));
+        assert!(leader.insert("g b", vec![G, B], Action::Shell(String::from("git blame"))));
+        assert!(!leader.insert("g b", vec![G, B], Action::Shell(String::from("again"))));
+        leader
+    }
+
+    #[test]
+    fn leader_runs_and_aborts_sequences() {
+        let mut leader = leader();
+
+        // Nothing is done until the leader is pressed
+        assert_eq!(leader.press(G), LeaderOutput::Pass);
+        assert_eq!(leader.expire(), LeaderOutput::Pass);
+
+        // A sequence that starts a longer one runs once the timeout passes
+        leader.start();
+        assert_eq!(leader.press(G), LeaderOutput::Wait);
+        assert_eq!(leader.expire(), LeaderOutput::Run(Action::Shell(String::from("git status"))));
+        assert!(!leader.is_active());
+
+        // The longer one runs as soon as it is typed
+        leader.start();
+        assert_eq!(leader.press(G), LeaderOutput::Wait);
+        assert_eq!(leader.press(B), LeaderOutput::Run(Action::Shell(String::from("git blame"))));
+        assert!(!leader.is_active());
+
+        // A key that no sequence continues with aborts
+        leader.start();
+        assert_eq!(leader.press(G), LeaderOutput::Wait);
+        assert_eq!(leader.press(X), LeaderOutput::Abort);
+        assert!(!leader.is_active());
+        assert_eq!(leader.press(B), LeaderOutput::Pass);
+
+        // Timing out with nothing typed aborts
+        leader.start();
+        assert_eq!(leader.expire(), LeaderOutput::Abort);
+        assert!(!leader.is_active());
+    }
+}
@@ -1,9 +1,19 @@
 #![allow(clippy::module_inception)]
 
+pub(crate) mod binding;
 pub(crate) mod chord;
+pub(crate) mod combo;
+pub(crate) mod conflict;
 pub(crate) mod daemon;
+pub(crate) mod device;
 pub(crate) mod event_handler;
+pub(crate) mod executor;
 pub(crate) mod keyboard;
 pub(crate) mod keys;
 pub(crate) mod keysym;
+pub(crate) mod leader;
+pub(crate) mod modifiers;
+pub(crate) mod record;
 pub(crate) mod remap;
+pub(crate) mod timer;
+pub(crate) mod trie;
@@ -0,0 +1,186 @@
+//! The real modifiers that the modifiers written in the configuration file
+//! (e.g., `super`) are on. These are found from the modifier map of the
+//! keyboard instead of assuming the keys they are usually bound to
+
+use super::keys::CharacterMap;
+use thiserror::Error;
+use x11rb::protocol::xproto::Keycode;
+
+/// The modifiers that are named after the keys bound to them, along with the
+/// keysyms of those keys. The first keysym is the one on the left
+const NAMED_MODIFIERS: &[(&str, &[&str])] = &[
+    ("shift", &["Shift_L", "Shift_R"]),
+    ("ctrl", &["Control_L", "Control_R"]),
+    ("alt", &["Alt_L", "Alt_R"]),
+    ("meta", &["Meta_L", "Meta_R"]),
+    ("super", &["Super_L", "Super_R"]),
+    ("hyper", &["Hyper_L", "Hyper_R"]),
+    ("lock", &["Caps_Lock", "Shift_Lock"]),
+    ("numlock", &["Num_Lock"]),
+];
+
+/// The names of the 8 real modifiers, in the order of their bits
+const REAL_MODIFIERS: &[&str] = &["shift", "lock", "ctrl", "mod1", "mod2", "mod3", "mod4", "mod5"];
+
+// =================== Error ======================
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum Error {
+    #[error("{0} and {1} both map to {2}, so they cannot be told apart")]
+    Ambiguous(&'static str, &'static str, String),
+}
+
+// ================ Helper Funcs ==================
+
+/// Return the keysyms that the modifier `name` can be looked up as, from the
+/// first to try to the last. A name starting with `l` or `r` (e.g., `rsuper`)
+/// is only the keysym of that side. The real modifiers (e.g., `mod4`) are not
+/// named after any keys
+pub(crate) fn keysyms(name: &str) -> Option<&'static [&'static str]> {
+    let name = match name {
+        "control" => "ctrl",
+        "caps" | "capslock" => "lock",
+        other => other,
+    };
+
+    NAMED_MODIFIERS.iter().find_map(|(named, keysyms)| {
+        if *named == name {
+            return Some(*keysyms);
+        }
+
+        // Only modifiers with a key on both sides can have a side
+        let side = match name.strip_suffix(named)? {
+            "l" => 0,
+            "r" => 1,
+            _ => return None,
+        };
+        (keysyms.len() == 2).then(|| &keysyms[side..=side])
+    })
+}
+
+/// Return the names of the real modifiers in `mask` (e.g., `mod1 + mod4`)
+pub(crate) fn mask_names(mask: u16) -> String {
+    REAL_MODIFIERS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// Return the real modifiers that the key `keycode` sets. These are the real
+/// modifiers the key is listed under in `keycodes`, the keys of each real
+/// modifier from `GetModifierMapping`. A key that is not listed sets the real
+/// modifiers of its virtual modifiers `vmod`, which are in `vmods` at the
+/// index of each virtual modifier
+pub(crate) fn real_modifiers(
+    keycode: Keycode,
+    vmod: u16,
+    keycodes: &[Keycode],
+    vmods: &[u16],
+) -> u16 {
+    let per_modifier = keycodes.len() / REAL_MODIFIERS.len();
+    let listed = keycodes
+        .chunks(per_modifier.max(1))
+        .take(REAL_MODIFIERS.len())
+        .enumerate()
+        .filter(|(_, keys)| keys.contains(&keycode))
+        .fold(0, |mask, (bit, _)| mask | (1 << bit));
+
+    if listed != 0 {
+        return listed;
+    }
+
+    vmods
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| vmod & (1 << bit) != 0)
+        .fold(0, |mask, (_, real)| mask | real)
+}
+
+// ================= ModifierMap ==================
+
+/// The real modifiers that each of the modifiers named after keys is on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ModifierMap {
+    /// The named modifiers that are on the keyboard, with their real modifiers
+    masks: Vec<(&'static str, u16)>,
+}
+
+impl ModifierMap {
+    /// Create a new `ModifierMap` from the keys of the keyboard. The `modmask`
+    /// of a key that is a modifier is the real modifiers it sets
+    pub(crate) fn new(charmaps: &[CharacterMap]) -> Self {
+        let masks = NAMED_MODIFIERS
+            .iter()
+            .filter_map(|(name, keysyms)| {
+                let mask = charmaps
+                    .iter()
+                    .filter(|c| keysyms.contains(&c.utf()))
+                    .fold(0, |mask, c| mask | c.modmask());
+                (mask != 0).then_some((*name, mask))
+            })
+            .collect();
+
+        Self { masks }
+    }
+
+    /// Return every pair of named modifiers that share a real modifier, and so
+    /// match each other's bindings
+    pub(crate) fn ambiguities(&self) -> Vec<Error> {
+        self.masks
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, (name, mask))| {
+                self.masks[idx + 1..]
+                    .iter()
+                    .filter(move |(_, other)| mask & other != 0)
+                    .map(move |(other_name, other)| {
+                        Error::Ambiguous(name, other_name, mask_names(mask & other))
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_modifiers_and_ambiguities() {
+        assert_eq!(keysyms("super"), Some(&["Super_L", "Super_R"][..]));
+        assert_eq!(keysyms("rctrl"), Some(&["Control_R"][..]));
+        assert_eq!(keysyms("control"), Some(&["Control_L", "Control_R"][..]));
+        assert_eq!(keysyms("rnumlock"), None);
+        assert_eq!(keysyms("mod4"), None);
+
+        // Two keys per modifier: `Shift_L` on shift, `Super_L` and `Hyper_L` on
+        // mod4, and `Alt_L` on mod1. `Meta_L` is only on the virtual modifier
+        // that is on mod1
+        let keycodes = [50, 0, 0, 0, 37, 0, 64, 0, 0, 0, 0, 0, 133, 207, 0, 0];
+        let vmods = [0, 0, 0, 0b1000];
+        assert_eq!(real_modifiers(133, 0, &keycodes, &vmods), 1 << 6);
+        assert_eq!(real_modifiers(207, 0b1000, &keycodes, &vmods), 1 << 6);
+        assert_eq!(real_modifiers(205, 0b1000, &keycodes, &vmods), 1 << 3);
+        assert_eq!(real_modifiers(38, 0, &keycodes, &vmods), 0);
+
+        let charmaps = [
+            ("Shift_L", 50, 1 << 0),
+            ("Super_L", 133, 1 << 6),
+            ("Hyper_L", 207, 1 << 6),
+            ("Alt_L", 64, 1 << 3),
+            ("Meta_L", 205, 1 << 3),
+            ("a", 38, 0),
+        ]
+        .into_iter()
+        .map(|(utf, code, mask)| CharacterMap::new(utf.into(), code, mask, 0, 1, 0, 0, true))
+        .collect::<Vec<_>>();
+
+        assert_eq!(ModifierMap::new(&charmaps).ambiguities(), vec![
+            Error::Ambiguous("alt", "meta", String::from("mod1")),
+            Error::Ambiguous("super", "hyper", String::from("mod4")),
+        ]);
+    }
+}
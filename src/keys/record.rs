@@ -0,0 +1,419 @@
+//! Decoding of the data intercepted by the [`record`](x11rb::protocol::record)
+//! extension. A single reply can hold any number of events, replies, and
+//! errors back to back, each in the byte order of the client they came from
+
+use super::keyboard::Keyboard;
+use anyhow::{Context, Result};
+use colored::Colorize;
+use crossbeam_channel::Sender;
+use std::borrow::Cow;
+use thiserror::Error;
+use x11rb::{
+    connection::RequestConnection,
+    protocol::{record::ConnectionExt as _, xinput, xkb, xproto, Event},
+};
+
+/// The category of a reply holding data intercepted from the X-Server
+const FROM_SERVER: u8 = 0;
+/// The category of the first reply, sent once the context is enabled
+const START_OF_DATA: u8 = 4;
+
+/// The `response_type` of a reply
+const REPLY: u8 = 0;
+/// The `response_type` of an error
+const ERROR: u8 = 1;
+/// The size of every event and error, and the minimum size of a reply
+const RECORD_SIZE: usize = 32;
+
+// =================== Error ======================
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("record data is truncated: needed {0} bytes, but only {1} remain")]
+    Truncated(usize, usize),
+    #[error("byte swapped events of type {0} are unsupported")]
+    Swapped(u8),
+}
+
+// ================== Extensions ==================
+
+/// The codes that the X-Server gave the extensions whose events can be
+/// byte swapped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Extensions {
+    /// The first event code of XKB. The kind of XKB event is the second byte
+    xkb:    Option<u8>,
+    /// The major opcode of XInput, which its generic events are sent with
+    xinput: Option<u8>,
+}
+
+impl Extensions {
+    /// Create a new `Extensions`
+    pub(crate) fn new(xkb: Option<u8>, xinput: Option<u8>) -> Self {
+        Self { xkb, xinput }
+    }
+
+    /// Find the codes of the extensions on the connection of the `keyboard`
+    pub(crate) fn query(keyboard: &Keyboard) -> Self {
+        let info = |name| {
+            keyboard
+                .connection()
+                .extension_information(name)
+                .ok()
+                .flatten()
+        };
+
+        Self {
+            xkb:    info(xkb::X11_EXTENSION_NAME).map(|info| info.first_event),
+            xinput: info(xinput::X11_EXTENSION_NAME).map(|info| info.major_opcode),
+        }
+    }
+}
+
+// =================== Record =====================
+
+/// A single item of the data intercepted by `record`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Record<'a> {
+    /// An event, in the byte order of this program
+    Event(Cow<'a, [u8]>),
+    /// A reply to a request
+    Reply(&'a [u8]),
+    /// An error. Only the first byte is in the byte order of this program
+    Error(&'a [u8]),
+}
+
+// =================== Records ====================
+
+/// An iterator over every [`Record`] in the data of a `record` reply
+#[derive(Debug, Clone)]
+pub(crate) struct Records<'a> {
+    /// The data that has not been decoded yet
+    data:       &'a [u8],
+    /// Whether the data is in the opposite byte order of this program
+    swapped:    bool,
+    /// The codes of the extensions whose events can be byte swapped
+    extensions: Extensions,
+}
+
+impl<'a> Records<'a> {
+    /// Create a new `Records` over `data`
+    pub(crate) fn new(data: &'a [u8], swapped: bool, extensions: Extensions) -> Self {
+        Self { data, swapped, extensions }
+    }
+
+    /// Return the size of the item at the start of the data. Replies and
+    /// generic events are followed by a number of 4-byte units
+    fn size(&self) -> Result<usize, Error> {
+        match self.data[0] & 0x7f {
+            REPLY | xproto::GE_GENERIC_EVENT => {
+                let bytes = self
+                    .data
+                    .get(4..8)
+                    .ok_or(Error::Truncated(8, self.data.len()))?;
+                let mut length = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                if self.swapped {
+                    length = length.swap_bytes();
+                }
+
+                Ok(RECORD_SIZE + length as usize * 4)
+            },
+            _ => Ok(RECORD_SIZE),
+        }
+    }
+
+    /// Return an event in the byte order of this program
+    fn event(&self, event: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        if !self.swapped {
+            return Ok(Cow::Borrowed(event));
+        }
+
+        match event[0] & 0x7f {
+            xproto::KEY_PRESS_EVENT..=xproto::MOTION_NOTIFY_EVENT =>
+                Ok(Cow::Owned(swap_input(event))),
+            code if Some(code) == self.extensions.xkb => swap_xkb(event).map(Cow::Owned),
+            xproto::GE_GENERIC_EVENT if Some(event[1]) == self.extensions.xinput =>
+                swap_xinput(event).map(Cow::Owned),
+            code => Err(Error::Swapped(code)),
+        }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let size = match self.size() {
+            Ok(size) if size <= self.data.len() => size,
+            result => {
+                // Nothing after a truncated item can be trusted
+                let remaining = self.data.len();
+                self.data = &[];
+                return Some(Err(
+                    result.map_or_else(|e| e, |size| Error::Truncated(size, remaining))
+                ));
+            },
+        };
+
+        let (item, rest) = self.data.split_at(size);
+        self.data = rest;
+
+        Some(match item[0] & 0x7f {
+            REPLY => Ok(Record::Reply(item)),
+            ERROR => Ok(Record::Error(item)),
+            _ => self.event(item).map(Record::Event),
+        })
+    }
+}
+
+// ================ Helper Funcs ==================
+
+/// Read the replies of the `record` context, sending every event in them to
+/// the [`Daemon`](super::daemon::Daemon). This runs on its own thread until
+/// the `Daemon` stops listening, or the connection to the X-Server is lost
+pub(crate) fn capture(keyboard: &Keyboard, sender: &Sender<Result<Event>>) {
+    let extensions = Extensions::query(keyboard);
+    let replies = match keyboard
+        .data_connection()
+        .record_enable_context(keyboard.id())
+        .context("failed to get `record_enable_context`")
+    {
+        Ok(replies) => replies,
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            return;
+        },
+    };
+
+    for reply in replies {
+        let reply = match reply.context("failed to get `record_enable_context` reply") {
+            Ok(reply) => reply,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            },
+        };
+
+        match reply.category {
+            FROM_SERVER =>
+                if !send_events(keyboard, sender, &reply.data, reply.client_swapped, extensions) {
+                    return;
+                },
+            START_OF_DATA => log::info!(
+                "{} is {}",
+                "foreground daemon".red().bold(),
+                "STARTING".green().bold()
+            ),
+            _ => log::warn!("`record` reply category is unknown: {:#?}", reply),
+        }
+    }
+}
+
+/// Decode the data of a single reply, sending every event in it. Returns
+/// whether the [`Daemon`](super::daemon::Daemon) is still listening
+fn send_events(
+    keyboard: &Keyboard,
+    sender: &Sender<Result<Event>>,
+    data: &[u8],
+    swapped: bool,
+    extensions: Extensions,
+) -> bool {
+    for record in Records::new(data, swapped, extensions) {
+        match record {
+            Ok(Record::Event(data)) => match keyboard.connection().parse_event(&data) {
+                Ok(event) =>
+                    if sender.send(Ok(event)).is_err() {
+                        return false;
+                    },
+                Err(e) => log::error!("{}: failed to parse event: {}", "record".red().bold(), e),
+            },
+            Ok(Record::Reply(data)) => {
+                log::error!("{}::UnparsedReply({:?})", "record".red().bold(), data);
+            },
+            Ok(Record::Error(data)) => {
+                log::error!("{}::UnparsedError({:?})", "record".red().bold(), data);
+            },
+            Err(e) => log::warn!("{}: {}", "record".red().bold(), e),
+        }
+    }
+
+    true
+}
+
+/// Swap the byte order of the 2-byte fields at `u16_fields` and the 4-byte
+/// fields at `u32_fields` of an event
+fn swap_fields(event: &[u8], u16_fields: &[usize], u32_fields: &[usize]) -> Vec<u8> {
+    let mut event = event.to_vec();
+    for offset in u16_fields {
+        event[*offset..*offset + 2].reverse();
+    }
+    for offset in u32_fields {
+        event[*offset..*offset + 4].reverse();
+    }
+    event
+}
+
+/// Swap the byte order of a core input event (`KeyPress`, `KeyRelease`,
+/// `ButtonPress`, `ButtonRelease`, `MotionNotify`), which all share a layout
+fn swap_input(event: &[u8]) -> Vec<u8> {
+    // The `sequence`, `root_x`, `root_y`, `event_x`, `event_y` and `state`
+    // fields, then the `time`, `root`, `event` and `child` fields
+    swap_fields(event, &[2, 20, 22, 24, 26, 28], &[4, 8, 12, 16])
+}
+
+/// Swap the byte order of the XKB events that the
+/// [`Daemon`](super::daemon::Daemon) handles, `NewKeyboardNotify` and
+/// `MapNotify`
+fn swap_xkb(event: &[u8]) -> Result<Vec<u8>, Error> {
+    match event[1] {
+        // The `sequence` and `changed` fields, then the `time` field
+        xkb::NEW_KEYBOARD_NOTIFY_EVENT => Ok(swap_fields(event, &[2, 16], &[4])),
+        // The `sequence`, `changed` and `virtual_mods` fields, then the `time`
+        // field
+        xkb::MAP_NOTIFY_EVENT => Ok(swap_fields(event, &[2, 10, 28], &[4])),
+        _ => Err(Error::Swapped(event[0] & 0x7f)),
+    }
+}
+
+/// Swap the byte order of the XInput raw key events, which are generic events
+/// followed by a list of 4-byte values
+fn swap_xinput(event: &[u8]) -> Result<Vec<u8>, Error> {
+    let event_type = u16::from_ne_bytes([event[9], event[8]]);
+    if event_type != xinput::RAW_KEY_PRESS_EVENT && event_type != xinput::RAW_KEY_RELEASE_EVENT {
+        return Err(Error::Swapped(xproto::GE_GENERIC_EVENT));
+    }
+
+    // The `sequence`, `event_type`, `deviceid`, `sourceid` and `valuators_len`
+    // fields, then the `length`, `time`, `detail` and `flags` fields, and the
+    // valuator mask and axis values that follow
+    let values = (RECORD_SIZE..event.len()).step_by(4).collect::<Vec<_>>();
+    let mut event = swap_fields(event, &[2, 8, 10, 20, 22], &[4, 12, 16, 24]);
+    event = swap_fields(&event, &[], &values);
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::x11_utils::TryParse;
+
+    /// Build a key press event in native byte order
+    fn key_press(detail: u8, time: u32, state: u16) -> Vec<u8> {
+        let mut event = vec![0; RECORD_SIZE];
+        event[0] = xproto::KEY_PRESS_EVENT;
+        event[1] = detail;
+        event[4..8].copy_from_slice(&time.to_ne_bytes());
+        event[28..30].copy_from_slice(&state.to_ne_bytes());
+        event
+    }
+
+    #[test]
+    fn records_walks_every_item() {
+        let mut reply = vec![0; RECORD_SIZE + 8];
+        reply[4..8].copy_from_slice(&2_u32.to_ne_bytes());
+
+        let mut data = key_press(38, 100, 0);
+        data.extend(&reply);
+        data.extend(key_press(39, 200, 4));
+
+        let records = Records::new(&data, false, Extensions::default())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1], Record::Reply(&reply));
+
+        match &records[2] {
+            Record::Event(event) => {
+                let (event, _) = xproto::KeyPressEvent::try_parse(event).unwrap();
+                assert_eq!((event.detail, event.time, event.state), (39, 200, 4));
+            },
+            other => panic!("expected an event, found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn records_swaps_byte_order() {
+        let mut data = key_press(38, 0x0102_0304, 0x0008);
+        data[4..8].reverse();
+        data[28..30].reverse();
+
+        let mut records = Records::new(&data, true, Extensions::default());
+        match records.next() {
+            Some(Ok(Record::Event(event))) => {
+                let (event, _) = xproto::KeyPressEvent::try_parse(&event).unwrap();
+                assert_eq!((event.detail, event.time, event.state), (38, 0x0102_0304, 0x0008));
+            },
+            other => panic!("expected an event, found {other:?}"),
+        }
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn records_swaps_extension_events() {
+        const XKB: u8 = 85;
+        const XINPUT: u8 = 131;
+
+        let mut map = vec![0; RECORD_SIZE];
+        map[0] = XKB;
+        map[1] = xkb::MAP_NOTIFY_EVENT;
+        map[4..8].copy_from_slice(&0x0102_0304_u32.swap_bytes().to_ne_bytes());
+        map[10..12].copy_from_slice(&0x0005_u16.swap_bytes().to_ne_bytes());
+
+        let mut raw = vec![0; RECORD_SIZE + 4];
+        raw[0] = xproto::GE_GENERIC_EVENT;
+        raw[1] = XINPUT;
+        raw[4..8].copy_from_slice(&1_u32.swap_bytes().to_ne_bytes());
+        raw[8..10].copy_from_slice(&xinput::RAW_KEY_PRESS_EVENT.swap_bytes().to_ne_bytes());
+        raw[10..12].copy_from_slice(&14_u16.swap_bytes().to_ne_bytes());
+        raw[12..16].copy_from_slice(&0x0506_0708_u32.swap_bytes().to_ne_bytes());
+        raw[16..20].copy_from_slice(&38_u32.swap_bytes().to_ne_bytes());
+        raw[20..22].copy_from_slice(&15_u16.swap_bytes().to_ne_bytes());
+        raw[22..24].copy_from_slice(&1_u16.swap_bytes().to_ne_bytes());
+
+        let mut data = map;
+        data.extend(&raw);
+
+        let extensions = Extensions::new(Some(XKB), Some(XINPUT));
+        let records = Records::new(&data, true, extensions)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+
+        match &records[0] {
+            Record::Event(event) => {
+                let (event, _) = xkb::MapNotifyEvent::try_parse(event).unwrap();
+                assert_eq!((event.time, event.changed), (0x0102_0304, 0x0005));
+            },
+            other => panic!("expected an event, found {other:?}"),
+        }
+        match &records[1] {
+            Record::Event(event) => {
+                let (event, _) = xinput::RawKeyPressEvent::try_parse(event).unwrap();
+                assert_eq!(
+                    (event.deviceid, event.time, event.detail, event.sourceid),
+                    (14, 0x0506_0708, 38, 15)
+                );
+                assert_eq!(event.valuator_mask, vec![0]);
+            },
+            other => panic!("expected an event, found {other:?}"),
+        }
+
+        // Without the codes of the extensions, their events cannot be decoded
+        let mut records = Records::new(&data, true, Extensions::default());
+        assert!(matches!(records.next(), Some(Err(Error::Swapped(XKB)))));
+    }
+
+    #[test]
+    fn records_stops_when_truncated() {
+        let mut data = key_press(38, 100, 0);
+        data.truncate(20);
+
+        let mut records = Records::new(&data, false, Extensions::default());
+        assert!(matches!(records.next(), Some(Err(Error::Truncated(32, 20)))));
+        assert!(records.next().is_none());
+    }
+}
@@ -4,17 +4,16 @@ use super::{
     chord::{Chain, Chord},
     keys::{ButtonCode, CharacterMap},
 };
-use crate::{
-    config::Config,
-    parse::parser::{TokenizedLine, MOD_PATTERN},
-};
+use crate::{config::Config, parse::parser};
 use anyhow::{Context, Result};
 use colored::Colorize;
 use indexmap::IndexMap;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     sync::atomic::{AtomicBool, AtomicU8, Ordering},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 use x11rb::protocol::xproto::{self, Keycode, Keysym, Timestamp};
 
@@ -52,8 +51,8 @@ pub(crate) struct RemapKeyState {
 
 impl RemapKeyState {
     /// Create an `RemapKeyState` from 2 [`Chain`](crate::keys::chord::Chain).
-    /// `Chain`s are used since `TokenizedLine` already parses the
-    /// configuration into `Chain`s
+    /// `Chain`s are used since the [`parser`](crate::parse::parser) already
+    /// parses the configuration into `Chain`s
     pub(crate) fn from_chains(from: &Chain, to: &Chain) -> Option<Self> {
         if from.chords().is_empty() || to.chords().is_empty() {
             return None;
@@ -68,7 +67,7 @@ impl RemapKeyState {
             is_used:      AtomicBool::new(
                 to.chords()
                     .iter()
-                    .any(|c| MOD_PATTERN.is_match(c.charmap().utf())),
+                    .any(|c| parser::is_modifier(c.charmap().utf())),
             ),
         })
     }
@@ -113,6 +112,50 @@ impl RemapKeyState {
     // }
 }
 
+// =================== HeldKey ====================
+
+/// A key event that was held back, or that is sent by this program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HeldKey {
+    /// The key of the event
+    pub(crate) keycode: Keycode,
+    /// Whether the event was a press or a release
+    pub(crate) pressed: bool,
+}
+
+impl HeldKey {
+    /// Create a key press
+    pub(crate) const fn press(keycode: Keycode) -> Self {
+        Self { keycode, pressed: true }
+    }
+
+    /// Create a key release
+    pub(crate) const fn release(keycode: Keycode) -> Self {
+        Self { keycode, pressed: false }
+    }
+}
+
+// ================= RemapOutput ==================
+
+/// What should be done with a key event after [`TapHoldState`] or
+/// [`OneShotState`] has seen it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RemapOutput {
+    /// The event has nothing to do with the key
+    Pass,
+    /// The event is held back until the tap-hold key is decided
+    Wait,
+    /// The event is dropped
+    Swallow,
+    /// The key was released. This is resolved a short time later with
+    /// `finish`, since autorepeat sends a release along with a press
+    Defer,
+    /// These events are sent in place of the ones that were held back
+    Send(Vec<HeldKey>),
+    /// These events are sent after the event, which is processed as usual
+    Follow(Vec<HeldKey>),
+}
+
 // ================== RemapState ==================
 
 /// The overall state of remapped keys
@@ -122,6 +165,15 @@ pub(crate) struct RemapState {
     mouse_held:    AtomicBool,
     /// The keys that are remapped when pressed
     remapped_keys: Vec<RemapKeyState>,
+    /// The index into `remapped_keys` of the first remap to each `Keycode`
+    to_index:      HashMap<Keycode, usize>,
+    /// The keys that act differently when tapped and held
+    tap_holds:     TapHoldState,
+    /// The keys that apply a modifier to the next key only
+    one_shots:     OneShotState,
+    /// The number of events sent again by this program for each key that have
+    /// not been seen yet
+    replayed:      HashMap<Keycode, usize>,
 }
 
 impl RemapState {
@@ -142,12 +194,96 @@ impl RemapState {
 
     /// Insert a `RemapKeyState` into the inner vector
     pub(crate) fn insert(&mut self, remapped: RemapKeyState) {
+        let idx = self.remapped_keys.len();
+        for to in remapped.to_keys() {
+            self.to_index.entry(to.charmap().code()).or_insert(idx);
+        }
+
         self.remapped_keys.push(remapped);
     }
 
+    /// Return every `RemapKeyState` that remaps `key`. There is more than one
+    /// when they are limited to different keyboards
+    fn remaps_from(&self, key: Keycode) -> impl Iterator<Item = &RemapKeyState> {
+        self.remapped_keys
+            .iter()
+            .filter(move |map| map.from_key().charmap().code() == key)
+    }
+
+    /// Return the first `RemapKeyState` that `key` is remapped to
+    fn remap_to(&self, key: Keycode) -> Option<&RemapKeyState> {
+        self.to_index
+            .get(&key)
+            .and_then(|idx| self.remapped_keys.get(*idx))
+    }
+
     /// Return whether there are any remapped keys
     pub(crate) fn is_empty(&self) -> bool {
         self.remapped_keys.is_empty()
+            && self.tap_holds.keys.is_empty()
+            && self.one_shots.is_empty()
+    }
+
+    /// Return the keys that act differently when tapped and held
+    pub(crate) fn tap_holds(&self) -> &TapHoldState {
+        &self.tap_holds
+    }
+
+    /// Return the keys that act differently when tapped and held, mutably
+    pub(crate) fn tap_holds_mut(&mut self) -> &mut TapHoldState {
+        &mut self.tap_holds
+    }
+
+    /// Return the keys that apply a modifier to the next key only
+    pub(crate) fn one_shots(&self) -> &OneShotState {
+        &self.one_shots
+    }
+
+    /// Return the keys that apply a modifier to the next key only, mutably
+    pub(crate) fn one_shots_mut(&mut self) -> &mut OneShotState {
+        &mut self.one_shots
+    }
+
+    /// Forget the keys that are down or were held back, along with the tap-hold
+    /// and one-shot keys that are in progress
+    pub(crate) fn reset(&mut self) {
+        self.tap_holds.reset();
+        self.one_shots.reset();
+        self.replayed.clear();
+    }
+
+    /// Record that `key` is about to be sent again by this program, after it
+    /// was held back
+    pub(crate) fn mark_replayed(&mut self, key: Keycode) {
+        *self.replayed.entry(key).or_insert(0) += 1;
+    }
+
+    /// Return whether an event for `key` is expected to be sent again by this
+    /// program, without marking it as seen
+    pub(crate) fn is_replayed(&self, key: Keycode) -> bool {
+        self.replayed.contains_key(&key)
+    }
+
+    /// Return whether a tap-hold or one-shot key is in progress. The keys
+    /// that were pressed by this program are meant to stay down meanwhile
+    pub(crate) fn is_active(&self) -> bool {
+        self.tap_holds.active.is_some() || self.one_shots.is_active()
+    }
+
+    /// Check whether the event for `key` was sent again by this program. If
+    /// so, it is no longer expected
+    pub(crate) fn check_if_replayed(&mut self, key: Keycode) -> bool {
+        match self.replayed.get_mut(&key) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                true
+            },
+            Some(_) => {
+                self.replayed.remove(&key);
+                true
+            },
+            None => false,
+        }
     }
 
     /// Set the `mouse_held` field
@@ -163,10 +299,8 @@ impl RemapState {
 
     /// Change inner state to pressed
     pub(crate) fn mark_pressed(&mut self, key: Keycode) -> Option<bool> {
-        self.remapped_keys
-            .iter()
-            .find(|m| m.from_key().charmap().code() == key)
-            .map(|ref mut map| {
+        self.remaps_from(key)
+            .map(|map| {
                 let old = &map.pressed;
                 map.pressed.store(true, Ordering::Relaxed);
 
@@ -181,15 +315,14 @@ impl RemapState {
 
                 old.load(Ordering::Relaxed)
             })
+            .reduce(|first, _| first)
     }
 
     /// Mark keys that have already been marked as `pressed` as no longer being
     /// `pressed`
     pub(crate) fn mark_released(&mut self, key: Keycode) -> Option<bool> {
-        self.remapped_keys
-            .iter()
-            .find(|m| m.from_key().charmap().code() == key)
-            .map(|ref mut map| {
+        self.remaps_from(key)
+            .map(|map| {
                 let (old_pressed, old_used) = (&map.pressed, &map.is_used);
                 map.pressed.store(false, Ordering::Relaxed);
                 map.is_used.store(false, Ordering::Relaxed);
@@ -209,6 +342,7 @@ impl RemapState {
                 // old_used.load(Ordering::Relaxed),
                 // )
             })
+            .reduce(|first, _| first)
     }
 
     /// Mark all keys that are currently held as a modifier. Or if the key is
@@ -230,11 +364,7 @@ impl RemapState {
 
     /// Mark the keys that are created by this program as being `generated`
     pub(crate) fn mark_generated(&mut self, key: Keycode) {
-        if let Some(ref mut map) = &mut self
-            .remapped_keys
-            .iter()
-            .find(|m| m.to_keys().iter().any(|to| to.charmap().code() == key))
-        {
+        if let Some(map) = self.remap_to(key) {
             log::debug!(
                 "{}: changing generated to true for {}",
                 "remap".red().bold(),
@@ -248,11 +378,7 @@ impl RemapState {
     /// Check if the key has been `generated`. If so, change it to not
     /// being `generated`
     pub(crate) fn check_if_generated(&mut self, key: Keycode) -> bool {
-        if let Some(ref mut map) = &mut self
-            .remapped_keys
-            .iter()
-            .find(|m| m.to_keys().iter().any(|to| to.charmap().code() == key))
-        {
+        if let Some(map) = self.remap_to(key) {
             log::debug!(
                 "{}: changing generated to false for {}",
                 "remap".red().bold(),
@@ -265,3 +391,610 @@ impl RemapState {
         }
     }
 }
+
+// =================== Strategy ===================
+
+/// How a key that is different when tapped and held decides which one it is,
+/// when another key is pressed before the key is released
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Strategy {
+    /// The key is held as soon as another key is pressed
+    #[serde(alias = "hold_on_other_key_press")]
+    HoldOnOtherKeyPress,
+    /// The key is held once another key is pressed and released while the
+    /// key is still down
+    #[serde(alias = "permissive_hold")]
+    PermissiveHold,
+    /// The key is held only once it has been down for the tapping term
+    #[serde(alias = "tapping_term")]
+    #[default]
+    TappingTerm,
+}
+
+// ================== TapHoldKey ==================
+
+/// The default number of milliseconds a key has to be down to be held
+pub(crate) const DEFAULT_TAPPING_TERM: u32 = 200;
+
+/// A key that is one key when tapped and another when held (e.g., `a` when
+/// tapped and `Super_L` when held)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TapHoldKey {
+    /// The key as it was written in the configuration
+    source:    String,
+    /// The key that is pressed
+    key:       Keycode,
+    /// The key that is sent when `key` is tapped
+    tap:       Keycode,
+    /// The key that is sent while `key` is held
+    hold:      Keycode,
+    /// How a tap is told apart from a hold
+    strategy:  Strategy,
+    /// The number of milliseconds `key` has to be down to be held
+    term:      u32,
+    /// Pressing `key` again within this many milliseconds of a tap is always
+    /// a tap, so that the tap key can be repeated
+    quick_tap: Option<u32>,
+}
+
+impl TapHoldKey {
+    /// Create a new `TapHoldKey`
+    pub(crate) fn new(
+        source: &str,
+        key: Keycode,
+        tap: Keycode,
+        hold: Keycode,
+        strategy: Strategy,
+        term: u32,
+        quick_tap: Option<u32>,
+    ) -> Self {
+        Self {
+            source: source.to_string(),
+            key,
+            tap,
+            hold,
+            strategy,
+            term,
+            quick_tap,
+        }
+    }
+
+    /// Return the key that is pressed
+    pub(crate) fn key(&self) -> Keycode {
+        self.key
+    }
+
+    /// Return the amount of time the key has to be down to be held
+    pub(crate) fn duration(&self) -> Duration {
+        Duration::from_millis(u64::from(self.term))
+    }
+}
+
+/// Whether a tap-hold key was tapped or held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    /// The key was tapped
+    Tap,
+    /// The key was held
+    Hold,
+}
+
+/// A tap-hold key that is currently down
+#[derive(Debug, Clone)]
+struct ActiveTapHold {
+    /// Index of the key in [`TapHoldState`]
+    idx:      usize,
+    /// The time the key was pressed
+    pressed:  Timestamp,
+    /// The time the key was released, while it is unknown whether the release
+    /// was from autorepeat
+    released: Option<Timestamp>,
+    /// Whether the key was tapped or held, once it is known
+    decision: Option<Decision>,
+    /// Events of other keys that are held back until the decision is made
+    held:     Vec<HeldKey>,
+    /// Other keys that were pressed after this key
+    others:   HashSet<Keycode>,
+}
+
+// ================= TapHoldState =================
+
+/// Every tap-hold key, along with the one that is currently down
+#[derive(Debug, Default)]
+pub(crate) struct TapHoldState {
+    /// Every tap-hold key in the configuration file
+    keys:     Vec<TapHoldKey>,
+    /// The tap-hold key that is currently down
+    active:   Option<ActiveTapHold>,
+    /// The time each tap-hold key was last tapped
+    last_tap: HashMap<Keycode, Timestamp>,
+}
+
+impl TapHoldState {
+    /// Add a `TapHoldKey`
+    pub(crate) fn insert(&mut self, key: TapHoldKey) {
+        self.keys.push(key);
+    }
+
+    /// Return every key that acts differently when tapped and held. These are
+    /// the keys that have to be grabbed, so that they can be held back
+    pub(crate) fn keys(&self) -> HashSet<Keycode> {
+        self.keys.iter().map(|k| k.key).collect()
+    }
+
+    /// Forget the active key and when each key was last tapped
+    pub(crate) fn reset(&mut self) {
+        self.active = None;
+        self.last_tap.clear();
+    }
+
+    /// Return the amount of time the active key has to be down to be held
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        self.active
+            .as_ref()
+            .map(|active| self.keys[active.idx].duration())
+    }
+
+    /// Decide that the active key is held, sending the held key along with
+    /// the events that were held back
+    fn hold(&mut self) -> RemapOutput {
+        if let Some(active) = &mut self.active {
+            let th = &self.keys[active.idx];
+            log::debug!("{}: holding {}", "tap-hold".red().bold(), th.source.purple().bold());
+
+            active.decision = Some(Decision::Hold);
+            let mut keys = vec![HeldKey::press(th.hold)];
+            keys.append(&mut active.held);
+            RemapOutput::Send(keys)
+        } else {
+            RemapOutput::Pass
+        }
+    }
+
+    /// Handle a key press
+    pub(crate) fn press(&mut self, key: Keycode, time: Timestamp) -> RemapOutput {
+        if let Some(active) = &mut self.active {
+            let th = &self.keys[active.idx];
+
+            if key == th.key {
+                // Autorepeat sends a release and a press at the same time
+                if active.released.take().is_some() && active.decision == Some(Decision::Tap) {
+                    return RemapOutput::Send(vec![HeldKey::press(th.tap)]);
+                }
+                return RemapOutput::Swallow;
+            }
+
+            if active.decision.is_some() {
+                return RemapOutput::Pass;
+            }
+
+            active.held.push(HeldKey::press(key));
+            active.others.insert(key);
+
+            if th.strategy == Strategy::HoldOnOtherKeyPress {
+                return self.hold();
+            }
+            return RemapOutput::Wait;
+        }
+
+        if let Some(idx) = self.keys.iter().position(|k| k.key == key) {
+            let th = &self.keys[idx];
+            let quick_tap = th.quick_tap.is_some_and(|window| {
+                self.last_tap
+                    .get(&key)
+                    .is_some_and(|last| time.wrapping_sub(*last) <= window)
+            });
+
+            self.active = Some(ActiveTapHold {
+                idx,
+                pressed: time,
+                released: None,
+                decision: quick_tap.then_some(Decision::Tap),
+                held: Vec::new(),
+                others: HashSet::new(),
+            });
+
+            if quick_tap {
+                return RemapOutput::Send(vec![HeldKey::press(th.tap)]);
+            }
+            return RemapOutput::Wait;
+        }
+
+        RemapOutput::Pass
+    }
+
+    /// Handle a key release
+    pub(crate) fn release(&mut self, key: Keycode, time: Timestamp) -> RemapOutput {
+        if let Some(active) = &mut self.active {
+            let th = &self.keys[active.idx];
+
+            if key == th.key {
+                active.released = Some(time);
+                return RemapOutput::Defer;
+            }
+
+            if active.decision.is_some() {
+                return RemapOutput::Pass;
+            }
+
+            active.held.push(HeldKey::release(key));
+
+            if th.strategy == Strategy::PermissiveHold && active.others.contains(&key) {
+                return self.hold();
+            }
+            return RemapOutput::Wait;
+        }
+
+        RemapOutput::Pass
+    }
+
+    /// Resolve a release of the active key that was not from autorepeat
+    pub(crate) fn finish(&mut self) -> RemapOutput {
+        let active = match self.active.take() {
+            Some(active) if active.released.is_some() => active,
+            active => {
+                self.active = active;
+                return RemapOutput::Pass;
+            },
+        };
+
+        let th = &self.keys[active.idx];
+        let released = active.released.unwrap_or(active.pressed);
+
+        let decision = active.decision.unwrap_or_else(|| {
+            if released.wrapping_sub(active.pressed) >= th.term {
+                Decision::Hold
+            } else {
+                Decision::Tap
+            }
+        });
+
+        let key = match decision {
+            Decision::Tap => {
+                self.last_tap.insert(th.key, released);
+                th.tap
+            },
+            Decision::Hold => th.hold,
+        };
+
+        let mut keys = Vec::new();
+        if active.decision.is_none() {
+            keys.push(HeldKey::press(key));
+            keys.extend(active.held);
+        }
+        keys.push(HeldKey::release(key));
+
+        RemapOutput::Send(keys)
+    }
+
+    /// Handle the tapping term passing while the active key is still down
+    pub(crate) fn expire(&mut self) -> RemapOutput {
+        match &self.active {
+            Some(active) if active.decision.is_none() && active.released.is_none() =>
+                self.hold(),
+            _ => RemapOutput::Pass,
+        }
+    }
+}
+
+// ================== OneShotKey ==================
+
+/// A key that applies a modifier to the next key only when tapped. Tapping it
+/// twice locks the modifier, and a third tap releases it. When the key is
+/// held along with other keys it acts as a normal modifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OneShotKey {
+    /// The key as it was written in the configuration
+    source:   String,
+    /// The key that is pressed
+    key:      Keycode,
+    /// The modifier that is applied
+    modifier: Keycode,
+}
+
+impl OneShotKey {
+    /// Create a new `OneShotKey`
+    pub(crate) fn new(source: &str, key: Keycode, modifier: Keycode) -> Self {
+        Self {
+            source: source.to_string(),
+            key,
+            modifier,
+        }
+    }
+}
+
+/// How far along a one-shot key is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Sticky {
+    /// The modifier is not applied, unless the key is down
+    #[default]
+    Idle,
+    /// The key was tapped, and the modifier is applied to the next key
+    Pending,
+    /// The key was tapped twice, and the modifier stays applied
+    Locked,
+}
+
+/// The state of a single one-shot key
+#[derive(Debug, Clone, Default)]
+struct OneShotKeyState {
+    /// How far along the key is
+    sticky:   Sticky,
+    /// Whether the key is down
+    down:     bool,
+    /// Whether another key was pressed while this key was down
+    used:     bool,
+    /// The time the key was released, while it is unknown whether the release
+    /// was from autorepeat
+    released: Option<Timestamp>,
+}
+
+// ================= OneShotState =================
+
+/// Every one-shot key, along with how far along each one is
+#[derive(Debug, Default)]
+pub(crate) struct OneShotState {
+    /// Every one-shot key in the configuration file
+    keys:   Vec<OneShotKey>,
+    /// The state of each key in `keys`
+    states: Vec<OneShotKeyState>,
+}
+
+impl OneShotState {
+    /// Add a `OneShotKey`
+    pub(crate) fn insert(&mut self, key: OneShotKey) {
+        self.keys.push(key);
+        self.states.push(OneShotKeyState::default());
+    }
+
+    /// Return whether there are any one-shot keys
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Return every one-shot key. These are the keys that have to be grabbed
+    pub(crate) fn keys(&self) -> HashSet<Keycode> {
+        self.keys.iter().map(|k| k.key).collect()
+    }
+
+    /// Return whether any one-shot key is down, or was tapped and is applying
+    /// its modifier
+    pub(crate) fn is_active(&self) -> bool {
+        self.states
+            .iter()
+            .any(|state| state.down || state.sticky != Sticky::Idle)
+    }
+
+    /// Put every one-shot key back to not being tapped
+    pub(crate) fn reset(&mut self) {
+        self.states.fill_with(OneShotKeyState::default);
+    }
+
+    /// Handle a key press
+    pub(crate) fn press(&mut self, key: Keycode, time: Timestamp) -> RemapOutput {
+        if let Some(idx) = self.keys.iter().position(|k| k.key == key) {
+            let (one_shot, state) = (&self.keys[idx], &mut self.states[idx]);
+            let mut keys = Vec::new();
+
+            match state.released.take() {
+                // Autorepeat sends a release and a press at the same time
+                Some(released) if released == time => return RemapOutput::Swallow,
+                // Tapped again before the last release was resolved
+                Some(_) => keys.extend(Self::resolve(one_shot, state)),
+                None if state.down => return RemapOutput::Swallow,
+                None => {},
+            }
+
+            state.down = true;
+            state.used = false;
+
+            if state.sticky == Sticky::Idle {
+                keys.push(HeldKey::press(one_shot.modifier));
+            }
+
+            return if keys.is_empty() {
+                RemapOutput::Swallow
+            } else {
+                RemapOutput::Send(keys)
+            };
+        }
+
+        // Any other key is the one the pending modifiers are applied to
+        let mut keys = Vec::new();
+        for (one_shot, state) in self.keys.iter().zip(self.states.iter_mut()) {
+            if state.down {
+                state.used = true;
+            } else if state.sticky == Sticky::Pending {
+                log::debug!(
+                    "{}: applied {}",
+                    "one-shot".red().bold(),
+                    one_shot.source.purple().bold()
+                );
+                state.sticky = Sticky::Idle;
+                keys.push(HeldKey::release(one_shot.modifier));
+            }
+        }
+
+        if keys.is_empty() {
+            RemapOutput::Pass
+        } else {
+            RemapOutput::Follow(keys)
+        }
+    }
+
+    /// Handle a key release
+    pub(crate) fn release(&mut self, key: Keycode, time: Timestamp) -> RemapOutput {
+        match self.keys.iter().position(|k| k.key == key) {
+            Some(idx) if self.states[idx].down => {
+                self.states[idx].released = Some(time);
+                RemapOutput::Defer
+            },
+            Some(_) => RemapOutput::Swallow,
+            None => RemapOutput::Pass,
+        }
+    }
+
+    /// Resolve the releases of one-shot keys that were not from autorepeat
+    pub(crate) fn finish(&mut self) -> RemapOutput {
+        let mut keys = Vec::new();
+
+        for (one_shot, state) in self.keys.iter().zip(self.states.iter_mut()) {
+            if state.released.take().is_some() {
+                keys.extend(Self::resolve(one_shot, state));
+            }
+        }
+
+        if keys.is_empty() {
+            RemapOutput::Swallow
+        } else {
+            RemapOutput::Send(keys)
+        }
+    }
+
+    /// Resolve the release of a one-shot key, moving on to its next state.
+    /// Returns the release of its modifier once it is no longer applied
+    fn resolve(one_shot: &OneShotKey, state: &mut OneShotKeyState) -> Option<HeldKey> {
+        state.down = false;
+
+        state.sticky = match state.sticky {
+            _ if state.used => Sticky::Idle,
+            Sticky::Idle => Sticky::Pending,
+            Sticky::Pending => Sticky::Locked,
+            Sticky::Locked => Sticky::Idle,
+        };
+
+        log::debug!(
+            "{}: {} is {:?}",
+            "one-shot".red().bold(),
+            one_shot.source.purple().bold(),
+            state.sticky
+        );
+
+        (state.sticky == Sticky::Idle).then(|| HeldKey::release(one_shot.modifier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Keycode = 38;
+    const J: Keycode = 44;
+    const SUPER: Keycode = 133;
+
+    fn state(strategy: Strategy) -> TapHoldState {
+        let mut state = TapHoldState::default();
+        state.insert(TapHoldKey::new("a", A, A, SUPER, strategy, 200, Some(150)));
+        state
+    }
+
+    #[test]
+    fn tap_hold_tapping_term() {
+        let mut state = state(Strategy::TappingTerm);
+
+        // Rolling over another key within the tapping term is a tap
+        assert_eq!(state.press(A, 0), RemapOutput::Wait);
+        assert_eq!(state.press(J, 50), RemapOutput::Wait);
+        assert_eq!(state.release(A, 80), RemapOutput::Defer);
+        assert_eq!(
+            state.finish(),
+            RemapOutput::Send(vec![
+                HeldKey::press(A),
+                HeldKey::press(J),
+                HeldKey::release(A)
+            ])
+        );
+        assert_eq!(state.release(J, 90), RemapOutput::Pass);
+
+        // Quick tap, with autorepeat of the tap key
+        assert_eq!(state.press(A, 150), RemapOutput::Send(vec![HeldKey::press(A)]));
+        assert_eq!(state.release(A, 600), RemapOutput::Defer);
+        assert_eq!(state.press(A, 600), RemapOutput::Send(vec![HeldKey::press(A)]));
+        assert_eq!(state.finish(), RemapOutput::Pass);
+        assert_eq!(state.release(A, 700), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Send(vec![HeldKey::release(A)]));
+
+        // Held past the tapping term
+        assert_eq!(state.press(A, 1000), RemapOutput::Wait);
+        assert_eq!(state.expire(), RemapOutput::Send(vec![HeldKey::press(SUPER)]));
+        assert_eq!(state.press(J, 1300), RemapOutput::Pass);
+        assert_eq!(state.release(A, 1400), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Send(vec![HeldKey::release(SUPER)]));
+    }
+
+    #[test]
+    fn tap_hold_hold_on_other_key_press() {
+        let mut state = state(Strategy::HoldOnOtherKeyPress);
+
+        assert_eq!(state.press(A, 0), RemapOutput::Wait);
+        assert_eq!(
+            state.press(J, 50),
+            RemapOutput::Send(vec![HeldKey::press(SUPER), HeldKey::press(J)])
+        );
+        assert_eq!(state.release(J, 60), RemapOutput::Pass);
+    }
+
+    #[test]
+    fn one_shot_applies_and_locks() {
+        const SHIFT: Keycode = 50;
+        let mut state = OneShotState::default();
+        state.insert(OneShotKey::new("Shift_L", SHIFT, SHIFT));
+
+        // Tapped, then applied to the next key only
+        assert_eq!(state.press(SHIFT, 0), RemapOutput::Send(vec![HeldKey::press(SHIFT)]));
+        assert_eq!(state.release(SHIFT, 50), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Swallow);
+        assert_eq!(state.press(A, 100), RemapOutput::Follow(vec![HeldKey::release(SHIFT)]));
+        assert_eq!(state.press(J, 200), RemapOutput::Pass);
+
+        // Held along with another key like a normal modifier
+        assert_eq!(state.press(SHIFT, 300), RemapOutput::Send(vec![HeldKey::press(SHIFT)]));
+        assert_eq!(state.press(A, 350), RemapOutput::Pass);
+        assert_eq!(state.release(SHIFT, 400), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Send(vec![HeldKey::release(SHIFT)]));
+
+        // Locked by a double tap and released by a third
+        assert_eq!(state.press(SHIFT, 500), RemapOutput::Send(vec![HeldKey::press(SHIFT)]));
+        assert_eq!(state.release(SHIFT, 510), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Swallow);
+        assert_eq!(state.press(SHIFT, 520), RemapOutput::Swallow);
+        assert_eq!(state.release(SHIFT, 530), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Swallow);
+        assert_eq!(state.press(A, 600), RemapOutput::Pass);
+        assert!(state.is_active());
+        assert_eq!(state.press(SHIFT, 700), RemapOutput::Swallow);
+        assert_eq!(state.release(SHIFT, 710), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Send(vec![HeldKey::release(SHIFT)]));
+        assert!(!state.is_active());
+
+        // Autorepeat is swallowed, but a quick tap again locks it
+        assert_eq!(state.press(SHIFT, 800), RemapOutput::Send(vec![HeldKey::press(SHIFT)]));
+        assert_eq!(state.release(SHIFT, 810), RemapOutput::Defer);
+        assert_eq!(state.press(SHIFT, 810), RemapOutput::Swallow);
+        assert_eq!(state.release(SHIFT, 820), RemapOutput::Defer);
+        assert_eq!(state.press(SHIFT, 825), RemapOutput::Swallow);
+        assert_eq!(state.release(SHIFT, 830), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Swallow);
+        assert_eq!(state.press(A, 900), RemapOutput::Pass);
+        assert!(state.is_active());
+    }
+
+    #[test]
+    fn tap_hold_permissive_hold() {
+        let mut state = state(Strategy::PermissiveHold);
+
+        assert_eq!(state.press(A, 0), RemapOutput::Wait);
+        assert_eq!(state.press(J, 50), RemapOutput::Wait);
+        assert_eq!(
+            state.release(J, 60),
+            RemapOutput::Send(vec![
+                HeldKey::press(SUPER),
+                HeldKey::press(J),
+                HeldKey::release(J)
+            ])
+        );
+        assert_eq!(state.release(A, 70), RemapOutput::Defer);
+        assert_eq!(state.finish(), RemapOutput::Send(vec![HeldKey::release(SUPER)]));
+    }
+}
@@ -0,0 +1,103 @@
+//! Deadlines that the [`Daemon`](super::daemon::Daemon) wakes up for while it
+//! is waiting on events from the X-Server
+
+use std::time::{Duration, Instant};
+use x11rb::protocol::xproto::Keycode;
+
+// ==================== Timer =====================
+
+/// Something that has to be done once a deadline has passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timer {
+    /// A long-press binding has been held down for its `hold_time`
+    Hold(Keycode),
+    /// A long-press binding was released. This is delayed slightly, since
+    /// autorepeat sends a release and a press for a key that is still held
+    Release(Keycode),
+    /// No further tap of a multi-tap binding was seen within its `tap_window`
+    Taps(Keycode),
+    /// The rest of a combo was not pressed within `combo_timeout`
+    Combo,
+    /// A tap-hold key has been down for its `tapping_term`
+    TapHold,
+    /// A tap-hold key was released. This is delayed slightly, like `Release`
+    TapHoldRelease,
+    /// A one-shot key was released. This is delayed slightly, like `Release`
+    OneShotRelease,
+    /// No key of a leader sequence was typed within `leader_timeout`
+    Leader,
+    /// The user has not been pressing any key for a while, so any key that is
+    /// still down was pressed by this program and is stuck
+    Stuck,
+    /// The keymap changed. This is delayed slightly, since a single change
+    /// (e.g., `setxkbmap`) sends several events
+    Keymap,
+    /// A key event has been waiting for the raw event that says which keyboard
+    /// it came from for `SOURCE_GRACE`
+    Source,
+}
+
+// =================== Timers =====================
+
+/// The set of pending [`Timer`]s
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    /// Each `Timer` along with the time that it fires at
+    pending: Vec<(Instant, Timer)>,
+}
+
+impl Timers {
+    /// Create an empty set of `Timers`
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fire `timer` after `after` has elapsed. An identical `Timer` that is
+    /// already pending is replaced
+    pub(crate) fn schedule(&mut self, after: Duration, timer: Timer) {
+        self.cancel(timer);
+        self.pending.push((Instant::now() + after, timer));
+    }
+
+    /// Remove a pending `Timer`. Returns whether anything was removed
+    pub(crate) fn cancel(&mut self, timer: Timer) -> bool {
+        let len = self.pending.len();
+        self.pending.retain(|(_, t)| *t != timer);
+        len != self.pending.len()
+    }
+
+    /// Return whether `timer` is pending
+    pub(crate) fn contains(&self, timer: Timer) -> bool {
+        self.pending.iter().any(|(_, t)| *t == timer)
+    }
+
+    /// Return the earliest deadline, if there is one
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().map(|(at, _)| *at).min()
+    }
+
+    /// Remove and return every `Timer` whose deadline is at or before `now`,
+    /// in the order that they expired
+    pub(crate) fn expired(&mut self, now: Instant) -> Vec<Timer> {
+        let mut expired = self
+            .pending
+            .iter()
+            .filter(|(at, _)| *at <= now)
+            .copied()
+            .collect::<Vec<_>>();
+        self.pending.retain(|(at, _)| *at > now);
+
+        expired.sort_by_key(|(at, _)| *at);
+        expired.into_iter().map(|(_, t)| t).collect()
+    }
+
+    /// Remove every pending `Timer`
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Return whether there are no pending `Timer`s
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
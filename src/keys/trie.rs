@@ -0,0 +1,120 @@
+//! A prefix tree, used to match sequences of keys one key at a time
+
+use std::{collections::HashMap, hash::Hash};
+
+// ==================== Lookup ====================
+
+/// The result of looking up a sequence in a [`Trie`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lookup<'a, V> {
+    /// Nothing starts with the sequence
+    None,
+    /// The sequence is the start of one or more longer sequences
+    Prefix,
+    /// The sequence has a value, and nothing longer starts with it
+    Match(&'a V),
+    /// The sequence has a value, but is also the start of longer sequences
+    Ambiguous(&'a V),
+}
+
+// ===================== Trie =====================
+
+/// A prefix tree mapping sequences of `K` to a value `V`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Trie<K: Eq + Hash, V> {
+    /// The value of the sequence ending at this node
+    value:    Option<V>,
+    /// The nodes of the sequences that continue from this one
+    children: HashMap<K, Trie<K, V>>,
+}
+
+impl<K: Eq + Hash, V> Default for Trie<K, V> {
+    fn default() -> Self {
+        Self {
+            value:    None,
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Trie<K, V> {
+    /// Create an empty `Trie`
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return whether there are no sequences in the `Trie`
+    pub(crate) fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    /// Insert a sequence, returning the value it had before
+    pub(crate) fn insert<I: IntoIterator<Item = K>>(&mut self, keys: I, value: V) -> Option<V> {
+        let node = keys
+            .into_iter()
+            .fold(self, |node, key| node.children.entry(key).or_default());
+        node.value.replace(value)
+    }
+
+    /// Return the node at the end of a sequence
+    fn node(&self, keys: &[K]) -> Option<&Self> {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+
+    /// Return the value of a sequence
+    pub(crate) fn get(&self, keys: &[K]) -> Option<&V> {
+        self.node(keys).and_then(|node| node.value.as_ref())
+    }
+
+    /// Look up how a sequence matches the `Trie`
+    pub(crate) fn lookup(&self, keys: &[K]) -> Lookup<'_, V> {
+        match self.node(keys) {
+            None => Lookup::None,
+            Some(node) => match (&node.value, node.children.is_empty()) {
+                (Some(value), true) => Lookup::Match(value),
+                (Some(value), false) => Lookup::Ambiguous(value),
+                (None, false) => Lookup::Prefix,
+                (None, true) => Lookup::None,
+            },
+        }
+    }
+
+    /// Return the values of every sequence, in no particular order
+    pub(crate) fn values(&self) -> Vec<&V> {
+        let mut values = self.value.iter().collect::<Vec<_>>();
+        for child in self.children.values() {
+            values.extend(child.values());
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trie_lookup() {
+        let mut trie = Trie::new();
+        assert!(trie.is_empty());
+
+        assert_eq!(trie.insert("gb".chars(), 1), None);
+        assert_eq!(trie.insert("term".chars(), 2), None);
+        assert_eq!(trie.insert("te".chars(), 3), None);
+        assert_eq!(trie.insert("gb".chars(), 4), Some(1));
+
+        let seq = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(trie.lookup(&seq("g")), Lookup::Prefix);
+        assert_eq!(trie.lookup(&seq("gb")), Lookup::Match(&4));
+        assert_eq!(trie.lookup(&seq("gc")), Lookup::None);
+        assert_eq!(trie.lookup(&seq("gbx")), Lookup::None);
+        assert_eq!(trie.lookup(&seq("te")), Lookup::Ambiguous(&3));
+        assert_eq!(trie.lookup(&seq("term")), Lookup::Match(&2));
+        assert_eq!(trie.get(&seq("ter")), None);
+
+        let mut values = trie.values();
+        values.sort();
+        assert_eq!(values, vec![&2, &3, &4]);
+    }
+}
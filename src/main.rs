@@ -80,7 +80,8 @@
     )
 )]
 
-// mod app;
+mod app;
+mod check;
 mod cli;
 mod config;
 mod keys;
@@ -90,30 +91,46 @@ mod utils;
 mod xcb_utils;
 
 use anyhow::{Context, Result};
+use app::App;
 use clap::Parser;
 use cli::Opts;
 use colored::Colorize;
 use config::Config;
 use keys::{daemon::Daemon, keyboard::Keyboard};
-use nix::{
-    sys::signal::{self, Signal},
-    unistd::{Pid, Uid},
-};
-use std::{env, fs};
+use nix::unistd::Uid;
+use std::process;
 use x11rb::{connection::Connection, protocol::Event};
 use xcb_utils::XUtility;
 
-#[cfg(feature = "daemonize")]
-use daemonize::{Daemonize, User};
-
 fn main() -> Result<()> {
+    // Block the signals that stop the daemon before any other thread is
+    // spawned, which includes the ones that logging and the X connections start
+    let signals = Daemon::block_signals()?;
+
     if Uid::effective().is_root() || Uid::current().is_root() {
         lxhkd_fatal!("this program is not meant to be ran as a root user. Try again");
     }
 
-    let config = Config::load_default().context("failed to load default configuration file")?;
     let args = Opts::parse();
 
+    // Checked before the default configuration file is loaded, since that is
+    // likely what is being checked
+    if let Some(file) = &args.check {
+        let path = match file.as_ref().or(args.config.as_ref()) {
+            Some(path) => path.clone(),
+            None => config::get_config_file()?,
+        };
+
+        let (ctrl_conn, screen_num) = XUtility::setup_connection()?;
+        let (data_conn, _) = XUtility::setup_connection()?;
+        let keyboard = Keyboard::new(ctrl_conn, data_conn, screen_num, &Config::default())?;
+
+        let valid = check::run(&keyboard, &path)?;
+        process::exit(i32::from(!valid));
+    }
+
+    let config = Config::load_default().context("failed to load default configuration file")?;
+
     // #[cfg(not(test))]
     if let Ok(dir) = utils::initialize_logging(&config, &args) {
         if config.global.log_to_file {
@@ -128,70 +145,7 @@ fn main() -> Result<()> {
         log::info!("logging failed to initialize");
     }
 
-    #[cfg(feature = "daemonize")]
-    {
-        let runtime_dir = dirs::runtime_dir().unwrap_or_else(env::temp_dir);
-        let pidpath = &args.pidfile.unwrap_or_else(|| {
-            config
-                .clone()
-                .global
-                .pid_file
-                .unwrap_or_else(|| runtime_dir.join("lxhkd.pid"))
-        });
-
-        if args.daemonize {
-            log::info!("pid-path: {}", pidpath.display().to_string().blue().bold());
-
-            Daemonize::new()
-                .pid_file(pidpath)
-                .user(User::Id(Uid::current().into()))
-                .umask(0o600)
-                .exit_action(|| log::info!("> Daemon started <"))
-                .start()
-                .unwrap_or_else(|_| {
-                    lxhkd_fatal!(
-                        "daemon is already running: {}",
-                        fs::read_to_string(pidpath)
-                            .unwrap_or_else(|_| String::from("N/A"))
-                            .green()
-                            .bold()
-                    )
-                });
-        } else if args.kill {
-            // TODO: Check for daemon in background if trying to run in foreground
-            let pid_contents =
-                fs::read_to_string(pidpath).context("failed to read pidfile to string")?;
-            let pid = pid_contents.parse::<i32>().unwrap_or_else(|_| {
-                lxhkd_fatal!(
-                    "unable to kill the daemon. The process has either been terminated manually, \
-                     or the pidfile's contents have been modified. Contents ({})",
-                    pid_contents
-                )
-            });
-            let colored_pid = pid.to_string().green().bold();
-
-            // Checking whether or not the process is running before trying to kill it
-            if let Some(_process) = psutil::process::processes()
-                .context("failed to get list of processes")?
-                .iter()
-                .filter_map(|p| p.as_ref().ok())
-                .find(|p| p.pid() as usize == pid as usize)
-            {
-                if let Err(e) = signal::kill(Pid::from_raw(pid), Signal::SIGINT) {
-                    log::error!("failed to terminate process {}: {}", colored_pid, e);
-                } else {
-                    log::info!("successfully terminated daemon: {}", colored_pid);
-                }
-            } else {
-                log::error!(
-                    "the daemon is not currently running or the PID file has been modified: {}",
-                    colored_pid
-                );
-            }
-
-            std::process::exit(1);
-        }
-    }
+    App::new(&args, config.clone()).run_commands()?;
 
     // Control connection
     let (ctrl_conn, screen_num) = XUtility::setup_connection()?;
@@ -202,12 +156,12 @@ fn main() -> Result<()> {
 
     if args.keysyms {
         keyboard.list_keysyms()?;
-        std::process::exit(1);
+        process::exit(1);
     }
 
     let mut daemon = Daemon::new(keyboard, config);
     daemon.process_configuration()?;
-    daemon.daemonize()?;
+    daemon.daemonize(signals)?;
 
     Ok(())
 }
@@ -0,0 +1,195 @@
+//! The syntax tree of the keys in the configuration file.
+//!
+//! A [`ChainExpr`] is the keys as they were written. Its groups are expanded
+//! and its keys are looked up on the keyboard by
+//! [`parser`](super::parser) to create a [`Chain`](crate::keys::chord::Chain)
+
+use super::lexer::Span;
+
+// ==================== Keys ======================
+
+/// A key as it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum KeyExpr {
+    /// A modifier, keysym name, or character (e.g., `super`, `Return`, `a`)
+    Name(String),
+    /// A keysym given by its code (e.g., `[0x61]` or `[97]`)
+    Code(u32),
+    /// A physical key given by its keycode (e.g., `[kc:38]`)
+    Keycode(u8),
+    /// A physical key given by its XKB name (e.g., `<AC01>`)
+    KeyName(String),
+    /// A mouse button (e.g., `mouse1`)
+    Mouse(u8),
+}
+
+/// A [`KeyExpr`] along with where it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Key {
+    /// The key
+    pub(crate) expr: KeyExpr,
+    /// Where the key is in the input
+    pub(crate) span: Span,
+}
+
+impl Key {
+    /// Create a new `Key`
+    pub(crate) const fn new(expr: KeyExpr, span: Span) -> Self {
+        Self { expr, span }
+    }
+}
+
+// ==================== Group =====================
+
+/// A group of keys. Every key of the group creates its own binding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Group {
+    /// A list of keys (e.g., `{a,c,e}`)
+    Options(Vec<Key>),
+    /// Every key between two others, including both (e.g., `{a-e}`,
+    /// `{F1-F12}`, or `{Left-Down}`)
+    Range {
+        /// The first key of the range
+        start: Key,
+        /// The last key of the range
+        end:   Key,
+        /// Every key of the range, in order
+        keys:  Vec<Key>,
+    },
+    /// A list of keys that are pressed along with the next part of the chord,
+    /// or nothing if an option is `_` (e.g., `{_,shift + ,ctrl + }a`)
+    Prefixes(Vec<Vec<Key>>),
+}
+
+impl Group {
+    /// Return the keys of every option of the group
+    pub(crate) fn choices(&self) -> Vec<Vec<Key>> {
+        match self {
+            Self::Options(keys) => keys.iter().map(|key| vec![key.clone()]).collect(),
+            Self::Range { keys, .. } => keys.iter().map(|key| vec![key.clone()]).collect(),
+            Self::Prefixes(prefixes) => prefixes.clone(),
+        }
+    }
+}
+
+// ==================== Chain =====================
+
+/// A single key or group of a chord, as separated by '+'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Atom {
+    /// A single key
+    Key(Key),
+    /// A group of keys
+    Group(Group),
+}
+
+/// An [`Atom`] that may be prefixed by '~'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Part {
+    /// Whether the release of the key is matched instead of its press
+    pub(crate) release: bool,
+    /// The key or group
+    pub(crate) atom:    Atom,
+    /// Where the part is in the input, including the '~'
+    pub(crate) span:    Span,
+}
+
+impl Part {
+    /// Return whether the part is a group of prefixes, which the next part of
+    /// the chord follows without a '+'
+    pub(crate) const fn is_prefix(&self) -> bool {
+        matches!(self.atom, Atom::Group(Group::Prefixes(_)))
+    }
+}
+
+/// The keys that are pressed together, separated by '+' (e.g., `super + a`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChordExpr {
+    /// The keys and groups of the chord
+    pub(crate) parts: Vec<Part>,
+    /// Where the chord is in the input
+    pub(crate) span:  Span,
+}
+
+/// The chords that are pressed one after the other, separated by ';' (e.g.,
+/// `super + a ; b`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChainExpr {
+    /// The chords of the chain
+    pub(crate) chords: Vec<ChordExpr>,
+    /// Where the chain is in the input
+    pub(crate) span:   Span,
+}
+
+/// A key of a chord after the groups of its chain are expanded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExpandedKey {
+    /// The key
+    pub(crate) key:     Key,
+    /// Whether the release of the key is matched instead of its press
+    pub(crate) release: bool,
+}
+
+/// The keys of every chord of a chain after its groups are expanded
+pub(crate) type ExpandedChain = Vec<Vec<ExpandedKey>>;
+
+impl ChainExpr {
+    /// Return the parts of the chain that are groups
+    pub(crate) fn groups(&self) -> impl Iterator<Item = &Part> {
+        self.chords
+            .iter()
+            .flat_map(|chord| &chord.parts)
+            .filter(|part| matches!(part.atom, Atom::Group(_)))
+    }
+
+    /// Expand the groups of the chain, returning the keys of every combination
+    /// of them. The keys of the last group change the fastest, so that
+    /// `{a,b} + {1,2}` expands to `a + 1`, `a + 2`, `b + 1`, and `b + 2`
+    pub(crate) fn expand(&self) -> Vec<ExpandedChain> {
+        let groups = self
+            .groups()
+            .filter_map(|part| match &part.atom {
+                Atom::Group(group) => Some(group.choices()),
+                Atom::Key(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let total = groups.iter().map(Vec::len).product::<usize>();
+
+        (0..total)
+            .map(|mut idx| {
+                let mut choices = groups
+                    .iter()
+                    .rev()
+                    .map(|options| {
+                        let keys = &options[idx % options.len()];
+                        idx /= options.len();
+                        keys
+                    })
+                    .collect::<Vec<_>>();
+
+                self.chords
+                    .iter()
+                    .map(|chord| {
+                        chord
+                            .parts
+                            .iter()
+                            .flat_map(|part| {
+                                let keys = match &part.atom {
+                                    Atom::Key(key) => vec![key.clone()],
+                                    Atom::Group(_) => choices
+                                        .pop()
+                                        .cloned()
+                                        .expect("every group has an option chosen"),
+                                };
+                                keys.into_iter().map(|key| ExpandedKey {
+                                    key,
+                                    release: part.release,
+                                })
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
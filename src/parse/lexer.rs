@@ -0,0 +1,169 @@
+//! The lexer of the keys in the configuration file (e.g., `super + {a-c}`).
+//!
+//! Every [`Token`] keeps the [`Span`] of the input it was read from, so that
+//! errors found while parsing can point at it
+
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+pub(crate) const EXTRA_PREFIX: char = '=';
+
+pub(crate) const RELEASE_PREFIX: char = '~';
+pub(crate) const SYM_START: char = '[';
+pub(crate) const SYM_END: char = ']';
+pub(crate) const SYM_SEP: char = '+';
+pub(crate) const SEQ_START: char = '{';
+pub(crate) const SEQ_END: char = '}';
+pub(crate) const SEQ_NONE: char = '_';
+pub(crate) const RANGE_SEP: char = '-';
+pub(crate) const OPTION_SEP: char = ',';
+pub(crate) const GROUP_SEP: char = ':';
+pub(crate) const LINK_SEP: char = ';';
+
+pub(crate) const KEYCODE_PREFIX: &str = "kc:";
+pub(crate) const KEY_NAME_START: char = '<';
+pub(crate) const KEY_NAME_END: char = '>';
+
+// ===================== Span =====================
+
+/// The bytes of the input something was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(crate) struct Span {
+    /// The index of the first byte
+    pub(crate) start: usize,
+    /// The index after the last byte
+    pub(crate) end:   usize,
+}
+
+impl Span {
+    /// Create a new `Span`
+    pub(crate) const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Return a `Span` that covers both this one and `other`
+    pub(crate) fn to(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Return the `Span` moved `offset` bytes further into the input
+    pub(crate) const fn shift(self, offset: usize) -> Self {
+        Self::new(self.start + offset, self.end + offset)
+    }
+}
+
+// ==================== Token =====================
+
+/// The kind of a [`Token`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// A plus sign, separating the keys of a chord
+    Plus,
+    /// A semicolon, separating the chords of a chain
+    Semicolon,
+    /// A tilde, placed before a key to match its release
+    Tilde,
+    /// The start of a group '{'
+    OpenBrace,
+    /// The end of a group '}'
+    CloseBrace,
+    /// The start of a keysym code '['
+    OpenBracket,
+    /// The end of a keysym code ']'
+    CloseBracket,
+    /// A comma, separating the options of a group
+    Comma,
+    /// A dash, separating the ends of a range
+    Dash,
+    /// Any other run of characters, such as a key or a modifier
+    Word(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Plus => write!(f, "{SYM_SEP}"),
+            Self::Semicolon => write!(f, "{LINK_SEP}"),
+            Self::Tilde => write!(f, "{RELEASE_PREFIX}"),
+            Self::OpenBrace => write!(f, "{SEQ_START}"),
+            Self::CloseBrace => write!(f, "{SEQ_END}"),
+            Self::OpenBracket => write!(f, "{SYM_START}"),
+            Self::CloseBracket => write!(f, "{SYM_END}"),
+            Self::Comma => write!(f, "{OPTION_SEP}"),
+            Self::Dash => write!(f, "{RANGE_SEP}"),
+            Self::Word(word) => write!(f, "{word}"),
+        }
+    }
+}
+
+/// A single piece of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    /// What was read
+    pub(crate) kind: TokenKind,
+    /// Where it was read from
+    pub(crate) span: Span,
+}
+
+// ==================== Lexer =====================
+
+/// An iterator over the [`Token`]s of the input. Whitespace only separates
+/// tokens, and is otherwise skipped
+#[derive(Debug, Clone)]
+pub(crate) struct Lexer<'a> {
+    /// The input being read
+    input: &'a str,
+    /// The characters that have not been read yet
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    /// Create a new `Lexer` over `input`
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self { input, chars: input.char_indices().peekable() }
+    }
+
+    /// Return the kind of the token made up of the single character `c`
+    const fn punctuation(c: char) -> Option<TokenKind> {
+        Some(match c {
+            SYM_SEP => TokenKind::Plus,
+            LINK_SEP => TokenKind::Semicolon,
+            RELEASE_PREFIX => TokenKind::Tilde,
+            SEQ_START => TokenKind::OpenBrace,
+            SEQ_END => TokenKind::CloseBrace,
+            SYM_START => TokenKind::OpenBracket,
+            SYM_END => TokenKind::CloseBracket,
+            OPTION_SEP => TokenKind::Comma,
+            RANGE_SEP => TokenKind::Dash,
+            _ => return None,
+        })
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        let (start, c) = self.chars.next()?;
+        if let Some(kind) = Self::punctuation(c) {
+            return Some(Token {
+                kind,
+                span: Span::new(start, start + c.len_utf8()),
+            });
+        }
+
+        let mut end = start + c.len_utf8();
+        while let Some((idx, c)) = self
+            .chars
+            .next_if(|(_, c)| !c.is_whitespace() && Self::punctuation(*c).is_none())
+        {
+            end = idx + c.len_utf8();
+        }
+
+        Some(Token {
+            kind: TokenKind::Word(self.input[start..end].to_string()),
+            span: Span::new(start, end),
+        })
+    }
+}
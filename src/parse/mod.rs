@@ -1,3 +1,5 @@
 //! Files for parsing the configuration file
 
+pub(crate) mod ast;
+pub(crate) mod lexer;
 pub(crate) mod parser;
@@ -1,1623 +1,1494 @@
-//! Parse a configuration file of **bindings**. That is, the keys that are
-//! mapped to shell commands.
+//! Parse the keys of the configuration file (e.g., `super + {a-c}`).
 //!
-//! This file tokenizes them and returns a `Chain` of `Chord`s
-
-// TODO: Parse right hand side of binding
-// This entire parser really just needs to be rewritten
+//! The [`Lexer`] splits the keys into [`Token`]s, which are parsed into a
+//! [`ChainExpr`]. Its groups are then expanded, and every key is looked up on
+//! the keyboard, creating a `Chain` of `Chord`s for each expansion.
+//!
+//! ```text
+//! chain  := chord (';' chord)*
+//! chord  := part ('+' part)*      // no '+' is needed after a group of prefixes
+//! part   := '~'? atom
+//! atom   := word | '<' name '>' | '[' code ']' | '[' 'kc:' keycode ']' | '{' group '}'
+//! group  := word '-' word | word (',' word)* | prefix (',' prefix)*
+//! prefix := '_' | (word '+')+
+//! ```
 
 // YAML special characters
 // [] {} > | * & ! % # ` @ ,
 // ? : -
 
-use crate::{
-    config::{Action, Config},
-    keys::{
-        chord::{Chain, Chord},
-        keys::{ButtonCode, CharacterMap, ModifierMask},
-        keysym::KeysymHash,
+use super::{
+    ast::{Atom, ChainExpr, ChordExpr, ExpandedChain, ExpandedKey, Group, Key, KeyExpr, Part},
+    lexer::{
+        Lexer,
+        Span,
+        Token,
+        TokenKind,
+        KEYCODE_PREFIX,
+        KEY_NAME_END,
+        KEY_NAME_START,
+        SEQ_NONE,
+        SEQ_START,
+        SYM_START,
     },
-    lxhkd_fatal,
 };
-use anyhow::{Context, Result};
-use colored::Colorize;
-use indexmap::IndexMap;
-use itertools::{Itertools, PeekingNext, PeekingTakeWhile};
-use once_cell::sync::Lazy;
-use regex::{Captures, Regex};
-use serde::{Deserialize, Serialize};
-use std::{
-    cmp::Ordering,
-    collections::HashMap,
-    fmt,
-    num::ParseIntError,
-    ops::Range,
-    process::{Command, Stdio},
-    str::FromStr,
+use crate::keys::{
+    chord::{Chain, Chord},
+    keys::{ButtonCode, CharacterMap, ModifierMask, Sides},
+    keysym::KeysymHash,
+    modifiers,
 };
+use colored::Colorize;
+use format_serde_error::SerdeError;
+use std::{error::Error as StdError, iter::Peekable};
 use thiserror::Error;
 use x11rb::protocol::{xproto, xproto::MapIndex};
 
-pub(crate) const EXTRA_PREFIX: char = '=';
-
-pub(crate) const RELEASE_PREFIX: char = '~';
-pub(crate) const SYM_START: char = '[';
-pub(crate) const SYM_END: char = ']';
-pub(crate) const SYM_SEP: char = '+';
-pub(crate) const SEQ_START: char = '{';
-pub(crate) const SEQ_END: char = '}';
-pub(crate) const SEQ_NONE: char = '_';
-pub(crate) const RANGE_SEP: char = '-';
-pub(crate) const OPTION_SEP: char = ',';
-pub(crate) const GROUP_SEP: char = ':';
-pub(crate) const LINK_SEP: char = ';';
-
 // =================== Errors =====================
 
-#[derive(Debug, Error)]
-pub(crate) enum Error {
-    #[error("failed to lookup codepoint from string: {0}")]
-    CodepointLookup(String),
-    #[error("failed to lookup `CharacterMap` from string: {0}")]
-    UTFLookup(String),
+/// The kind of problem found with a set of keys
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    #[error("expected {0}, but the keys ended")]
+    UnexpectedEnd(&'static str),
+    #[error("expected {0}, found `{1}`")]
+    Unexpected(&'static str, String),
+    #[error("`{0}` is never closed")]
+    Unclosed(char),
+    #[error("`{0}` is not a keysym code. Either remove the brackets '[]' or use a valid code")]
+    InvalidCode(String),
+    #[error("`{0}` is not a keycode, which are written as 'kc:N' where 'N' is 8-255")]
+    InvalidKeycode(String),
+    #[error("`{0}` is not a mouse button, which are written as 'mouseN' where 'N' is 1-5")]
+    InvalidButton(String),
+    #[error("the end of the range `{1}` comes before its start `{0}`")]
+    RangeOrder(String, String),
+    #[error("`{0}` and `{1}` are not the same kind of key, so there is no range between them")]
+    RangeFamily(String, String),
+    #[error("there is no key `{0}`, so a range of digits has to end at 9")]
+    RangeDigits(String),
+    #[error("the options of a group have to either all be keys, or all be `_` or end with `+`, \
+             e.g., {{_,shift + }}")]
+    MixedGroup,
+    #[error("groups can only be used in `bindings`")]
+    Group,
+    #[error("unknown keysym `{0}`")]
+    UnknownKeysym(String),
+    #[error("unknown keysym code `{0:#x}`")]
+    UnknownCode(u32),
+    #[error("there is no key with the keycode `{0}`")]
+    UnknownKeycode(u8),
+    #[error("there is no key named `<{0}>`")]
+    UnknownKeyName(String),
+    #[error("invalid modifier `{0}`")]
+    InvalidModifier(String),
 }
 
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", match *self {
-            Self::Release => String::from("Release"),
-            Self::KeysymStart => String::from("["),
-            Self::KeysymEnd => String::from("]"),
-            Self::KeysymString(ref string) => format!("Keysym({})", string),
-            Self::Mouse(code) => format!("Mouse({})", code),
-            Self::Modifier(ref modifier) => format!("Modifier({})", modifier),
-            Self::Text(ref text) => format!("Text({})", text),
-            Self::Char(ch) => format!("Char({})", ch),
-            Self::UnknownChar(ref ch) => format!("UnknownChar({})", ch),
-            Self::OptionGroup(ref group) => format!("OptionGroup({:#?})", group),
-            Self::SeqStart => String::from("{"),
-            Self::SeqEnd => String::from("}"),
-            Self::RangeGroup(ref group) => format!("RangeGroup({:#?})", group),
-            Self::Comma => String::from(","),
-            Self::Plus => String::from("+"),
-            Self::Dash => String::from("-"),
-            Self::Unknown(ref string) => format!("UnknownString({})", string),
-            Self::Invalid => String::from("Invalid"),
-        })
+/// A problem found with a set of keys, along with where it is
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{kind}")]
+pub(crate) struct Error {
+    /// The problem
+    pub(crate) kind: ErrorKind,
+    /// Where the problem is in the keys
+    pub(crate) span: Span,
+}
+
+impl Error {
+    /// Create a new `Error`
+    pub(crate) const fn new(kind: ErrorKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Return the `Error` moved `offset` bytes further, for keys that were
+    /// parsed on their own out of a longer string
+    pub(crate) fn shift(mut self, offset: usize) -> Self {
+        self.span = self.span.shift(offset);
+        self
+    }
+
+    /// Render the `Error` below the line of the configuration file it was
+    /// found on, with a caret pointing at it. `text` is either the item `key`
+    /// of `section`, or one of its values. Only `text` is shown if it cannot be
+    /// found in `contents`
+    pub(crate) fn render(
+        &self,
+        contents: Option<&str>,
+        section: &str,
+        key: &str,
+        text: &str,
+    ) -> String {
+        let error: Box<dyn StdError> = Box::new(self.kind.clone());
+        let offset = text.get(..self.span.start).map_or(0, |s| s.chars().count());
+
+        let (input, line, column) = match contents
+            .and_then(|contents| Some((contents, locate(contents, section, key, text)?)))
+        {
+            Some((contents, (line, column))) => (contents.to_string(), line, column + offset),
+            None => (text.to_string(), 1, offset),
+        };
+
+        SerdeError::new(input, (error, Some(line), Some(column))).to_string()
     }
 }
 
-// =================== Tokens =====================
-
-/// Various tokens used to parse the keybindings
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum Token {
-    // MouseRelease(usize),
-    // Command,
-    // Keycode(u8),
-    /// Release event (mouse and key)
-    Release,
-    /// Start of specifying a keysym code '['
-    KeysymStart,
-    /// End of specifying a keysym code ']'
-    KeysymEnd,
-    /// A keysym string that is between the above two
-    KeysymString(String),
-    /// Final: A mouse button
-    Mouse(u8),
-    /// Final: A modifier key
-    Modifier(String),
-    // FIX: Do text and char really need to be different?
-    /// A catch all used before further parsing
-    Text(String),
-    /// Final: A single character key. E.g., 'a' in this: super + 'a'
-    Char(char),
-    /// A single character that is not found in `KeysymHash`
-    UnknownChar(char),
-    /// The start of an sequence '{'
-    SeqStart,
-    /// The end of an sequence '}'
-    SeqEnd,
-    /// The expanded items within an Option
-    OptionGroup(Vec<char>),
-    /// The expanded items within a Range
-    RangeGroup(Vec<char>),
-    /// A comma
-    Comma,
-    /// A plus sign
-    Plus,
-    /// A dash, usually located within an option
-    Dash,
-    Unknown(String),
-    /// Used as a finalized placeholder to filter results that do not contain
-    /// this
-    Invalid,
+// =================== Parser =====================
+
+/// An option of a group, before it is known which kind of group it is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GroupOption {
+    /// A single key (e.g., `a` in `{a,c}`)
+    Key(Key),
+    /// The keys of a prefix, which are none for `_` (e.g., `shift` in
+    /// `{_,shift + }`)
+    Prefix(Vec<Key>),
 }
 
-// ==================== Line ======================
-
-/// A split line in the configuration file
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) struct Line<'a> {
-    /// Index the line is in the `IndexMap` of keys and bindings
-    pub(crate) line_num: usize,
-    /// The length of the characters in the line
-    pub(crate) char_len: usize,
-    /// The number of possible `Tokens`
-    pub(crate) n_keys:   usize,
-    /// The line split on '+'
-    pub(crate) vector:   Vec<&'a str>,
+/// Parses the [`Token`]s of a set of keys into a [`ChainExpr`]
+#[derive(Debug, Clone)]
+pub(crate) struct Parser<'a> {
+    /// The length of the input, which is where it ends unexpectedly
+    len:    usize,
+    /// The tokens that have not been parsed yet
+    tokens: Peekable<Lexer<'a>>,
 }
 
-impl<'a> Line<'a> {
-    /// Create a new `Line` by splitting on '+'
-    pub(crate) fn new(line: &'a str, idx: usize) -> Self {
-        if line.is_empty() {
-            Self {
-                line_num: idx,
-                char_len: line.len(),
-                n_keys:   0,
-                vector:   vec![],
-            }
-        } else {
-            let split = line.split('+').map(str::trim).collect::<Vec<_>>();
-            Self {
-                line_num: idx,
-                char_len: line.len(),
-                n_keys:   split.len(),
-                vector:   split,
-            }
+impl<'a> Parser<'a> {
+    /// Create a new `Parser` of `input`
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self {
+            len:    input.len(),
+            tokens: Lexer::new(input).peekable(),
         }
     }
 
-    /// Create a new `Line` by splitting on '+' but keeping the '+' characters
-    /// in the split
-    pub(crate) fn new_plus(line: &'a str, idx: usize) -> Self {
-        let mut split = vec![];
-        let mut last = 0;
+    /// Parse the whole input
+    pub(crate) fn parse(mut self) -> Result<ChainExpr, Error> {
+        let mut chords = vec![self.chord()?];
 
-        for (idx, matched) in line.match_indices(|c| c == '+') {
-            // Pushes 'super'
-            if last != idx {
-                split.push(line[last..idx].trim());
+        while let Some(token) = self.tokens.next() {
+            match token.kind {
+                TokenKind::Semicolon => chords.push(self.chord()?),
+                kind => return Err(Self::unexpected("`+` or `;`", &kind, token.span)),
             }
-            // Pushes '+'
-            split.push(matched.trim());
-
-            last = idx + matched.len();
-        }
-        // Pushes rest
-        if last < line.len() {
-            split.push(line[last..].trim());
         }
 
-        // This is for whenever there are no '+' within the binding.
-        // For example, "~mouse2"
-        if split.is_empty() {
-            Self::new(line, idx)
-        } else {
-            Self {
-                line_num: idx,
-                char_len: line.len(),
-                n_keys:   split.len(),
-                vector:   split,
-            }
+        let span = chords[0].span.to(chords[chords.len() - 1].span);
+        Ok(ChainExpr { chords, span })
+    }
+
+    /// Parse the keys of a chord, which are separated by '+'. A group of
+    /// prefixes ends with its own '+', so the next part follows it directly
+    fn chord(&mut self) -> Result<ChordExpr, Error> {
+        let mut parts = vec![self.part()?];
+
+        while parts[parts.len() - 1].is_prefix()
+            || self.tokens.next_if(|t| t.kind == TokenKind::Plus).is_some()
+        {
+            parts.push(self.part()?);
         }
+
+        let span = parts[0].span.to(parts[parts.len() - 1].span);
+        Ok(ChordExpr { parts, span })
+    }
+
+    /// Parse a single key or group, which may be prefixed by '~'
+    fn part(&mut self) -> Result<Part, Error> {
+        let tilde = self.tokens.next_if(|t| t.kind == TokenKind::Tilde);
+        let token = self.next("a key")?;
+
+        let (atom, span) = match token.kind {
+            TokenKind::Word(word) => (Atom::Key(Self::key(word, token.span)?), token.span),
+            TokenKind::OpenBracket => {
+                let key = self.code(token.span)?;
+                let span = key.span;
+                (Atom::Key(key), span)
+            },
+            TokenKind::OpenBrace => {
+                let (group, close) = self.group(token.span)?;
+                (Atom::Group(group), token.span.to(close))
+            },
+            kind => return Err(Self::unexpected("a key", &kind, token.span)),
+        };
+
+        Ok(Part {
+            release: tilde.is_some(),
+            atom,
+            span: tilde.map_or(span, |t| t.span.to(span)),
+        })
     }
 
-    /// Create a `TokenizedLine`
-    pub(crate) fn tokenize(&self) -> TokenizedLine {
-        let mut all_res = vec![];
-        let token_text = |text: &mut String, res: &mut Vec<Token>| {
-            if !text.is_empty() {
-                res.push(Token::Text(String::from(&*text)));
-                text.clear();
+    /// Parse a keysym code (e.g., `[0x61]`) or a keycode (e.g., `[kc:38]`),
+    /// after the '[' at `open`
+    fn code(&mut self, open: Span) -> Result<Key, Error> {
+        let token = self.next("a keysym code")?;
+        let word = match token.kind {
+            TokenKind::Word(word) => word,
+            kind => return Err(Self::unexpected("a keysym code", &kind, token.span)),
+        };
+
+        let expr = if let Some(keycode) = word.strip_prefix(KEYCODE_PREFIX) {
+            keycode
+                .parse::<u8>()
+                .map(KeyExpr::Keycode)
+                .map_err(|_| Error::new(ErrorKind::InvalidKeycode(word.clone()), token.span))?
+        } else {
+            match word.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => word.parse::<u32>(),
             }
+            .map(KeyExpr::Code)
+            .map_err(|_| Error::new(ErrorKind::InvalidCode(word.clone()), token.span))?
         };
 
-        for tok in &self.vector {
-            let mut res = vec![];
-            let mut text = String::new();
-            let mut chars = tok.chars().peekable();
+        let close = self.close(&TokenKind::CloseBracket, SYM_START, open, "`]`")?;
+        Ok(Key::new(expr, open.to(close)))
+    }
 
-            while let Some(&c) = chars.peek() {
-                match c {
-                    SEQ_START => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::SeqStart);
-                    },
-                    SEQ_END => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::SeqEnd);
-                    },
-                    SYM_START => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::KeysymStart);
-                    },
-                    SYM_END => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::KeysymEnd);
-                    },
-                    RANGE_SEP => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::Dash);
-                    },
-                    SYM_SEP => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::Plus);
-                    },
-                    OPTION_SEP => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::Comma);
-                    },
-                    RELEASE_PREFIX => {
-                        token_text(&mut text, &mut res);
-                        res.push(Token::Release);
-                    },
-                    ' ' => {
-                        token_text(&mut text, &mut res);
-                    },
-                    ch => {
-                        text.push(ch);
+    /// Parse a range (e.g., `{a-c}`), options (e.g., `{a,c}`), or prefixes
+    /// (e.g., `{_,shift + }`), after the '{' at `open`. Returns the group along
+    /// with the `Span` of its '}'
+    fn group(&mut self, open: Span) -> Result<(Group, Span), Error> {
+        let first = self.option(open)?;
+
+        if let GroupOption::Key(first) = &first {
+            if self.tokens.next_if(|t| t.kind == TokenKind::Dash).is_some() {
+                let last = self.element(open)?;
+                let close = self.close(&TokenKind::CloseBrace, SEQ_START, open, "`}`")?;
+                let keys = Self::range(first, &last, open.to(close))?;
+
+                return Ok((
+                    Group::Range {
+                        start: first.clone(),
+                        end: last,
+                        keys,
                     },
-                }
-                chars.next();
-            }
-            if !text.is_empty() {
-                res.push(Token::Text(text));
+                    close,
+                ));
             }
-            if !res.is_empty() {
-                all_res.push(res);
+        }
+
+        let mut options = vec![first];
+        while self.tokens.next_if(|t| t.kind == TokenKind::Comma).is_some() {
+            options.push(self.option(open)?);
+        }
+        let close = self.close(&TokenKind::CloseBrace, SEQ_START, open, "`,`, `-` or `}`")?;
+
+        let mut keys = Vec::new();
+        let mut prefixes = Vec::new();
+        for option in options {
+            match option {
+                GroupOption::Key(key) => keys.push(key),
+                GroupOption::Prefix(prefix) => prefixes.push(prefix),
             }
         }
 
-        TokenizedLine { line: self.clone(), tokenized: all_res }
-    }
-}
+        if !keys.is_empty() && !prefixes.is_empty() {
+            return Err(Error::new(ErrorKind::MixedGroup, open.to(close)));
+        }
+        if !prefixes.is_empty() {
+            return Ok((Group::Prefixes(prefixes), close));
+        }
 
-impl fmt::Display for Line<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.vector.join(" "))
+        Ok((Group::Options(keys), close))
     }
-}
 
-// ============= Regex + Modifiers ================
+    /// Return every key of the range from `start` to `end`, which is at `span`.
+    /// The ends are either single characters (e.g., `{a-e}` or `{1-9}`), keys
+    /// with the same name followed by a number (e.g., `{F1-F12}`), or keysyms
+    /// with the same high byte (e.g., `{Left-Down}`). There are only keys for
+    /// the digits, so a range of numbers without a name cannot go past 9
+    fn range(start: &Key, end: &Key, span: Span) -> Result<Vec<Key>, Error> {
+        let (first, last) = (text(start), text(end));
+        let order = || Error::new(ErrorKind::RangeOrder(first.clone(), last.clone()), span);
+        let family = || Error::new(ErrorKind::RangeFamily(first.clone(), last.clone()), span);
 
-// TODO: Do something with `fn` and `meh`
-
-#[rustfmt::skip]
-const MODIFIER_STR: &[&str] = &[
-    "alt",   "lalt",      "ralt",       "Alt_L",    "Alt_R",
-    "shift", "lshift",    "rshift",     "Shift_L",  "Shift_R",
-    "super", "lsuper",    "rsuper",     "Super_L",  "Super_R",
-    "meta",  "lmeta",     "rmeta",      "Meta_L",   "Meta_R",
-    "ctrl",  "control",   "lctrl",      "rctrl",    "Control_L",   "Control_R",
-    "hyper", "lhyper",    "rhyper",     "Hyper_L",  "Hyper_R",
-    "mod1",  "mod2",      "mod3",       "mod4",     "mod5",
-    "lock",  "Caps_Lock", "Shift_Lock", "Num_Lock", "Scroll_Lock", // Unsure if these are needed
-    "fn",    "meh",
-];
+        if let (Some(from), Some(to)) = (as_char(&first), as_char(&last)) {
+            if to < from {
+                return Err(order());
+            }
+            return Ok((from..=to)
+                .map(|c| Key::new(KeyExpr::Name(c.to_string()), span))
+                .collect());
+        }
 
-// static BIND_RANGE_PATTERN: Lazy<Regex> =
-//     Lazy::new(|| Regex::new(r"([0-9a-z])-([0-9a-z])").unwrap());
-// static NUM_RANGE_PATTERN: Lazy<Regex> = Lazy::new(||
-// Regex::new(r"([0-9]+)-([0-9]+)").unwrap()); static ALPHA_RANGE_PATTERN:
-// Lazy<Regex> = Lazy::new(|| Regex::new(r"([a-z])-([a-z])").unwrap());
-// static CMD_RANGE_PATTERN: Lazy<Regex> =
-//     Lazy::new(||
-// Regex::new(r"(?m)^(([0-9]+)-([0-9]+))|(([a-z]+)-([a-z]+))$").unwrap());
-
-pub(crate) static MOD_PATTERN: Lazy<Regex> =
-    Lazy::new(|| Regex::new(&MODIFIER_STR.join("|")).unwrap());
-static MOUSE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"mouse([0-9]+)").unwrap());
-
-// =============== TokenizedLine ==================
-
-/// A line in the configuration file after being tokenized
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) struct TokenizedLine<'a> {
-    /// The split line
-    pub(crate) line:      Line<'a>,
-    /// The tokenized line
-    pub(crate) tokenized: Vec<Vec<Token>>,
-}
+        match (numbered(&first), numbered(&last)) {
+            (Some((name, from)), Some((other, to))) if name == other => {
+                if to < from {
+                    return Err(order());
+                }
+                if name.is_empty() && to > 9 {
+                    return Err(Error::new(ErrorKind::RangeDigits(last), end.span));
+                }
+                return (from..=to)
+                    .map(|n| Self::key(format!("{name}{n}"), span))
+                    .collect();
+            },
+            (Some(_), Some(_)) => return Err(family()),
+            _ => {},
+        }
 
-impl<'a> TokenizedLine<'a> {
-    /// Test whether the keybinding contains a `Token::Release`
-    pub(crate) fn contains_release(&self, idx: usize) -> bool {
-        self.tokenized[idx].contains(&Token::Release)
-    }
+        let hash = KeysymHash::HASH;
+        let code = |key: &Key, name: &str| match key.expr {
+            KeyExpr::Code(code) => Ok(code),
+            _ => hash.get_keysym_code_from_str(name).ok_or_else(|| {
+                Error::new(ErrorKind::UnknownKeysym(name.to_string()), key.span)
+            }),
+        };
+        let (from, to) = (code(start, &first)?, code(end, &last)?);
 
-    /// Test whether the keybinding is a `Release`
-    pub(crate) fn is_release(&self, idx: usize) -> bool {
-        self.tokenized[idx][0] == Token::Release
-    }
+        // Keysyms are grouped by their high bytes (e.g., the cursor keys are
+        // `0xff50` to `0xff58`)
+        if from >> 8 != to >> 8 {
+            return Err(family());
+        }
+        if to < from {
+            return Err(order());
+        }
 
-    /// Flatten the vector of vectors into one vector
-    pub(crate) fn flatten_it(&self) -> Vec<&Token> {
-        self.tokenized.iter().fold(Vec::new(), |mut acc, c| {
-            c.iter().for_each(|i| acc.push(i));
-            acc
-        })
+        Ok((from..=to)
+            .filter(|code| hash.get_str_from_keysym_code(*code).is_some())
+            .map(|code| Key::new(KeyExpr::Code(code), span))
+            .collect())
     }
 
-    /// Finalize the tokenization by returning a flattened vector that is split
-    /// on `Token::Plus`
-    pub(crate) fn finalize_split(&mut self) -> Vec<&Token> {
-        let tokes = self.flatten_it();
-
-        // Another fold is required because after the split, a vector of slices is
-        // returned
-        let split = tokes
-            .split(|c| **c == Token::Plus)
-            .fold(Vec::new(), |mut acc, c| {
-                c.iter().for_each(|i| acc.push(*i));
-                acc
-            })
-            .iter()
-            .copied()
-            .collect::<Vec<_>>();
+    /// Parse an option of the group started at `open`, which is either a key,
+    /// or the keys of a prefix
+    fn option(&mut self, open: Span) -> Result<GroupOption, Error> {
+        if let Some(token) = self.tokens.peek() {
+            if token.kind == TokenKind::Word(SEQ_NONE.to_string()) {
+                self.tokens.next();
+                return Ok(GroupOption::Prefix(Vec::new()));
+            }
+        }
 
-        split
-    }
+        let key = self.element(open)?;
+        if self.tokens.next_if(|t| t.kind == TokenKind::Plus).is_none() {
+            return Ok(GroupOption::Key(key));
+        }
 
-    /// Map common keysyms to their correct UTF-8 representations
-    pub(crate) fn map_common_syms(charmaps: &'a [CharacterMap], tomatch: &'a str) -> &'a str {
-        match tomatch.trim() {
-            "enter" | "return" => "Return",
-            "escape" | "esc" => "Escape",
-            "tab" => "Tab",
-            "backspace" => "BackSpace",
-            "delete" | "del" => "Delete",
-            "pageup" | "PageUp" => "Page_Up",
-            "pagedown" | "PageDown" => "Page_Down",
-            "up" => "Up",
-            "left" => "Left",
-            "right" => "Right",
-            "down" => "Down",
-
-            other => other,
+        let mut prefix = vec![key];
+        while self
+            .tokens
+            .peek()
+            .is_some_and(|t| !matches!(t.kind, TokenKind::Comma | TokenKind::CloseBrace))
+        {
+            prefix.push(self.element(open)?);
+            if self.tokens.next_if(|t| t.kind == TokenKind::Plus).is_none() {
+                let token = self.next("`+`")?;
+                return Err(Self::unexpected("`+`", &token.kind, token.span));
+            }
         }
-    }
 
-    /// Map modifiers to their correct UTF-8 representations
-    pub(crate) fn map_modifiers(charmaps: &'a [CharacterMap], tomatch: &'a str) -> &'a str {
-        // Will match the `modmask` level of `modN` keys with the `CharacterMap`
-        // database, returning the actual modifier for that `mod` key. For example,
-        // `match_modmask(3, "Alt_L")` will match `mod1` and if it fails, `Alt_L` will
-        // be used instead. `Alt_L` is used because that is what it is by default
-        let match_modmask = |mask: u16, or: &'a str| -> &'a str {
-            charmaps
-                .iter()
-                .find(|m| m.modmask() == (1 << mask))
-                .map_or(or, CharacterMap::utf)
-        };
+        Ok(GroupOption::Prefix(prefix))
+    }
 
-        match tomatch.trim() {
-            "caps" | "capslock" => "Caps_Lock",
-            "super" | "lsuper" => "Super_L",
-            "rsuper" => "Super_R",
-            "hyper" | "lhyper" => "Hyper_L",
-            "rhyper" => "Hyper_R",
-            "alt" | "lalt" => "Alt_L",
-            "ralt" => "Alt_R",
-            "shift" | "lshift" => "Shift_L",
-            "rshift" => "Shift_R",
-            "ctrl" | "lctrl" | "control" => "Control_L",
-            "rctrl" => "Control_R",
-            "mod1" => match_modmask(3, "Alt_L"),
-            "mod2" => match_modmask(4, "Num_Lock"),
-            // This one is probably not set on most people's keyboards
-            "mod3" => match_modmask(5, "Hyper_L"),
-            "mod4" => match_modmask(6, "Super_L"),
-            "mod5" => match_modmask(7, "ISO_Level3_Lock"),
-
-            // This will catch the actual `Keysym` named modifiers
-            // i.e., the names on the right side of the match above
-            other => other,
+    /// Parse a key of the group started at `open`, which can be written as a
+    /// keysym code too
+    fn element(&mut self, open: Span) -> Result<Key, Error> {
+        match self.tokens.next() {
+            Some(Token { kind: TokenKind::Word(word), span }) => Self::key(word, span),
+            Some(Token { kind: TokenKind::OpenBracket, span }) => self.code(span),
+            Some(token) => Err(Self::unexpected("a key", &token.kind, token.span)),
+            None => Err(Error::new(ErrorKind::Unclosed(SEQ_START), open)),
         }
     }
 
-    /// Convert a `TokenizedLine` to a `Chain`
-    pub(crate) fn convert_to_chain(
-        &'a mut self,
-        charmaps: &'a [CharacterMap],
-        is_xcape: bool,
-    ) -> Option<Chain> {
-        let line = self.finalize_split();
-        let mut chords = vec![];
-        let mut is_release = false;
-        let mut modmask = ModifierMask::new(0);
-
-        // TODO: Confirm mouse bindings here
-        // TODO: If line contains both `char` and `mouse` return None
-        if line.contains(&&Token::Invalid) {
-            return None;
+    /// Create a `Key` from a word, which is a mouse button if it is written as
+    /// 'mouseN', or a physical key if it is written as '<NAME>'
+    fn key(word: String, span: Span) -> Result<Key, Error> {
+        let name = word
+            .strip_prefix(KEY_NAME_START)
+            .and_then(|w| w.strip_suffix(KEY_NAME_END))
+            .filter(|name| !name.is_empty());
+        if let Some(name) = name {
+            return Ok(Key::new(KeyExpr::KeyName(name.to_string()), span));
         }
 
-        if line.contains(&&Token::Release) {
-            is_release = true;
+        match word.strip_prefix("mouse") {
+            Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => n
+                .parse::<u8>()
+                .map(|n| Key::new(KeyExpr::Mouse(n), span))
+                .map_err(|_| Error::new(ErrorKind::InvalidButton(word.clone()), span)),
+            _ => Ok(Key::new(KeyExpr::Name(word), span)),
         }
+    }
 
-        // for idx in 0..line.len() {
-        //     let bind = line[idx];
-
-        for bind in line {
-            match bind {
-                Token::Modifier(modifier) => {
-                    let mapped = Self::map_modifiers(charmaps, modifier);
-                    if let Some(charmap) = CharacterMap::charmap_from_keysym_utf(charmaps, mapped) {
-                        log::debug!(
-                            "found `modifier`: {}\n{:#?}",
-                            mapped.yellow().bold(),
-                            charmap
-                        );
-
-                        // Skip pushing modifier keys, since the events only register masks
-                        modmask.combine_u16(charmap.modmask());
-                        if is_xcape {
-                            chords.push(Chord::new(
-                                &charmap,
-                                modmask.mask(),
-                                0.into(),
-                                is_release
-                                    .then(|| xproto::KEY_RELEASE_EVENT)
-                                    .unwrap_or(xproto::KEY_PRESS_EVENT),
-                            ));
-                        }
-                    } else {
-                        log::error!("{} was not found in the `CharacterMap` database", mapped);
-                    }
-                },
-                Token::Char(ch) => {
-                    if let Some(mut charmap) =
-                        CharacterMap::charmap_from_keysym_utf(charmaps, &ch.to_string())
-                    {
-                        log::debug!(
-                            "found `char`: {}\n{:#?}",
-                            ch.to_string().yellow().bold(),
-                            charmap
-                        );
-
-                        // TODO: Confirm: Convert uppercase characters to shift + lowercase
-                        // If the character is a captital letter, then change the character
-                        // to a lowercase character, so we can grab it.
-                        //
-                        // For whatever reason, it registers a correct key event and grabbed it, but
-                        // since the shift character has to be held to create a capital letter, the
-                        // keysyms are different and it isn't picking it up?
-                        if (charmap.symbol() >= 0x41 && charmap.symbol() <= 0x5A)
-                            || (charmap.symbol() >= 0xC0 && charmap.symbol() <= 0xD6)
-                            || (charmap.symbol() >= 0xD8 && charmap.symbol() <= 0xDE)
-                        {
-                            modmask.combine_u16(1 << u16::from(MapIndex::SHIFT));
-                            if let Some(lower) = CharacterMap::charmap_from_keysym_utf(
-                                charmaps,
-                                &ch.to_string().to_lowercase(),
-                            ) {
-                                log::debug!(
-                                    "converting uppercase {} to lowercase",
-                                    charmap.utf().green().bold()
-                                );
-                                charmap = lower;
-                            } else {
-                                log::warn!(
-                                    "failed to convert uppercase {} to lowercase",
-                                    charmap.utf().green().bold()
-                                );
-                                log::warn!(
-                                    "instead of using <modifier + CAP>; use <modifier + shift + \
-                                     lower>"
-                                );
-                            }
-                        }
-
-                        chords.push(Chord::new(
-                            &charmap,
-                            modmask.mask(),
-                            0.into(),
-                            is_release
-                                .then(|| xproto::KEY_RELEASE_EVENT)
-                                .unwrap_or(xproto::KEY_PRESS_EVENT),
-                        ));
-                    } else {
-                        log::info!("{} was not found in the `CharacterMap` database", ch);
-                    }
-                },
-                Token::RangeGroup(group) =>
-                    for ch in group {
-                        if let Some(charmap) =
-                            CharacterMap::charmap_from_keysym_utf(charmaps, &ch.to_string())
-                        {
-                            log::debug!(
-                                "found `RangeGroup(char)`: {}\n{:#?}",
-                                ch.to_string().yellow().bold(),
-                                charmap
-                            );
-                            chords.push(Chord::new(
-                                &charmap,
-                                modmask.mask(),
-                                0.into(),
-                                is_release
-                                    .then(|| xproto::KEY_RELEASE_EVENT)
-                                    .unwrap_or(xproto::KEY_PRESS_EVENT),
-                            ));
-                        } else {
-                            log::info!("{} was not found in the `CharacterMap` database", ch);
-                        }
-                    },
-                Token::Text(text) | Token::KeysymString(text) => {
-                    let mapped = Self::map_common_syms(charmaps, text);
-                    if let Some(charmap) = CharacterMap::charmap_from_keysym_utf(charmaps, mapped) {
-                        log::debug!("found `text`: {}\n{:#?}", text.yellow().bold(), charmap);
-                        chords.push(Chord::new(
-                            &charmap,
-                            modmask.mask(),
-                            0.into(),
-                            is_release
-                                .then(|| xproto::KEY_RELEASE_EVENT)
-                                .unwrap_or(xproto::KEY_PRESS_EVENT),
-                        ));
-                    }
-                },
-                Token::Mouse(n) => {
-                    let charmap = CharacterMap::blank_charmap(&format!("mouse{}", n));
-                    log::debug!(
-                        "found `mouse`: {}\n{:#?}",
-                        n.to_string().yellow().bold(),
-                        charmap
-                    );
-                    chords.push(Chord::new(
-                        &charmap,
-                        modmask.mask(),
-                        ButtonCode::from(*n),
-                        is_release
-                            .then(|| xproto::BUTTON_RELEASE_EVENT)
-                            .unwrap_or(xproto::BUTTON_PRESS_EVENT),
-                    ));
-                },
-                _ => {},
-            }
+    /// Return the next token, or an error expecting `expected` if there are
+    /// none left
+    fn next(&mut self, expected: &'static str) -> Result<Token, Error> {
+        let end = Span::new(self.len, self.len);
+        self.tokens
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEnd(expected), end))
+    }
+
+    /// Parse the token of `kind` that closes the `opening` one at `open`,
+    /// returning its `Span`
+    fn close(
+        &mut self,
+        kind: &TokenKind,
+        opening: char,
+        open: Span,
+        expected: &'static str,
+    ) -> Result<Span, Error> {
+        match self.tokens.next() {
+            Some(token) if token.kind == *kind => Ok(token.span),
+            Some(token) => Err(Self::unexpected(expected, &token.kind, token.span)),
+            None => Err(Error::new(ErrorKind::Unclosed(opening), open)),
         }
+    }
 
-        // println!("== CHORDS: :{:#?}", chords);
+    /// Create an error for a token of `kind` found in place of `expected`
+    fn unexpected(expected: &'static str, kind: &TokenKind, span: Span) -> Error {
+        Error::new(ErrorKind::Unexpected(expected, kind.to_string()), span)
+    }
+}
 
-        Some(Chain::new(chords, is_release, modmask))
+/// Return the text a key was written as
+fn text(key: &Key) -> String {
+    match &key.expr {
+        KeyExpr::Name(name) => name.clone(),
+        KeyExpr::Code(code) => format!("[{code:#x}]"),
+        KeyExpr::Keycode(code) => format!("[{KEYCODE_PREFIX}{code}]"),
+        KeyExpr::KeyName(name) => format!("{KEY_NAME_START}{name}{KEY_NAME_END}"),
+        KeyExpr::Mouse(n) => format!("mouse{n}"),
     }
+}
 
-    /// A function that runs all of the tokenizing/parsing functions in the
-    /// order they're supposed to be ran
-    pub(crate) fn parse_tokens(&mut self) -> Result<()> {
-        // Check for `Keysym` indicators before matching with regex
-        // which may convert it to a `char`
-        self.check_keysym();
+/// Split a name that ends with a number (e.g., `F12`) into the name before it
+/// and the number
+fn numbered(name: &str) -> Option<(&str, u32)> {
+    let start = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let number = name[start..].parse::<u32>().ok()?;
+    Some((&name[..start], number))
+}
 
-        /// Tokenize with regular expressions. Perhaps only using these, with
-        /// less iteration and less tokenizing would be easier
-        self.regex_token()?;
+/// Return the character of `name` if it is a single character
+fn as_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    chars.next().filter(|_| chars.next().is_none())
+}
 
-        /// Expand ranges ({a-c}) after converting items to `char` from
-        /// `regex_token`
-        self.expand_range();
+// ================== Modifiers ===================
 
-        /// Split `Options` ({a,c}) after converting items to `char` from
-        /// `regex_token`
-        self.split_option();
+// TODO: Do something with `fn` and `meh`
 
-        log::debug!("{}: {:#?}", "Binding".green().bold(), self.tokenized);
+#[rustfmt::skip]
+const MODIFIER_STR: &[&str] = &[
+    "alt",   "lalt",      "ralt",       "Alt_L",    "Alt_R",
+    "shift", "lshift",    "rshift",     "Shift_L",  "Shift_R",
+    "super", "lsuper",    "rsuper",     "Super_L",  "Super_R",
+    "meta",  "lmeta",     "rmeta",      "Meta_L",   "Meta_R",
+    "ctrl",  "control",   "lctrl",      "rctrl",    "Control_L",   "Control_R",
+    "hyper", "lhyper",    "rhyper",     "Hyper_L",  "Hyper_R",
+    "mod1",  "mod2",      "mod3",       "mod4",     "mod5",
+    "lock",  "Caps_Lock", "Shift_Lock", "Num_Lock", "Scroll_Lock", // Unsure if these are needed
+    "fn",    "meh",
+];
 
-        Ok(())
-    }
+/// The modifiers that only match while they are held on one side of the
+/// keyboard. The others match either side, including the keysyms of a side
+/// (e.g., `Super_L`), which are what the modifiers are looked up as
+#[rustfmt::skip]
+const SIDED_MODIFIER_STR: &[&str] = &[
+    "lalt",   "ralt",   "lshift", "rshift", "lsuper", "rsuper",
+    "lmeta",  "rmeta",  "lctrl",  "rctrl",  "lhyper", "rhyper",
+];
 
-    /// Further tokenize the `Token::Text` items in the line by using regular
-    /// expressions. Modifies the `TokenizedLine` in place, resulting in a
-    /// `Mouse`, `Modifier`, and `Char` `Token`s being added
-    pub(crate) fn regex_token(&mut self) -> Result<()> {
-        let hash = KeysymHash::HASH;
+/// Return whether `name` is the name of a modifier
+pub(crate) fn is_modifier(name: &str) -> bool {
+    MODIFIER_STR.contains(&name)
+}
 
-        for vec_idx in 0..self.tokenized.len() {
-            for tok_idx in 0..self.tokenized[vec_idx].len() {
-                match &self.tokenized[vec_idx][tok_idx] {
-                    Token::Text(text) | Token::KeysymString(text) =>
-                        if MOD_PATTERN.is_match(text) {
-                            log::trace!("Found {}({})", "Token::Modifier".red().bold(), text);
-                            self.tokenized[vec_idx][tok_idx] = Token::Modifier(text.to_string());
-                        } else if MOUSE_PATTERN.is_match(text) {
-                            log::trace!("Found {}({})", "Token::Mouse".red().bold(), text);
-                            self.tokenized[vec_idx][tok_idx] =
-                                Token::Mouse(text.replace("mouse", "").parse::<u8>().context(
-                                    "mouse buttons are defined by 'mouseN' where 'N' is a number \
-                                     1-5",
-                                )?);
-                        } else if text.len() == 1 {
-                            let char = text
-                                .parse::<char>()
-                                .context("failure in determining length of character")?;
-
-                            if hash.get_keysym_code_from_char(char).is_some() {
-                                log::debug!("Found {}({})", "Token::Char".red().bold(), text);
-                                self.tokenized[vec_idx][tok_idx] = Token::Char(char);
-                            } else {
-                                log::info!(
-                                    "Unkown {}({})",
-                                    "Token::UnknownChar".red().bold(),
-                                    text
-                                );
-                                self.tokenized[vec_idx][tok_idx] = Token::UnknownChar(char);
-                            }
-                        },
-                    _ => {},
-                }
-            }
-        }
+/// Return whether `name` is the name of a modifier that is held on one side
+pub(crate) fn is_sided_modifier(name: &str) -> bool {
+    SIDED_MODIFIER_STR.contains(&name)
+}
 
-        Ok(())
+/// Map common keysyms to their correct UTF-8 representations
+pub(crate) fn map_common_syms(tomatch: &str) -> &str {
+    match tomatch.trim() {
+        "enter" | "return" => "Return",
+        "escape" | "esc" => "Escape",
+        "tab" => "Tab",
+        "backspace" => "BackSpace",
+        "delete" | "del" => "Delete",
+        "pageup" | "PageUp" => "Page_Up",
+        "pagedown" | "PageDown" => "Page_Down",
+        "up" => "Up",
+        "left" => "Left",
+        "right" => "Right",
+        "down" => "Down",
+
+        other => other,
     }
+}
 
-    /// Check if the `TokenizedLine` contains a `Keysym` start indicator '[' to
-    /// map the string to the correct code
-    pub(crate) fn check_keysym(&mut self) -> Result<()> {
-        let hash = KeysymHash::HASH;
+/// Map modifiers to the keysym of a key on the keyboard that sets them. The
+/// modifiers named after keys (e.g., `super`) are the first of their keys that
+/// is on the keyboard, and the real modifiers (e.g., `mod4`) are a key that
+/// sets only that modifier in the modifier map of the keyboard
+pub(crate) fn map_modifiers<'a>(charmaps: &'a [CharacterMap], tomatch: &'a str) -> &'a str {
+    let tomatch = tomatch.trim();
+    let on_keyboard = |utf: &str| charmaps.iter().any(|c| c.utf() == utf);
 
-        for vec_idx in 0..self.tokenized.len() {
-            for tok_idx in 0..self.tokenized[vec_idx].len() {
-                if self.tokenized[vec_idx][tok_idx] == Token::KeysymStart {
-                    if let Token::Text(keysym_code) = &self.tokenized[vec_idx][tok_idx + 1] {
-                        let code = {
-                            if keysym_code.starts_with("0x") {
-                                u32::from_str_radix(keysym_code.trim_start_matches("0x"), 16)
-                            } else {
-                                keysym_code.parse::<u32>()
-                            }
-                        }
-                        .with_context(|| {
-                            format!(
-                                "a Keysym code was indicated in the configuration file, but {} is \
-                                 not a number. Either remove the brackets '[]' or use a valid \
-                                 Keysym code",
-                                keysym_code.red().bold()
-                            )
-                        })?;
-
-                        if let Some(keysym_str) = hash.get_str_from_keysym_code(code) {
-                            log::info!(
-                                "<{}> - Found {}({}) => {}",
-                                self.line.to_string().purple().bold(),
-                                "Token::Keysym".red().bold(),
-                                keysym_code.yellow().bold(),
-                                keysym_str.green().bold()
-                            );
-                            let mut res = vec![];
-                            if self.contains_release(vec_idx) {
-                                res.push(Token::Release);
-                            }
-                            res.push(Token::KeysymString(keysym_str.clone()));
-                            self.tokenized[vec_idx] = res;
-                            break;
-                        }
-                        log::info!(
-                            "<{}> - Unknown keysym code: {}",
-                            self.line.to_string().purple().bold(),
-                            keysym_code.yellow().bold()
-                        );
-                        self.tokenized[vec_idx][tok_idx + 1] =
-                            Token::Unknown(keysym_code.to_string());
-                    }
-                }
-            }
-        }
+    if let Some(keysyms) = modifiers::keysyms(tomatch) {
+        return keysyms
+            .iter()
+            .find(|utf| on_keyboard(utf))
+            .map_or(keysyms[0], |utf| *utf);
+    }
+
+    let real = match tomatch {
+        "mod1" => MapIndex::M1,
+        "mod2" => MapIndex::M2,
+        "mod3" => MapIndex::M3,
+        "mod4" => MapIndex::M4,
+        "mod5" => MapIndex::M5,
+        // This will catch the actual `Keysym` named modifiers (e.g., `Super_L`)
+        other => return other,
+    };
+
+    charmaps
+        .iter()
+        .find(|c| is_modifier(c.utf()) && c.modmask() == 1 << u16::from(real))
+        .map_or(tomatch, CharacterMap::utf)
+}
 
-        Ok(())
-    }
+// ================== Resolution ==================
+
+/// A key once it has been looked up on the keyboard
+#[derive(Debug, Clone)]
+enum Resolved {
+    /// A modifier, which is folded into the mask of the other keys, along with
+    /// the side it has to be held on
+    Modifier(CharacterMap, Sides),
+    /// A key, along with the modifiers it needs on its own
+    Key(CharacterMap, u16),
+    /// A physical key, which is matched in every layout group
+    Physical(CharacterMap),
+    /// A mouse button
+    Mouse(u8),
+}
 
-    /// Split the comma within an option to convert: {a,c,b} to an
-    /// `OptionGroup('a', 'c', 'b')`
-    pub(crate) fn split_option(&mut self) {
-        for vec_idx in 0..self.tokenized.len() {
-            if self.tokenized[vec_idx].contains(&Token::SeqStart)
-                && self.tokenized[vec_idx].contains(&Token::Comma)
-                && self.tokenized[vec_idx].contains(&Token::SeqEnd)
-            {
-                for tok_idx in 0..self.tokenized[vec_idx].len() {
-                    let mut is_release = false;
-                    if self.is_release(vec_idx) {
-                        is_release = true;
-                        self.tokenized[vec_idx].remove(0);
-                    }
-                    // Don't match the end option because there can be more than two `char`
-                    if let [Token::SeqStart, Token::Char(_), Token::Comma, Token::Char(_)] =
-                        &self.tokenized[vec_idx][..4]
-                    {
-                        log::info!(
-                            "<{}> - Found {}({:?})",
-                            self.line.to_string().purple().bold(),
-                            "Token::OptionGroup".red().bold(),
-                            self.tokenized[vec_idx]
-                        );
-                        // Split and take every other item
-                        let splits = &self.tokenized[vec_idx]
-                            .iter()
-                            .skip(1)
-                            .step_by(2)
-                            .cloned()
-                            .collect::<Vec<_>>();
-
-                        if splits.iter().all(|c| matches!(c, Token::Char(_))) {
-                            self.tokenized[vec_idx] = vec![Token::OptionGroup(
-                                splits
-                                    .iter()
-                                    .filter_map(|c| match *c {
-                                        Token::Char(ch) => Some(ch),
-                                        _ => None,
-                                    })
-                                    .collect::<Vec<_>>(),
-                            )];
-                            if is_release {
-                                self.tokenized[vec_idx].insert(0, Token::Release);
-                            }
-                            break;
-                        }
-                    }
-                    log::warn!(
-                        "invalid option found in configuration: {}\n{} may only be single 'char's \
-                         or digits. For example: {{a,b,c}}",
-                        "Options".green().bold(),
-                        self.line.vector[vec_idx].to_string().red().bold()
-                    );
-                    // Was an invalid option so replace item with an `Invalid` token
-                    self.tokenized[vec_idx] = vec![Token::Invalid];
-                }
-            }
-        }
-    }
+impl Resolved {
+    /// Create the `Chord` of the key, pressed along with `modmask` held on
+    /// `sides`
+    fn into_chord(self, mut modmask: ModifierMask, sides: Sides, release: bool) -> Chord {
+        let (key_event, button_event) = if release {
+            (xproto::KEY_RELEASE_EVENT, xproto::BUTTON_RELEASE_EVENT)
+        } else {
+            (xproto::KEY_PRESS_EVENT, xproto::BUTTON_PRESS_EVENT)
+        };
 
-    // TODO: Possibly add support for keysym codes in ranges
-    // TODO: Check char len, if ranges are single chars, it will be len 5
-
-    /// Expand the range operator within an option to convert {a-c} to
-    /// `RangeGroup('a', 'b', 'c')`
-    #[rustfmt::skip] // Can't use on statements without nightly
-    pub(crate) fn expand_range(&mut self) {
-        for vec_idx in 0..self.tokenized.len() {
-            // Shitty way, but check if it contains {..}
-            if self.tokenized[vec_idx].contains(&Token::SeqStart)
-                && self.tokenized[vec_idx].contains(&Token::Dash)
-                && self.tokenized[vec_idx].contains(&Token::SeqEnd)
-            {
-                let tok_idx = 0;
-                let mut is_release = false;
-                // Remove release to add back later after parsing
-                if self.is_release(vec_idx) {
-                    is_release = true;
-                    self.tokenized[vec_idx].remove(0);
-                }
+        let mut chord = match self {
+            // The event of a modifier does not have its own mask set yet
+            Self::Modifier(charmap, _) => {
+                modmask.ignore(charmap.modmask());
+                Chord::new(&charmap, modmask.mask(), 0.into(), key_event)
+            },
+            Self::Key(charmap, mask) => {
+                modmask.combine_u16(mask);
+                Chord::new(&charmap, modmask.mask(), 0.into(), key_event)
+            },
+            Self::Physical(charmap) => Chord::new(&charmap, modmask.mask(), 0.into(), key_event),
+            Self::Mouse(n) => Chord::new(
+                &CharacterMap::blank_charmap(&format!("mouse{n}")),
+                modmask.mask(),
+                ButtonCode::from(n),
+                button_event,
+            ),
+        };
 
-                if self.tokenized[vec_idx][tok_idx] == Token::SeqStart {
-                    if let [
-                        Token::SeqStart,
-                        Token::Char(_),
-                        Token::Dash,
-                        Token::Char(_),
-                        Token::SeqEnd
-                    ] =
-                        &self.tokenized[vec_idx][..]
-                    {
-                        let start = &self.tokenized[vec_idx][tok_idx + 1];
-                        let end = &self.tokenized[vec_idx][tok_idx + 3];
-                        log::info!(
-                            "<{}> - Found {}({})",
-                            self.line.to_string().purple().bold(),
-                            "Token::RangeGroup".red().bold(),
-                            format!("{}-{}", start, end)
-                        );
-
-                        let mut chars = vec![];
-                        if let Token::Char(s) = start {
-                            if let Token::Char(e) = end {
-                                if e < s {
-                                    log::warn!(
-                                        "<{}>: range end ({}) must be less than range start ({})",
-                                        self.line.vector[vec_idx].red().bold(),
-                                        e,
-                                        s
-                                    );
-                                    self.tokenized[vec_idx] = vec![Token::Invalid];
-                                    break;
-                                }
-
-                                for n in *s..=*e {
-                                    chars.push(n);
-                                }
-                            }
-                        }
-
-                        if !chars.is_empty() {
-                            self.tokenized[vec_idx] = vec![Token::RangeGroup(chars)];
-                            if is_release {
-                                self.tokenized[vec_idx].insert(0, Token::Release);
-                            }
-                            break;
-                        }
-                    }
-                }
-                log::warn!(
-                    "invalid range found in configuration: {}",
-                    "Ranges".green().bold(),
-                );
-                log::warn!(
-                    "{} may only be single 'char's or digits. For example: {{a-e}}",
-                    self.line.vector[vec_idx].to_string().red().bold()
-                );
-                // Was an invalid range so replace item with an `Invalid` token
-                self.tokenized[vec_idx] = vec![Token::Invalid];
-            }
+        chord.update_sides(sides);
+        chord
+    }
+
+    /// Return the key in every layout group it is matched in. A physical key
+    /// is matched in every group. A keysym is matched in the group it was
+    /// found in, unless `all_groups` is set
+    fn in_groups(self, charmaps: &[CharacterMap], all_groups: bool) -> Vec<Self> {
+        match self {
+            Self::Physical(charmap) => CharacterMap::charmaps_from_keycode(charmaps, charmap.code())
+                .into_iter()
+                .map(Self::Physical)
+                .collect(),
+            Self::Key(charmap, mask) if all_groups => {
+                let last = charmaps.iter().map(CharacterMap::group).max().unwrap_or(0);
+                (0..=last)
+                    .filter_map(|group| {
+                        let mut in_group = charmaps.iter().filter(|c| c.group() == group);
+                        in_group
+                            .clone()
+                            .find(|c| c.utf() == charmap.utf())
+                            .or_else(|| in_group.find(|c| c.code() == charmap.code()))
+                            .map(|c| Self::Key(c.clone(), mask))
+                    })
+                    .collect()
+            },
+            other => vec![other],
         }
     }
 }
 
-// ==================== Tests =====================
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn token_empty() -> Result<()> {
-        let line = Line::new_plus("", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert!(tokenized.tokenized.is_empty());
-        Ok(())
-    }
-
-    #[test]
-    fn token_char() -> Result<()> {
-        let line = Line::new_plus("a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Char('a')]]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_text() -> Result<()> {
-        let line = Line::new_plus("abc", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Text(String::from(
-            "abc"
-        ))]]);
-        Ok(())
-    }
+/// Look up a single key on the keyboard
+fn resolve_key(charmaps: &[CharacterMap], hash: &KeysymHash, key: &Key) -> Result<Resolved, Error> {
+    let lookup = |utf: &str| CharacterMap::charmap_from_keysym_utf(charmaps, utf);
+    let unknown = |name: &str| Error::new(ErrorKind::UnknownKeysym(name.to_string()), key.span);
+
+    let physical = match &key.expr {
+        KeyExpr::Keycode(code) => Some(
+            CharacterMap::charmap_from_keycode(charmaps, *code)
+                .ok_or_else(|| Error::new(ErrorKind::UnknownKeycode(*code), key.span))?,
+        ),
+        KeyExpr::KeyName(name) => Some(
+            charmaps
+                .iter()
+                .find(|c| c.name() == name)
+                .cloned()
+                .ok_or_else(|| Error::new(ErrorKind::UnknownKeyName(name.clone()), key.span))?,
+        ),
+        _ => None,
+    };
+    if let Some(charmap) = physical {
+        return Ok(if is_modifier(charmap.utf()) {
+            Resolved::Modifier(charmap, Sides::default())
+        } else {
+            Resolved::Physical(charmap)
+        });
+    }
+
+    let name = match &key.expr {
+        KeyExpr::Mouse(n) => return Ok(Resolved::Mouse(*n)),
+        KeyExpr::Code(code) => hash
+            .get_str_from_keysym_code(*code)
+            .ok_or_else(|| Error::new(ErrorKind::UnknownCode(*code), key.span))?,
+        KeyExpr::Name(name) => name,
+        KeyExpr::Keycode(_) | KeyExpr::KeyName(_) => unreachable!("physical keys are found above"),
+    };
+
+    if is_modifier(name) {
+        return lookup(map_modifiers(charmaps, name))
+            .map(|charmap| {
+                let sides = if is_sided_modifier(name) {
+                    Sides::of_key(&charmap)
+                } else {
+                    Sides::default()
+                };
+                Resolved::Modifier(charmap, sides)
+            })
+            .ok_or_else(|| Error::new(ErrorKind::InvalidModifier(name.clone()), key.span));
+    }
+
+    let Some(ch) = as_char(name) else {
+        return lookup(map_common_syms(name))
+            .map(|charmap| Resolved::Key(charmap, 0))
+            .ok_or_else(|| unknown(name));
+    };
+
+    // Characters that are not letters or digits are named differently (e.g.,
+    // `/` is `slash`). The keysyms of Latin-1 characters are their code points
+    let charmap = lookup(name)
+        .or_else(|| {
+            let code = u32::from(ch);
+            (code <= 0xff)
+                .then_some(code)
+                .or_else(|| hash.get_keysym_code_from_char(ch))
+                .and_then(|code| hash.get_str_from_keysym_code(code))
+                .and_then(|name| lookup(name))
+        })
+        .ok_or_else(|| unknown(name))?;
+
+    // An uppercase character is bound as shift and the lowercase character,
+    // since shift has to be held to type it and the lowercase keysym is the
+    // one that is grabbed
+    if (charmap.symbol() >= 0x41 && charmap.symbol() <= 0x5A)
+        || (charmap.symbol() >= 0xC0 && charmap.symbol() <= 0xD6)
+        || (charmap.symbol() >= 0xD8 && charmap.symbol() <= 0xDE)
+    {
+        let shift = 1 << u16::from(MapIndex::SHIFT);
+        if let Some(lower) = lookup(&name.to_lowercase()) {
+            log::debug!("converting uppercase {} to lowercase", charmap.utf().green().bold());
+            return Ok(Resolved::Key(lower, shift));
+        }
 
-    #[test]
-    fn token_modifier() -> Result<()> {
-        let line = Line::new_plus("super", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Modifier(
-            String::from("super")
-        )]]);
-        Ok(())
+        log::warn!("failed to convert uppercase {} to lowercase", charmap.utf().green().bold());
+        log::warn!("instead of using <modifier + CAP>; use <modifier + shift + lower>");
+        return Ok(Resolved::Key(charmap, shift));
     }
 
-    #[test]
-    fn token_modifier_left() -> Result<()> {
-        let line = Line::new_plus("lsuper", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Modifier(
-            String::from("lsuper")
-        )]]);
-        Ok(())
-    }
+    Ok(Resolved::Key(charmap, 0))
+}
 
-    #[test]
-    fn token_modifier_left_keysym_str() -> Result<()> {
-        let line = Line::new_plus("Hyper_L", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Modifier(
-            String::from("Hyper_L")
-        )]]);
-        Ok(())
-    }
+/// Look up the keys of a chain on the keyboard once its groups are expanded.
+/// Every key of a chord creates its own `Chord`, with the modifiers of the
+/// chord. A chord made up of only modifiers (e.g., `Shift_L`) matches the
+/// press of the last one. A `Chain` is returned for every combination of the
+/// layout groups its keys are matched in
+fn resolve(
+    charmaps: &[CharacterMap],
+    hash: &KeysymHash,
+    chain: &ExpandedChain,
+    all_groups: bool,
+) -> Result<Vec<Chain>, Vec<Error>> {
+    let mut errors = Vec::new();
+    let mut keys_in_groups = Vec::new();
+
+    for keys in chain {
+        let release = keys.iter().any(|k| k.release);
+        let mut modmask = ModifierMask::new(0);
+        let mut sides = Sides::default();
+        let mut last_modifier = None;
+        let mut resolved = Vec::new();
+
+        for ExpandedKey { key, .. } in keys {
+            match resolve_key(charmaps, hash, key) {
+                Ok(Resolved::Modifier(charmap, side)) => {
+                    modmask.combine_u16(charmap.modmask());
+                    sides.combine(side);
+                    last_modifier = Some(Resolved::Modifier(charmap, side));
+                },
+                Ok(other) => resolved.push(other),
+                Err(e) => errors.push(e),
+            }
+        }
 
-    #[test]
-    fn token_range_char() -> Result<()> {
-        let line = Line::new_plus("a-c", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Char('a'),
-            Token::Dash,
-            Token::Char('c'),
-        ]]);
-        Ok(())
-    }
+        if resolved.is_empty() {
+            resolved.extend(last_modifier);
+        }
 
-    #[test]
-    fn token_range_char_space() -> Result<()> {
-        let line = Line::new_plus("a - c", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Char('a'),
-            Token::Dash,
-            Token::Char('c'),
-        ]]);
-        Ok(())
+        for key in resolved {
+            let keys = key.in_groups(charmaps, all_groups);
+            keys_in_groups.push((keys, modmask, sides, release));
+        }
     }
 
-    // NOTE: This should never be allowed in the configuration
-    #[test]
-    fn token_range_str() -> Result<()> {
-        let line = Line::new_plus("abc-cba", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Text(String::from("abc")),
-            Token::Dash,
-            Token::Text(String::from("cba")),
-        ]]);
-        Ok(())
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
-    // NOTE: This should never be allowed in the configuration
-    #[test]
-    fn token_range_str_space() -> Result<()> {
-        let line = Line::new_plus("abc - cba", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Text(String::from("abc")),
-            Token::Dash,
-            Token::Text(String::from("cba")),
-        ]]);
-        Ok(())
-    }
+    let mut chains = vec![(Vec::new(), ModifierMask::new(0))];
+    for (keys, modmask, sides, release) in keys_in_groups {
+        chains = chains
+            .into_iter()
+            .flat_map(|(chords, chain_mask)| {
+                keys.iter().map(move |key| {
+                    let chord = key.clone().into_chord(modmask, sides, release);
+                    let mut chain_mask = chain_mask;
+                    chain_mask.combine_modmask(chord.modmask());
 
-    #[test]
-    fn token_plus() -> Result<()> {
-        let line = Line::new_plus("super+t", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Char('t')],
-        ]);
-        Ok(())
+                    let mut chords = chords.clone();
+                    chords.push(chord);
+                    (chords, chain_mask)
+                })
+            })
+            .collect();
     }
 
-    #[test]
-    fn token_plus_space() -> Result<()> {
-        let line = Line::new_plus("super + t", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Char('t')],
-        ]);
-        Ok(())
-    }
+    let is_release = chain.iter().flatten().any(|k| k.release);
+    Ok(chains
+        .into_iter()
+        .map(|(chords, chain_mask)| Chain::new(chords, is_release, chain_mask))
+        .collect())
+}
 
-    #[test]
-    fn token_option() -> Result<()> {
-        let line = Line::new_plus("{a,b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::OptionGroup(vec![
-            'a', 'b'
-        ])]]);
-        Ok(())
-    }
+// ================ Helper Funcs ==================
 
-    #[test]
-    fn token_option_space() -> Result<()> {
-        let line = Line::new_plus("{a, b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::OptionGroup(vec![
-            'a', 'b'
-        ])]]);
-        Ok(())
-    }
+/// Parse a set of keys into its syntax tree
+pub(crate) fn parse(input: &str) -> Result<ChainExpr, Error> {
+    Parser::new(input).parse()
+}
 
-    #[test]
-    fn token_option_three() -> Result<()> {
-        let line = Line::new_plus("{a,b,z}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::OptionGroup(vec![
-            'a', 'b', 'z'
-        ])]]);
-        Ok(())
+/// Parse a set of keys, returning a `Chain` for every combination of keys its
+/// groups expand to, and of the layout groups its keys are matched in. Keysyms
+/// are matched in every layout group if `all_groups` is set. Every problem with
+/// the keys is returned
+pub(crate) fn parse_chains(
+    charmaps: &[CharacterMap],
+    input: &str,
+    all_groups: bool,
+) -> Result<Vec<Chain>, Vec<Error>> {
+    let expr = parse(input).map_err(|e| vec![e])?;
+    let hash = KeysymHash::HASH;
+
+    let mut chains = Vec::new();
+    let mut errors = Vec::new();
+
+    for expanded in expr.expand() {
+        match resolve(charmaps, &hash, &expanded, all_groups) {
+            Ok(found) => chains.extend(found),
+            // A key outside of the groups is the same in every expansion
+            Err(found) =>
+                for e in found {
+                    if !errors.contains(&e) {
+                        errors.push(e);
+                    }
+                },
+        }
     }
 
-    #[test]
-    fn token_option_three_space() -> Result<()> {
-        let line = Line::new_plus("{a, b, z}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::OptionGroup(vec![
-            'a', 'b', 'z'
-        ])]]);
-        Ok(())
+    if errors.is_empty() {
+        Ok(chains)
+    } else {
+        Err(errors)
     }
+}
 
-    #[test]
-    fn token_option_range() -> Result<()> {
-        let line = Line::new_plus("{a-b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::RangeGroup(vec![
-            'a', 'b'
-        ])]]);
-        Ok(())
-    }
+/// Parse a set of keys into a single `Chain`, which cannot have any groups. A
+/// physical key is the one of the first layout group
+pub(crate) fn parse_chain(charmaps: &[CharacterMap], input: &str) -> Result<Chain, Vec<Error>> {
+    let expr = parse(input).map_err(|e| vec![e])?;
 
-    #[test]
-    fn token_option_range_space() -> Result<()> {
-        let line = Line::new_plus("{a - b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::RangeGroup(vec![
-            'a', 'b'
-        ])]]);
-        Ok(())
+    if let Some(group) = expr.groups().next() {
+        return Err(vec![Error::new(ErrorKind::Group, group.span)]);
     }
 
-    #[test]
-    fn token_option_range_three() -> Result<()> {
-        let line = Line::new_plus("{a-c}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::RangeGroup(vec![
-            'a', 'b', 'c'
-        ])]]);
-        Ok(())
+    let hash = KeysymHash::HASH;
+    match expr.expand().first() {
+        Some(expanded) => resolve(charmaps, &hash, expanded, false)
+            .map(|chains| chains.into_iter().next().unwrap_or_default()),
+        None => Ok(Chain::default()),
     }
+}
 
-    #[test]
-    fn token_option_range_three_space() -> Result<()> {
-        let line = Line::new_plus("{a - c}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::RangeGroup(vec![
-            'a', 'b', 'c'
-        ])]]);
-        Ok(())
-    }
+/// Split a sequence of keys on whitespace, returning each set of keys along
+/// with its offset in `input`
+pub(crate) fn split_keys(input: &str) -> impl Iterator<Item = (usize, &str)> {
+    input.split_whitespace().scan(0, move |start, keys| {
+        let offset = *start + input[*start..].find(keys)?;
+        *start = offset + keys.len();
+        Some((offset, keys))
+    })
+}
 
-    #[test]
-    fn token_option_range_less_than_char() -> Result<()> {
-        let line = Line::new_plus("{c-a}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Invalid]]);
-        Ok(())
-    }
+/// Return the line number and column of `text` in the configuration file
+/// `contents`, where `text` is either the item `key` of `section` or one of its
+/// values. Items of a section are only searched for after the start of that
+/// section, and global settings are searched for from the start of the file
+pub(crate) fn locate(
+    contents: &str,
+    section: &str,
+    key: &str,
+    text: &str,
+) -> Option<(usize, usize)> {
+    /// Quotes that can surround a key
+    const QUOTES: &[char] = &['"', '\''];
+
+    /// Return the rest of `line` after `key` and a colon, ignoring quotes
+    fn strip_key<'l>(line: &'l str, key: &str) -> Option<&'l str> {
+        line.trim_start()
+            .trim_start_matches(QUOTES)
+            .strip_prefix(key)
+            .map(|rest| rest.trim_start_matches(QUOTES).trim_start())
+            .and_then(|rest| rest.strip_prefix(':'))
+    }
+
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let column = |line: &str, offset: usize| line[..offset].chars().count();
+
+    let lines = contents.lines().collect::<Vec<_>>();
+    let start = if section == "global" {
+        0
+    } else {
+        let kebab = section.replace('_', "-");
+        lines.iter().position(|line| {
+            !line.starts_with(char::is_whitespace)
+                && (strip_key(line, section).is_some() || strip_key(line, &kebab).is_some())
+        })?
+    };
+
+    let (idx, line) = lines
+        .iter()
+        .enumerate()
+        .skip(start)
+        .find(|(_, line)| strip_key(line, key).is_some())?;
+
+    if text == key {
+        let offset = line.len() - line.trim_start().trim_start_matches(QUOTES).len();
+        return Some((idx + 1, column(line, offset)));
+    }
+
+    // A value is either on the same line as its key, or on one of the lines
+    // below it that are indented further
+    let rest = strip_key(line, key)?;
+    if let Some(found) = rest.find(text) {
+        return Some((idx + 1, column(line, line.len() - rest.len() + found)));
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .skip(idx + 1)
+        .take_while(|(_, below)| below.trim().is_empty() || indent(below) > indent(line))
+        .find_map(|(below_idx, below)| {
+            let value = below.find(':').map_or(0, |colon| colon + 1);
+            below[value..]
+                .find(text)
+                .map(|found| (below_idx + 1, column(below, value + found)))
+        })
+}
 
-    #[test]
-    fn token_option_range_less_than_digit() -> Result<()> {
-        let line = Line::new_plus("{3-1}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Invalid]]);
-        Ok(())
-    }
+// ==================== Tests =====================
 
-    #[test]
-    fn token_option_range_less_than_char_space() -> Result<()> {
-        let line = Line::new_plus("{c - a}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Invalid]]);
-        Ok(())
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    #[test]
-    fn token_option_range_less_than_digit_space() -> Result<()> {
-        let line = Line::new_plus("{3 - 1}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Invalid]]);
-        Ok(())
-    }
+    /// Write the parts of every chord in a normalized form, to compare them
+    fn parts(input: &str) -> Vec<Vec<String>> {
+        let key = |key: &Key| match &key.expr {
+            KeyExpr::Name(name) => name.clone(),
+            KeyExpr::Code(code) => format!("[{code}]"),
+            KeyExpr::Mouse(n) => format!("mouse{n}"),
+            _ => text(key),
+        };
 
-    #[test]
-    fn token_option_range_less_double_char() -> Result<()> {
-        let line = Line::new_plus("{a-dd}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Invalid]]);
-        Ok(())
+        parse(input)
+            .unwrap()
+            .chords
+            .iter()
+            .map(|chord| {
+                chord
+                    .parts
+                    .iter()
+                    .map(|part| {
+                        let atom = match &part.atom {
+                            Atom::Key(k) => key(k),
+                            Atom::Group(Group::Options(keys)) => {
+                                let keys = keys.iter().map(key).collect::<Vec<_>>();
+                                format!("{{{}}}", keys.join(","))
+                            },
+                            Atom::Group(Group::Range { start, end, .. }) =>
+                                format!("{{{}-{}}}", key(start), key(end)),
+                            Atom::Group(Group::Prefixes(prefixes)) => {
+                                let prefixes = prefixes
+                                    .iter()
+                                    .map(|keys| match keys.as_slice() {
+                                        [] => String::from("_"),
+                                        keys => keys.iter().map(|k| key(k) + "+").collect(),
+                                    })
+                                    .collect::<Vec<_>>();
+                                format!("{{{}}}", prefixes.join(","))
+                            },
+                        };
+                        format!("{}{}", if part.release { "~" } else { "" }, atom)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Return the error found while parsing `input`, along with the text it
+    /// points at
+    fn error(input: &str) -> (ErrorKind, &str) {
+        let e = parse(input).unwrap_err();
+        (e.kind, &input[e.span.start..e.span.end])
+    }
+
+    /// A keyboard with a few keys on it
+    fn charmaps() -> Vec<CharacterMap> {
+        [
+            ("a", 38, 0, 0x61),
+            ("A", 38, 0, 0x41),
+            ("b", 56, 0, 0x62),
+            ("c", 54, 0, 0x63),
+            ("slash", 61, 0, 0x2f),
+            ("Return", 36, 0, 0xff0d),
+            ("Super_L", 133, 64, 0xffeb),
+            ("Shift_L", 50, 1, 0xffe1),
+            ("Control_L", 37, 4, 0xffe3),
+            ("Control_R", 105, 4, 0xffe4),
+            ("Alt_L", 64, 8, 0xffe9),
+            ("Left", 113, 0, 0xff51),
+            ("Up", 111, 0, 0xff52),
+            ("F9", 75, 0, 0xffc6),
+            ("F10", 76, 0, 0xffc7),
+            ("F11", 95, 0, 0xffc8),
+            ("F12", 96, 0, 0xffc9),
+        ]
+        .into_iter()
+        .map(|(utf, code, mask, sym)| (utf, code, mask, sym, 0))
+        .chain([("Cyrillic_ef", 38, 0, 0x6c6, 1), ("Cyrillic_i", 56, 0, 0x6c9, 1)])
+        .map(|(utf, code, mask, sym, group)| {
+            let mut charmap = CharacterMap::new(utf.into(), code, mask, sym, 1, 0, group, true);
+            if code == 38 {
+                charmap.set_name(String::from("AC01"));
+            }
+            charmap
+        })
+        .collect()
     }
 
-    #[test]
-    fn token_mouse() -> Result<()> {
-        let line = Line::new_plus("mouse3", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Mouse(3)]]);
-        Ok(())
+    /// Return the keysym name and mask of every chord of every chain that
+    /// `input` expands to
+    fn chains(input: &str) -> Vec<Vec<(String, u16)>> {
+        chains_in(input, false)
     }
 
-    #[test]
-    fn token_release_mouse() -> Result<()> {
-        let line = Line::new_plus("~mouse3", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Release,
-            Token::Mouse(3)
-        ],]);
-        Ok(())
+    /// Return the chords of every chain like [`chains`], matching keysyms in
+    /// every layout group if `all_groups` is set
+    fn chains_in(input: &str, all_groups: bool) -> Vec<Vec<(String, u16)>> {
+        parse_chains(&charmaps(), input, all_groups)
+            .unwrap()
+            .iter()
+            .map(|chain| {
+                chain
+                    .chords()
+                    .iter()
+                    .map(|c| (c.charmap().utf().to_string(), c.modmask().mask()))
+                    .collect()
+            })
+            .collect()
     }
 
     #[test]
-    fn token_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("[0xffed]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Modifier(
-            String::from("Hyper_L")
-        ),],]);
-        Ok(())
+    fn parse_keys() {
+        assert_eq!(parts("a"), vec![vec!["a"]]);
+        assert_eq!(parts("abc"), vec![vec!["abc"]]);
+        assert_eq!(parts("super"), vec![vec!["super"]]);
+        assert_eq!(parts("lsuper"), vec![vec!["lsuper"]]);
+        assert_eq!(parts("Hyper_L"), vec![vec!["Hyper_L"]]);
+        assert_eq!(parts("mouse1"), vec![vec!["mouse1"]]);
+        assert_eq!(parts("~mouse2"), vec![vec!["~mouse2"]]);
+        assert_eq!(parts("~a"), vec![vec!["~a"]]);
     }
 
     #[test]
-    fn token_keysym_code() -> Result<()> {
-        let line = Line::new_plus("[65517]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![Token::Modifier(
-            String::from("Hyper_L")
-        ),],]);
-        Ok(())
+    fn parse_chords() {
+        for input in ["super+t", "super + t", " super +t "] {
+            assert_eq!(parts(input), vec![vec!["super", "t"]]);
+        }
+        for input in ["super+ctrl+a", "super + ctrl + a"] {
+            assert_eq!(parts(input), vec![vec!["super", "ctrl", "a"]]);
+        }
+        assert_eq!(parts("super+ctrl"), vec![vec!["super", "ctrl"]]);
+        assert_eq!(parts("~super + a"), vec![vec!["~super", "a"]]);
     }
 
     #[test]
-    fn token_modifier_plus_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super+[0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+    fn parse_chain_of_chords() {
+        assert_eq!(parts("super + a ; b"), vec![vec!["super", "a"], vec!["b"]]);
+        assert_eq!(parts("super+a;ctrl+b;c"), vec![
+            vec!["super", "a"],
+            vec!["ctrl", "b"],
+            vec!["c"]
         ]);
-        Ok(())
     }
 
     #[test]
-    fn token_modifier_plus_keysym_code_hex_space() -> Result<()> {
-        let line = Line::new_plus("super + [0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
+    fn parse_keysym_codes() {
+        for input in ["[0x61]", "[97]", "[ 0x61 ]"] {
+            assert_eq!(parts(input), vec![vec!["[97]"]]);
+        }
+        for input in ["super+~[97]", "super + ~[0x61]"] {
+            assert_eq!(parts(input), vec![vec!["super", "~[97]"]]);
+        }
+        for input in ["super+[0xffe3]+a", "super + [65507] + a"] {
+            assert_eq!(parts(input), vec![vec!["super", "[65507]", "a"]]);
+        }
+        for input in ["super+[0xffe3]+[0x61]", "super + [65507] + [97]"] {
+            assert_eq!(parts(input), vec![vec!["super", "[65507]", "[97]"]]);
+        }
+        assert_eq!(parts("~[65517]"), vec![vec!["~[65517]"]]);
     }
 
     #[test]
-    fn token_release_char() -> Result<()> {
-        let line = Line::new_plus("~a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Release,
-            Token::Char('a')
-        ],]);
-        Ok(())
+    fn parse_spacing() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["super+t", "super + t", "super +t", "super+ t", "  super + t  "], &[
+                "super", "t",
+            ]),
+            (&["super+ctrl", "super + ctrl", "super +ctrl"], &["super", "ctrl"]),
+            (&["super+ctrl+a", "super + ctrl + a", "super+ ctrl +a"], &["super", "ctrl", "a"]),
+            (&["super+~a", "super + ~a", "super +~a"], &["super", "~a"]),
+            (&["super+[0x61]", "super + [0x61]", "super+[ 0x61 ]"], &["super", "[97]"]),
+            (&["{a,b}", "{a, b}", "{ a ,b }"], &["{a,b}"]),
+            (&["{a,b,z}", "{a, b, z}", "{a ,b ,z}"], &["{a,b,z}"]),
+            (&["{a-b}", "{a - b}", "{ a-b }"], &["{a-b}"]),
+            (&["super+~{a,b}", "super + ~{a,b}", "super + ~{ a, b }"], &["super", "~{a,b}"]),
+        ];
+
+        for (inputs, expected) in cases {
+            for input in *inputs {
+                assert_eq!(parts(input), vec![expected.to_vec()], "{input}");
+            }
+        }
+        assert_eq!(parts("super+a;b"), parts("super + a ; b"));
+        assert_eq!(parts("super+a ;b"), parts("super + a; b"));
     }
 
     #[test]
-    fn token_release_modifier() -> Result<()> {
-        let line = Line::new_plus("super+~a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a')],
-        ]);
-        Ok(())
+    fn parse_mouse_buttons() {
+        for input in ["mouse3", " mouse3 "] {
+            assert_eq!(parts(input), vec![vec!["mouse3"]]);
+        }
+        assert_eq!(parts("~mouse3"), vec![vec!["~mouse3"]]);
+        for input in ["super+mouse1", "super + mouse1"] {
+            assert_eq!(parts(input), vec![vec!["super", "mouse1"]]);
+        }
+        for input in ["super+ctrl+~mouse2", "super + ctrl + ~mouse2"] {
+            assert_eq!(parts(input), vec![vec!["super", "ctrl", "~mouse2"]]);
+        }
+        assert_eq!(parts("a ; mouse9"), vec![vec!["a"], vec!["mouse9"]]);
+        assert_eq!(parts("super + {mouse1,mouse2}"), vec![vec!["super", "{mouse1,mouse2}"]]);
+        assert_eq!(parts("mouse255"), vec![vec!["mouse255"]]);
+        assert_eq!(error("mouse256"), (ErrorKind::InvalidButton("mouse256".into()), "mouse256"));
     }
 
     #[test]
-    fn token_release_modifier_space() -> Result<()> {
-        let line = Line::new_plus("super + ~a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a')],
-        ]);
-        Ok(())
-    }
+    fn parse_keysym_codes_in_every_position() {
+        // Alone
+        for input in ["[0xffed]", "[65517]"] {
+            assert_eq!(parts(input), vec![vec!["[65517]"]]);
+        }
+        // As a modifier
+        for input in ["[0xffe3]+a", "[65507] + a", "[ 0xffe3 ] + a"] {
+            assert_eq!(parts(input), vec![vec!["[65507]", "a"]]);
+        }
+        // As the key of a chord
+        for input in ["super+[0x61]", "super + [97]"] {
+            assert_eq!(parts(input), vec![vec!["super", "[97]"]]);
+        }
+        // Between modifiers
+        for input in ["super+[0xffe3]+ctrl+a", "super + [65507] + ctrl + a"] {
+            assert_eq!(parts(input), vec![vec!["super", "[65507]", "ctrl", "a"]]);
+        }
+        // As every key of a chord
+        for input in ["[0xffeb]+[0xffe3]+[0x61]", "[65515] + [65507] + [97]"] {
+            assert_eq!(parts(input), vec![vec!["[65515]", "[65507]", "[97]"]]);
+        }
+        // In every chord of a chain
+        for input in ["[0xffeb]+[0x61];[0x62]", "[65515] + [97] ; [98]"] {
+            assert_eq!(parts(input), vec![vec!["[65515]", "[97]"], vec!["[98]"]]);
+        }
+        // In groups
+        for input in ["{[0x61],[98]}", "{ [97], [0x62] }"] {
+            assert_eq!(parts(input), vec![vec!["{[97],[98]}"]]);
+        }
+        for input in ["{_,[0xffe3]+}a", "{_, [65507] + }a"] {
+            assert_eq!(parts(input), vec![vec!["{_,[65507]+}", "a"]]);
+        }
+        for input in ["{[0xff51]-[0xff52]}", "{ [65361] - [65362] }"] {
+            assert_eq!(parts(input), vec![vec!["{[65361]-[65362]}"]]);
+        }
 
-    #[test]
-    fn token_release_keysym_code() -> Result<()> {
-        let line = Line::new_plus("~[65517]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![vec![
-            Token::Release,
-            Token::Modifier(String::from("Hyper_L")),
-        ],]);
-        Ok(())
+        let s = |name: &str, mask| (name.to_string(), mask);
+        assert_eq!(chains("[0xffe3] + [0x61]"), chains("ctrl + a"));
+        assert_eq!(chains("[65515] + [65507] + [97]"), vec![vec![s("a", 68)]]);
+        assert_eq!(chains("{[0x61],[98]}"), vec![vec![s("a", 0)], vec![s("b", 0)]]);
+        assert_eq!(chains("[0xffeb] + a ; [98]"), vec![vec![s("a", 64), s("b", 0)]]);
+        assert_eq!(chains("{[0xff51]-[0xff52]}"), chains("{Left-Up}"));
+        assert_eq!(chains("{_,[0xffe3] + }a"), vec![vec![s("a", 0)], vec![s("a", 4)]]);
     }
 
     #[test]
-    fn token_release_modifier_plus_keysym_code() -> Result<()> {
-        let line = Line::new_plus("super+~[97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a'),],
-        ]);
-        Ok(())
+    fn parse_release_modifiers() {
+        for input in ["~super+a", "~super + a"] {
+            assert_eq!(parts(input), vec![vec!["~super", "a"]]);
+        }
+        for input in ["super+~ctrl+a", "super + ~ctrl + a"] {
+            assert_eq!(parts(input), vec![vec!["super", "~ctrl", "a"]]);
+        }
+        for input in ["~lsuper+~rctrl+~a", "~lsuper + ~rctrl + ~a"] {
+            assert_eq!(parts(input), vec![vec!["~lsuper", "~rctrl", "~a"]]);
+        }
+        for input in ["~[0xffe3]+a", "~[65507] + a"] {
+            assert_eq!(parts(input), vec![vec!["~[65507]", "a"]]);
+        }
+        for input in ["super+~[0xffe3]+[0x61]", "super + ~[65507] + [97]"] {
+            assert_eq!(parts(input), vec![vec!["super", "~[65507]", "[97]"]]);
+        }
+        for input in ["~Hyper_L+a", "~Hyper_L + a"] {
+            assert_eq!(parts(input), vec![vec!["~Hyper_L", "a"]]);
+        }
+        assert_eq!(parts("a ; ~super + b"), vec![vec!["a"], vec!["~super", "b"]]);
+        assert_eq!(parts("super + ~ctrl"), vec![vec!["super", "~ctrl"]]);
     }
 
     #[test]
-    fn token_release_modifier_plus_keysym_code_space() -> Result<()> {
-        let line = Line::new_plus("super + ~[97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a'),],
-        ]);
-        Ok(())
-    }
+    fn parse_invalid_ranges() {
+        let order = |start: &str, end: &str| ErrorKind::RangeOrder(start.into(), end.into());
 
-    #[test]
-    fn token_release_modifier_plus_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super+~[0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a'),],
-        ]);
-        Ok(())
+        for input in ["{c-a}", "{c - a}", "{ c-a }"] {
+            assert_eq!(error(input).0, order("c", "a"), "{input}");
+        }
+        for input in ["{3-1}", "{3 - 1}"] {
+            assert_eq!(error(input).0, order("3", "1"), "{input}");
+        }
+        assert_eq!(error("{a-dd}"), (ErrorKind::UnknownKeysym("dd".into()), "dd"));
+        for (input, end) in [("{8-11}", "11"), ("{1 - 12}", "12"), ("{10-12}", "12")] {
+            assert_eq!(error(input), (ErrorKind::RangeDigits(end.into()), end), "{input}");
+        }
     }
 
     #[test]
-    fn token_release_modifier_plus_keysym_code_hex_space() -> Result<()> {
-        let line = Line::new_plus("super + ~[0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::Char('a'),],
-        ]);
-        Ok(())
+    fn parse_ranges_outside_groups() {
+        for input in ["a-c", "a - c", "abc-cba", "abc - cba"] {
+            assert_eq!(error(input), (ErrorKind::Unexpected("`+` or `;`", "-".into()), "-"));
+        }
     }
 
     #[test]
-    fn token_two_modifiers() -> Result<()> {
-        let line = Line::new_plus("super+ctrl", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl"))],
+    fn parse_groups() {
+        for input in ["{a-c}", "{ a - c }"] {
+            assert_eq!(parts(input), vec![vec!["{a-c}"]]);
+        }
+        for input in ["{a,b,c}", "{a, b, c}"] {
+            assert_eq!(parts(input), vec![vec!["{a,b,c}"]]);
+        }
+        assert_eq!(parts("{0-9}"), vec![vec!["{0-9}"]]);
+        assert_eq!(parts("{F1-F12}"), vec![vec!["{F1-F12}"]]);
+        assert_eq!(parts("{Left,Right,Up,Down}"), vec![vec!["{Left,Right,Up,Down}"]]);
+        assert_eq!(parts("{a}"), vec![vec!["{a}"]]);
+        for input in ["super+~{a-b}", "super + ~{a-b}"] {
+            assert_eq!(parts(input), vec![vec!["super", "~{a-b}"]]);
+        }
+        for input in ["super+~{a,b,c}", "super + ~{a, b, c}"] {
+            assert_eq!(parts(input), vec![vec!["super", "~{a,b,c}"]]);
+        }
+        for input in ["super + {_,shift + }{1-9}", "super+{ _ , shift+ }{1-9}"] {
+            assert_eq!(parts(input), vec![vec!["super", "{_,shift+}", "{1-9}"]]);
+        }
+        assert_eq!(parts("{_,shift + ctrl + ,alt + }a ; b"), vec![
+            vec!["{_,shift+ctrl+,alt+}", "a"],
+            vec!["b"]
         ]);
-        Ok(())
     }
 
     #[test]
-    fn token_two_modifiers_space() -> Result<()> {
-        let line = Line::new_plus("super + ctrl", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl"))],
-        ]);
-        Ok(())
+    fn parse_errors() {
+        assert_eq!(error(""), (ErrorKind::UnexpectedEnd("a key"), ""));
+        assert_eq!(error("super +"), (ErrorKind::UnexpectedEnd("a key"), ""));
+        assert_eq!(error("super a"), (ErrorKind::Unexpected("`+` or `;`", "a".into()), "a"));
+        assert_eq!(error("super + ,"), (ErrorKind::Unexpected("a key", ",".into()), ","));
+        assert_eq!(error("super + {a,b"), (ErrorKind::Unclosed('{'), "{"));
+        assert_eq!(error("[0x61"), (ErrorKind::Unclosed('['), "["));
+        assert_eq!(error("[abc]"), (ErrorKind::InvalidCode("abc".into()), "abc"));
+        assert_eq!(error("mouse300"), (ErrorKind::InvalidButton("mouse300".into()), "mouse300"));
+        let order = |start: &str, end: &str| ErrorKind::RangeOrder(start.into(), end.into());
+        let family = |start: &str, end: &str| ErrorKind::RangeFamily(start.into(), end.into());
+        assert_eq!(error("{c-a}"), (order("c", "a"), "{c-a}"));
+        assert_eq!(error("{9-1}"), (order("9", "1"), "{9-1}"));
+        assert_eq!(error("{F12-F1}"), (order("F12", "F1"), "{F12-F1}"));
+        assert_eq!(error("{Down-Left}"), (order("Down", "Left"), "{Down-Left}"));
+        assert_eq!(error("{F1-KP_9}"), (family("F1", "KP_9"), "{F1-KP_9}"));
+        assert_eq!(error("{a-Left}"), (family("a", "Left"), "{a-Left}"));
+        assert_eq!(error("{ab-Left}"), (ErrorKind::UnknownKeysym("ab".into()), "ab"));
+        assert_eq!(error("{a-c,d}"), (ErrorKind::Unexpected("`}`", ",".into()), ","));
+        assert_eq!(error("{_,a}"), (ErrorKind::MixedGroup, "{_,a}"));
+        assert_eq!(error("{a,shift + }b"), (ErrorKind::MixedGroup, "{a,shift + }"));
+        assert_eq!(error("{_,shift + a}"), (ErrorKind::Unexpected("`+`", "}".into()), "}"));
+        assert_eq!(error("{_,shift + }"), (ErrorKind::UnexpectedEnd("a key"), ""));
+        assert_eq!(error("{_,shift + } + a"), (ErrorKind::Unexpected("a key", "+".into()), "+"));
     }
 
     #[test]
-    fn token_two_modifiers_plus_char() -> Result<()> {
-        let line = Line::new_plus("super+ctrl+a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
-    }
+    fn expand_groups() {
+        let expanded = parse("{a,b} + {1-2}")
+            .unwrap()
+            .expand()
+            .into_iter()
+            .map(|chain| {
+                chain[0]
+                    .iter()
+                    .map(|k| match &k.key.expr {
+                        KeyExpr::Name(name) => name.clone(),
+                        other => panic!("expected a name, found {other:?}"),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
 
-    #[test]
-    fn token_two_modifiers_plus_char_space() -> Result<()> {
-        let line = Line::new_plus("super + ctrl + a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(expanded, vec![
+            vec!["a", "1"],
+            vec!["a", "2"],
+            vec!["b", "1"],
+            vec!["b", "2"]
         ]);
-        Ok(())
     }
 
     #[test]
-    fn token_two_modifiers_plus_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super+ctrl+[0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
-    }
+    fn expand_ranges() {
+        let keys = |input: &str| match &parse(input).unwrap().chords[0].parts[0].atom {
+            Atom::Group(group @ Group::Range { .. }) =>
+                group.choices().iter().map(|keys| text(&keys[0])).collect::<Vec<_>>(),
+            other => panic!("expected a range, found {other:?}"),
+        };
 
-    #[test]
-    fn token_two_modifiers_plus_keysym_code_hex_space() -> Result<()> {
-        let line = Line::new_plus("super + ctrl + [0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
+        assert_eq!(keys("{a-e}"), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(keys("{F9-F12}"), vec!["F9", "F10", "F11", "F12"]);
+        assert_eq!(keys("{7-9}"), vec!["7", "8", "9"]);
+        assert_eq!(keys("{KP_1-KP_3}"), vec!["KP_1", "KP_2", "KP_3"]);
+        assert_eq!(keys("{mouse1-mouse3}"), vec!["mouse1", "mouse2", "mouse3"]);
+        assert_eq!(keys("{Left-Down}"), vec!["[0xff51]", "[0xff52]", "[0xff53]", "[0xff54]"]);
     }
 
     #[test]
-    fn token_two_modifiers_plus_keysym_code() -> Result<()> {
-        let line = Line::new_plus("super+ctrl+[97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
-    }
+    fn resolve_keys() {
+        let s = |name: &str, mask| (name.to_string(), mask);
 
-    #[test]
-    fn token_two_modifiers_plus_keysym_code_space() -> Result<()> {
-        let line = Line::new_plus("super + ctrl + [97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("ctrl")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(chains("super + {a-c}"), vec![
+            vec![s("a", 64)],
+            vec![s("b", 64)],
+            vec![s("c", 64)]
         ]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_two_modifiers_one_modifier_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super+[0xffe3]+a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(chains("ctrl + A"), vec![vec![s("a", 5)]]);
+        assert_eq!(chains("super + /"), vec![vec![s("slash", 64)]]);
+        assert_eq!(chains("super + enter ; [0x62]"), vec![vec![s("Return", 64), s("b", 0)]]);
+        assert_eq!(chains("[0xffeb] + a"), vec![vec![s("a", 64)]]);
+        assert_eq!(chains("super + Shift_L"), vec![vec![s("Shift_L", 64)]]);
+        assert_eq!(chains("mod1 + mod4 + a"), vec![vec![s("a", 72)]]);
+        assert_eq!(chains("ctrl + rctrl + a"), vec![vec![s("a", 4)]]);
+        assert_eq!(chains("a + b"), vec![vec![s("a", 0), s("b", 0)]]);
+        assert_eq!(chains("super + {F9-F12}"), vec![
+            vec![s("F9", 64)],
+            vec![s("F10", 64)],
+            vec![s("F11", 64)],
+            vec![s("F12", 64)]
         ]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_two_modifiers_one_modifier_keysym_code_hex_space() -> Result<()> {
-        let line = Line::new_plus("super + [0xffe3] + a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(chains("super + {Left-Up}"), vec![
+            vec![s("Left", 64)],
+            vec![s("Up", 64)]
         ]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_two_modifiers_modifier_keysym_code() -> Result<()> {
-        let line = Line::new_plus("super+[65507]+a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(chains("{Return,Left}"), vec![vec![s("Return", 0)], vec![s("Left", 0)]]);
+        assert_eq!(chains("super + {_,shift + ,shift + ctrl + }{a,b}"), vec![
+            vec![s("a", 64)],
+            vec![s("b", 64)],
+            vec![s("a", 65)],
+            vec![s("b", 65)],
+            vec![s("a", 69)],
+            vec![s("b", 69)],
         ]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_two_modifiers_modifier_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super + [65507] + a", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(chains("{_,ctrl + }a ; {_,shift + }b"), vec![
+            vec![s("a", 0), s("b", 0)],
+            vec![s("a", 0), s("b", 1)],
+            vec![s("a", 4), s("b", 0)],
+            vec![s("a", 4), s("b", 1)],
         ]);
-        Ok(())
     }
 
     #[test]
-    fn token_two_modifiers_two_keysym_code_hex() -> Result<()> {
-        let line = Line::new_plus("super+[0xffe3]+[0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
-        ]);
-        Ok(())
-    }
+    fn resolve_physical_keys() {
+        let s = |name: &str, mask| (name.to_string(), mask);
+        let both = vec![vec![s("a", 64)], vec![s("Cyrillic_ef", 64)]];
 
-    #[test]
-    fn token_two_modifiers_two_keysym_code_hex_space() -> Result<()> {
-        let line = Line::new_plus("super + [0xffe3] + [0x61]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(parts("super + [kc:38] ; <AC01>"), vec![vec!["super", "[kc:38]"], vec![
+            "<AC01>"
+        ]]);
+        assert_eq!(chains("super + [kc:38]"), both);
+        assert_eq!(chains("super + <AC01>"), both);
+        assert_eq!(chains("[kc:133] + b"), vec![vec![s("b", 64)]]);
+        assert_eq!(chains("super + a"), vec![vec![s("a", 64)]]);
+        assert_eq!(chains_in("super + a", true), both);
+        assert_eq!(chains_in("super + {b,Return}", true), vec![
+            vec![s("b", 64)],
+            vec![s("Cyrillic_i", 64)],
+            vec![s("Return", 64)]
         ]);
-        Ok(())
-    }
-
-    #[test]
-    fn token_two_modifiers_two_keysym_code() -> Result<()> {
-        let line = Line::new_plus("super+[65507]+[97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+        assert_eq!(
+            parse_chain(&charmaps(), "<AC01>").unwrap().chords()[0].charmap().utf(),
+            "a"
+        );
+
+        assert_eq!(error("[kc:300]"), (ErrorKind::InvalidKeycode("kc:300".into()), "kc:300"));
+        let errors = parse_chains(&charmaps(), "[kc:200] + <AB01>", false).unwrap_err();
+        assert_eq!(errors, vec![
+            Error::new(ErrorKind::UnknownKeycode(200), Span::new(0, 8)),
+            Error::new(ErrorKind::UnknownKeyName("AB01".into()), Span::new(11, 17)),
         ]);
-        Ok(())
     }
 
     #[test]
-    fn token_two_modifiers_two_keysym_code_space() -> Result<()> {
-        let line = Line::new_plus("super + [65507] + [97]", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Modifier(String::from("Control_L")),],
-            vec![Token::Plus],
-            vec![Token::Char('a')],
+    fn resolve_errors() {
+        let errors = parse_chains(&charmaps(), "hyper + {a,z} + foo", false).unwrap_err();
+        let found = errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>();
+
+        assert_eq!(found, vec![
+            ErrorKind::InvalidModifier("hyper".into()),
+            ErrorKind::UnknownKeysym("foo".into()),
+            ErrorKind::UnknownKeysym("z".into()),
         ]);
-        Ok(())
-    }
+        assert_eq!(errors[1].span, Span::new(16, 19));
 
-    #[test]
-    fn token_release_range() -> Result<()> {
-        let line = Line::new_plus("super+~{a-b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::RangeGroup(vec!['a', 'b'])]
+        let errors = parse_chains(&charmaps(), "rsuper + mod3 + a", false).unwrap_err();
+        let found = errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ErrorKind::InvalidModifier("rsuper".into()),
+            ErrorKind::InvalidModifier("mod3".into()),
         ]);
-        Ok(())
-    }
 
-    #[test]
-    fn token_release_range_space() -> Result<()> {
-        let line = Line::new_plus("super + ~{a-b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::RangeGroup(vec!['a', 'b'])]
-        ]);
-        Ok(())
+        let errors = parse_chain(&charmaps(), "super + {a,b}").unwrap_err();
+        assert_eq!(errors, vec![Error::new(ErrorKind::Group, Span::new(8, 13))]);
     }
 
     #[test]
-    fn token_release_option() -> Result<()> {
-        let line = Line::new_plus("super+~{a,b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::OptionGroup(vec!['a', 'b'])]
-        ]);
-        Ok(())
+    fn locate_keys() {
+        let contents = "\
+escape: esc
+bindings:
+  super + a: echo a
+  'super + {a,b}': echo b
+remaps:
+  a:
+    tap: b
+    hold: super
+";
+        assert_eq!(locate(contents, "global", "escape", "esc"), Some((1, 8)));
+        assert_eq!(locate(contents, "bindings", "super + a", "super + a"), Some((3, 2)));
+        assert_eq!(locate(contents, "bindings", "super + {a,b}", "super + {a,b}"), Some((4, 3)));
+        assert_eq!(locate(contents, "remaps", "a", "a"), Some((6, 2)));
+        assert_eq!(locate(contents, "remaps", "a", "super"), Some((8, 10)));
+        assert_eq!(locate(contents, "remaps", "b", "b"), None);
     }
 
     #[test]
-    fn token_release_option_space() -> Result<()> {
-        let line = Line::new_plus("super + ~{a,b}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::OptionGroup(vec!['a', 'b'])]
-        ]);
-        Ok(())
+    fn display_chains() {
+        let charmaps = charmaps();
+        let display = |input: &str| Chain::parse(&charmaps, input).unwrap().to_string();
+
+        assert_eq!(display("a"), "a");
+        assert_eq!(display("shift + Super_L + A ; control + c"), "super + shift + a ; ctrl + c");
+        assert_eq!(display("rctrl + a ; ctrl + rctrl + b"), "rctrl + a ; rctrl + b");
+        assert_eq!(display("lctrl + rctrl + Control_R + a"), "lctrl + rctrl + a");
+        assert_eq!(display("Control_R + [kc:105] + a"), "ctrl + a");
+        assert_eq!(display("rctrl + Control_L"), "Control_L");
+        assert_eq!(display("alt + ~[0x2f]"), "alt + ~slash");
+        assert_eq!(display("super + mouse1 ; Return"), "super + mouse1 ; Return");
+        assert_eq!(display("a + b"), "a ; b");
+        assert_eq!(display("super + shift"), "super + Shift_L");
     }
 
     #[test]
-    fn token_release_option_three() -> Result<()> {
-        let line = Line::new_plus("super+~{a,b,c}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::OptionGroup(vec!['a', 'b', 'c'])]
-        ]);
-        Ok(())
-    }
+    fn display_parses_to_same_chain() {
+        let charmaps = charmaps();
+        let modifiers = ["super", "alt", "ctrl", "shift", "rctrl", "lsuper"];
+        let keys = ["a", "A", "slash", "Return", "mouse1", "Super_L", "Shift_L"];
+
+        let chords = (0..1 << modifiers.len())
+            .flat_map(|set: usize| {
+                let held = modifiers
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| set & (1 << idx) != 0)
+                    .flat_map(|(_, m)| [*m, " + "])
+                    .collect::<String>();
+                keys.iter().flat_map(move |key| {
+                    [format!("{held}{key}"), format!("{held}~{key}")]
+                })
+            })
+            .collect::<Vec<_>>();
 
-    #[test]
-    fn token_release_option_three_space() -> Result<()> {
-        let line = Line::new_plus("super + ~{a, b, c}", 1);
-        let mut tokenized = line.tokenize();
-        tokenized.parse_tokens()?;
-        assert_eq!(tokenized.tokenized, vec![
-            vec![Token::Modifier(String::from("super"))],
-            vec![Token::Plus],
-            vec![Token::Release, Token::OptionGroup(vec!['a', 'b', 'c'])]
-        ]);
-        Ok(())
+        let inputs = chords
+            .iter()
+            .cloned()
+            .chain(chords.iter().map(|chord| format!("{chord} ; b")))
+            .chain(chords.iter().map(|chord| format!("ctrl + c ; {chord}")));
+
+        for input in inputs {
+            let chain = Chain::parse(&charmaps, &input).unwrap();
+            let text = chain.to_string();
+            assert_eq!(Chain::parse(&charmaps, &text), Ok(chain), "{input} => {text}");
+        }
     }
 }